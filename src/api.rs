@@ -0,0 +1,1143 @@
+//! JSON REST API under `/api/v1`.
+//!
+//! Clients log in with `POST /api/v1/sessions` and send the returned token as
+//! `Authorization: Bearer <token>`; browser sessions work as well. Errors are
+//! returned as `{"error": "..."}` with a matching status code.
+//!
+//! Captive portals redeem codes at `POST /api/redeem`, outside the versioned
+//! API, with the `[redemption]` API key from `config.toml` as their token.
+
+use crate::auth::{self, CurrentUser};
+use crate::export::ExportQuery;
+use crate::layout;
+use crate::portal::PortalLogin;
+use crate::redemption::{Device, RedeemError, Redemption, VoucherSession};
+use crate::stock;
+use crate::user::User;
+use crate::voucher::{Validity, Voucher, VoucherState, VoucherStateError, VoucherTransition};
+use crate::webhook::{self, WebhookEvent};
+use crate::wifi_network::{SecurityType, WiFiNetwork};
+use crate::AppState;
+use axum::{
+    extract::{rejection::JsonRejection, Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+/// The login endpoint, reachable without a session
+pub const LOGIN_PATH: &str = "/api/v1/sessions";
+
+/// Largest number of vouchers a single allocation may return
+pub const MAX_ALLOCATION: usize = 1000;
+
+/// Where captive portals redeem voucher codes
+pub const REDEEM_PATH: &str = "/api/redeem";
+
+/// Recorded as who redeemed a voucher when a portal used the API key
+const PORTAL_USER: &str = "portal";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", post(create_session).delete(delete_session))
+        .route("/networks", get(list_networks).post(create_network))
+        .route(
+            "/networks/:id",
+            get(get_network)
+                .patch(update_network)
+                .delete(delete_network),
+        )
+        .route(
+            "/networks/:id/vouchers",
+            get(list_vouchers).post(import_vouchers),
+        )
+        .route("/networks/:id/vouchers/allocate", post(allocate_vouchers))
+        .route("/vouchers/:id", get(get_voucher).patch(update_voucher))
+        .route("/vouchers/:id/transitions", get(get_voucher_transitions))
+        .route("/vouchers/:id/sessions", get(get_voucher_sessions))
+}
+
+/// An API error, rendered as `{"error": message}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(what: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, format!("{} not found", what))
+    }
+
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(ErrorBody {
+                error: &self.message,
+            }),
+        )
+            .into_response()
+    }
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        let message = match status {
+            StatusCode::FORBIDDEN => "You do not have access to this resource",
+            _ => status.canonical_reason().unwrap_or("Request failed"),
+        };
+        Self::new(status, message)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(_: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), rejection.body_text())
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct SessionResponse {
+    token: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+    user: User,
+}
+
+async fn create_session(
+    State(state): State<AppState>,
+    payload: Result<Json<LoginRequest>, JsonRejection>,
+) -> ApiResult<impl IntoResponse> {
+    let Json(login) = payload?;
+
+    let user = auth::authenticate(&state.database, &login.username, &login.password)
+        .await?
+        .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Invalid username or password"))?;
+
+    let _ = state.database.delete_expired_sessions().await;
+
+    let token = auth::generate_session_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(state.auth.session_ttl_hours);
+    state
+        .database
+        .create_session(&token, &user.id, expires_at)
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(SessionResponse {
+            token,
+            expires_at,
+            user,
+        }),
+    ))
+}
+
+async fn delete_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    if let Some(token) = auth::session_token(&headers) {
+        state.database.delete_session(&token).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct NewNetwork {
+    name: String,
+    ssid: String,
+    /// May be empty for open networks
+    #[serde(default)]
+    password: String,
+    description: Option<String>,
+    #[serde(default)]
+    security: SecurityType,
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    transition_disable: bool,
+}
+
+/// Fields to change on a network; missing fields keep their value
+#[derive(Deserialize)]
+struct NetworkUpdate {
+    name: Option<String>,
+    ssid: Option<String>,
+    password: Option<String>,
+    /// An empty string removes the description
+    description: Option<String>,
+    is_active: Option<bool>,
+    /// Id of a print layout preset
+    layout: Option<String>,
+    security: Option<SecurityType>,
+    hidden: Option<bool>,
+    transition_disable: Option<bool>,
+    /// Captive portal login QR codes
+    portal: Option<PortalLogin>,
+    /// Alert at this many unused vouchers or fewer; `null` turns alerts off
+    #[serde(default, deserialize_with = "nullable")]
+    low_stock_threshold: Option<Option<u32>>,
+}
+
+/// Tell an explicit `null` (`Some(None)`) apart from a missing field (`None`)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn check_network(network: &WiFiNetwork) -> ApiResult<()> {
+    if network.name.trim().is_empty() {
+        return Err(ApiError::invalid("name must not be empty"));
+    }
+    if network.ssid.trim().is_empty() {
+        return Err(ApiError::invalid("ssid must not be empty"));
+    }
+    if layout::preset(&network.layout).is_none() {
+        return Err(ApiError::invalid(format!(
+            "Unknown layout '{}'",
+            network.layout
+        )));
+    }
+    network
+        .validate_security()
+        .map_err(|error| ApiError::invalid(error.to_string()))?;
+    network
+        .portal
+        .validate()
+        .map_err(|error| ApiError::invalid(error.to_string()))?;
+    Ok(())
+}
+
+async fn list_networks(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+) -> ApiResult<Json<Vec<WiFiNetwork>>> {
+    let mut networks = state.database.get_all_networks().await?;
+
+    if !user.is_admin() {
+        let assigned = state.database.get_user_network_ids(&user.id).await?;
+        networks.retain(|network| assigned.contains(&network.id));
+    }
+
+    Ok(Json(networks))
+}
+
+async fn create_network(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    payload: Result<Json<NewNetwork>, JsonRejection>,
+) -> ApiResult<impl IntoResponse> {
+    auth::require_admin(&user)?;
+    let Json(input) = payload?;
+
+    let mut network = WiFiNetwork::new(
+        input.name.trim().to_string(),
+        input.ssid,
+        input.password,
+        input
+            .description
+            .filter(|description| !description.is_empty()),
+    );
+    network.security = input.security;
+    network.hidden = input.hidden;
+    network.transition_disable = input.transition_disable;
+    check_network(&network)?;
+    state.database.create_network(&network).await?;
+    state
+        .webhooks
+        .emit(
+            WebhookEvent::NetworkCreated,
+            webhook::network_data(&network, &user.username),
+        )
+        .await;
+
+    Ok((StatusCode::CREATED, Json(network)))
+}
+
+async fn get_network(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+) -> ApiResult<Json<WiFiNetwork>> {
+    auth::require_network_access(&state.database, &user, &network_id).await?;
+
+    let network = state
+        .database
+        .get_network(&network_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Network"))?;
+
+    Ok(Json(network))
+}
+
+async fn update_network(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    payload: Result<Json<NetworkUpdate>, JsonRejection>,
+) -> ApiResult<Json<WiFiNetwork>> {
+    auth::require_admin(&user)?;
+    let Json(update) = payload?;
+
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Network"))?;
+
+    if let Some(name) = update.name {
+        network.name = name.trim().to_string();
+    }
+    if let Some(ssid) = update.ssid {
+        network.ssid = ssid;
+    }
+    if let Some(password) = update.password {
+        network.password = password;
+    }
+    if let Some(description) = update.description {
+        network.description = Some(description).filter(|description| !description.is_empty());
+    }
+    if let Some(is_active) = update.is_active {
+        network.is_active = is_active;
+    }
+    if let Some(layout) = update.layout {
+        network.layout = layout;
+    }
+    if let Some(security) = update.security {
+        network.security = security;
+    }
+    if let Some(hidden) = update.hidden {
+        network.hidden = hidden;
+    }
+    if let Some(transition_disable) = update.transition_disable {
+        network.transition_disable = transition_disable;
+    }
+    if let Some(portal) = update.portal {
+        network.portal = portal;
+    }
+    if let Some(low_stock_threshold) = update.low_stock_threshold {
+        network.low_stock_threshold = low_stock_threshold;
+    }
+    check_network(&network)?;
+    state.database.update_network(&network).await?;
+    stock::check(&state, &network.id).await;
+
+    Ok(Json(network))
+}
+
+async fn delete_network(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+) -> ApiResult<StatusCode> {
+    auth::require_admin(&user)?;
+
+    let network = state
+        .database
+        .get_network(&network_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Network"))?;
+    if !state.database.delete_network(&network_id).await? {
+        return Err(ApiError::not_found("Network"));
+    }
+    state
+        .webhooks
+        .emit(
+            WebhookEvent::NetworkDeleted,
+            webhook::network_data(&network, &user.username),
+        )
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Return 404 unless the network exists
+async fn ensure_network(state: &AppState, network_id: &str) -> ApiResult<()> {
+    state
+        .database
+        .get_network(network_id)
+        .await?
+        .map(|_| ())
+        .ok_or_else(|| ApiError::not_found("Network"))
+}
+
+/// Lists a network's vouchers; accepts the `status`, `from`, `to` and `date_field`
+/// filters of the CSV export
+async fn list_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> ApiResult<Json<Vec<Voucher>>> {
+    auth::require_admin(&user)?;
+    let (_, filter) = query
+        .parse()
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    ensure_network(&state, &network_id).await?;
+
+    let mut vouchers = state.database.get_vouchers_for_network(&network_id).await?;
+    vouchers.retain(|voucher| filter.matches(voucher));
+
+    Ok(Json(vouchers))
+}
+
+#[derive(Deserialize)]
+struct ImportRequest {
+    codes: Vec<String>,
+    /// Validity applied to every imported code
+    #[serde(flatten)]
+    validity: Validity,
+}
+
+async fn import_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    payload: Result<Json<ImportRequest>, JsonRejection>,
+) -> ApiResult<impl IntoResponse> {
+    auth::require_admin(&user)?;
+    let Json(import) = payload?;
+    ensure_network(&state, &network_id).await?;
+
+    let mut codes: Vec<String> = Vec::new();
+    for code in import.codes.iter().map(|code| code.trim()) {
+        if !code.is_empty() && !codes.iter().any(|existing| existing == code) {
+            codes.push(code.to_string());
+        }
+    }
+    if codes.is_empty() {
+        return Err(ApiError::invalid("codes must contain at least one code"));
+    }
+    import
+        .validity
+        .validate()
+        .map_err(|e| ApiError::invalid(e.to_string()))?;
+
+    let vouchers: Vec<Voucher> = codes
+        .into_iter()
+        .map(|code| {
+            let mut voucher = Voucher::new(code);
+            voucher.network_id = Some(network_id.clone());
+            voucher.validity = import.validity.clone();
+            voucher
+        })
+        .collect();
+
+    if let Err(e) = state.database.create_vouchers(&vouchers).await {
+        let duplicate = e
+            .downcast_ref::<sqlx::Error>()
+            .and_then(|e| e.as_database_error())
+            .is_some_and(|e| e.is_unique_violation());
+        return Err(if duplicate {
+            ApiError::new(
+                StatusCode::CONFLICT,
+                "One or more codes already exist; nothing was imported",
+            )
+        } else {
+            e.into()
+        });
+    }
+    state
+        .webhooks
+        .vouchers_imported(Some(&network_id), vouchers.len(), "api")
+        .await;
+    stock::check(&state, &network_id).await;
+
+    Ok((StatusCode::CREATED, Json(vouchers)))
+}
+
+#[derive(Deserialize)]
+struct AllocateRequest {
+    count: usize,
+}
+
+/// Hand out unused vouchers, marking them used, as printing does
+async fn allocate_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    payload: Result<Json<AllocateRequest>, JsonRejection>,
+) -> ApiResult<Json<Vec<Voucher>>> {
+    auth::require_network_access(&state.database, &user, &network_id).await?;
+    let Json(request) = payload?;
+    if !(1..=MAX_ALLOCATION).contains(&request.count) {
+        return Err(ApiError::invalid(format!(
+            "count must be between 1 and {}",
+            MAX_ALLOCATION
+        )));
+    }
+    ensure_network(&state, &network_id).await?;
+
+    let vouchers = state
+        .database
+        .allocate_vouchers(&network_id, request.count, &user.username)
+        .await?;
+    for voucher in &vouchers {
+        state
+            .webhooks
+            .voucher_changed(false, voucher, &user.username)
+            .await;
+    }
+    stock::check(&state, &network_id).await;
+    if vouchers.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "The network has no unused vouchers left",
+        ));
+    }
+
+    Ok(Json(vouchers))
+}
+
+/// Either a new lifecycle state, or `is_used` as a shorthand for issued and available
+#[derive(Deserialize)]
+struct VoucherUpdate {
+    is_used: Option<bool>,
+    state: Option<VoucherState>,
+}
+
+async fn get_voucher(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(voucher_id): Path<String>,
+) -> ApiResult<Json<Voucher>> {
+    auth::require_admin(&user)?;
+
+    let voucher = state
+        .database
+        .get_voucher(&voucher_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Voucher"))?;
+
+    Ok(Json(voucher))
+}
+
+async fn update_voucher(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(voucher_id): Path<String>,
+    payload: Result<Json<VoucherUpdate>, JsonRejection>,
+) -> ApiResult<Json<Voucher>> {
+    auth::require_admin(&user)?;
+    let Json(update) = payload?;
+
+    let new_state = match (update.state, update.is_used) {
+        (Some(new_state), None) => new_state,
+        (None, Some(true)) => VoucherState::Issued,
+        (None, Some(false)) => VoucherState::Available,
+        _ => return Err(ApiError::invalid("send either state or is_used")),
+    };
+
+    let was_used = state
+        .database
+        .get_voucher(&voucher_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Voucher"))?
+        .state
+        .is_used();
+    let voucher = state
+        .database
+        .set_voucher_state(&voucher_id, new_state, &user.username)
+        .await
+        .map_err(|e| match e.downcast_ref::<VoucherStateError>() {
+            Some(error) => ApiError::new(StatusCode::CONFLICT, error.to_string()),
+            None => e.into(),
+        })?
+        .ok_or_else(|| ApiError::not_found("Voucher"))?;
+    state
+        .webhooks
+        .voucher_changed(was_used, &voucher, &user.username)
+        .await;
+    if let Some(network_id) = &voucher.network_id {
+        stock::check(&state, network_id).await;
+    }
+
+    Ok(Json(voucher))
+}
+
+async fn get_voucher_transitions(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(voucher_id): Path<String>,
+) -> ApiResult<Json<Vec<VoucherTransition>>> {
+    auth::require_admin(&user)?;
+
+    state
+        .database
+        .get_voucher(&voucher_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Voucher"))?;
+
+    Ok(Json(
+        state.database.get_voucher_transitions(&voucher_id).await?,
+    ))
+}
+
+async fn get_voucher_sessions(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(voucher_id): Path<String>,
+) -> ApiResult<Json<Vec<VoucherSession>>> {
+    auth::require_admin(&user)?;
+
+    state
+        .database
+        .get_voucher(&voucher_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Voucher"))?;
+
+    Ok(Json(
+        state.database.get_voucher_sessions(&voucher_id).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RedeemRequest {
+    code: String,
+    network_id: String,
+    client_mac: Option<String>,
+    client_ip: Option<String>,
+}
+
+/// Redeem a voucher code for a guest device and return the access it has left.
+/// Portals authenticate with the API key; logged-in users need access to the network.
+pub async fn redeem_voucher(
+    State(state): State<AppState>,
+    user: Option<Extension<CurrentUser>>,
+    payload: Result<Json<RedeemRequest>, JsonRejection>,
+) -> ApiResult<Json<Redemption>> {
+    let Json(request) = payload?;
+    // Without a session, the auth middleware has already checked the API key
+    let redeemed_by = match &user {
+        Some(Extension(CurrentUser(user))) => {
+            auth::require_network_access(&state.database, user, &request.network_id).await?;
+            user.username.as_str()
+        }
+        None => PORTAL_USER,
+    };
+    let code = request.code.trim();
+    if code.is_empty() {
+        return Err(ApiError::invalid("code must not be empty"));
+    }
+    let device = Device::parse(request.client_mac.as_deref(), request.client_ip.as_deref())
+        .map_err(|e| ApiError::invalid(e.to_string()))?;
+    ensure_network(&state, &request.network_id).await?;
+
+    let redemption = state
+        .database
+        .redeem_voucher(&request.network_id, code, &device, redeemed_by)
+        .await
+        .map_err(|e| match e.downcast_ref::<RedeemError>() {
+            Some(error) => ApiError::new(redeem_status(error), error.to_string()),
+            None => e.into(),
+        })?;
+
+    Ok(Json(redemption))
+}
+
+fn redeem_status(error: &RedeemError) -> StatusCode {
+    match error {
+        RedeemError::UnknownCode => StatusCode::NOT_FOUND,
+        RedeemError::InvalidMac(_) | RedeemError::InvalidIp(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        RedeemError::NotIssued | RedeemError::AlreadyRedeemed => StatusCode::CONFLICT,
+        RedeemError::UsedUp | RedeemError::Expired | RedeemError::Revoked => StatusCode::GONE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AuthConfig, PrintingConfig, ReceiptPrinterConfig, RedemptionConfig};
+    use crate::qr_generator::QrGenerator;
+    use crate::stock::StockAlerts;
+    use crate::test_util;
+    use crate::user::Role;
+    use crate::webhook::Webhooks;
+    use axum::{body::Body, http::Request, middleware};
+    use std::sync::Arc;
+    use tower::Service;
+
+    const PORTAL_KEY: &str = "portal-key-0123456789";
+
+    async fn app() -> (Router, String) {
+        let database = Arc::new(test_util::database().await);
+        let admin = User::new(
+            "admin".to_string(),
+            auth::hash_password("secret").unwrap(),
+            Role::Admin,
+        );
+        database.create_user(&admin).await.unwrap();
+
+        let state = AppState {
+            database: database.clone(),
+            qr_generator: QrGenerator::default(),
+            auth: Arc::new(AuthConfig::default()),
+            printing: Arc::new(PrintingConfig::default()),
+            receipt_printer: Arc::new(ReceiptPrinterConfig::default()),
+            redemption: Arc::new(RedemptionConfig {
+                api_key: Some(PORTAL_KEY.to_string()),
+            }),
+            stock_alerts: Arc::new(StockAlerts::default()),
+            webhooks: Webhooks::new(database),
+        };
+        let app = Router::new()
+            .route(REDEEM_PATH, post(redeem_voucher))
+            .nest("/api/v1", router())
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth::require_auth,
+            ))
+            .with_state(state);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            LOGIN_PATH,
+            None,
+            Some(r#"{"username": "admin", "password": "secret"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = body["token"].as_str().unwrap().to_string();
+        (app, token)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        if body.is_some() {
+            request = request.header("content-type", "application/json");
+        }
+        let request = request
+            .body(Body::from(body.unwrap_or_default().to_string()))
+            .unwrap();
+
+        let response = app.clone().call(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        (status, json)
+    }
+
+    #[tokio::test]
+    async fn test_requires_authentication() {
+        let (app, _) = app().await;
+        let (status, body) = send(&app, "GET", "/api/v1/networks", None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Authentication required");
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            LOGIN_PATH,
+            None,
+            Some(r#"{"username": "admin", "password": "wrong"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_network_and_voucher_lifecycle() {
+        let (app, token) = app().await;
+        let token = Some(token.as_str());
+
+        let (status, network) = send(
+            &app,
+            "POST",
+            "/api/v1/networks",
+            token,
+            Some(r#"{"name": "Lobby", "ssid": "Lobby-Guest", "password": "pw"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = network["id"].as_str().unwrap();
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"ssid": "Lobby-2"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(network["ssid"], "Lobby-2");
+        assert_eq!(network["name"], "Lobby");
+        assert_eq!(network["layout"], "a4");
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"layout": "herma-4360"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(network["layout"], "herma-4360");
+
+        let (status, _) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"layout": "napkin"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"security": "wpa3-sae", "hidden": true, "transition_disable": true}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(network["security"], "wpa3-sae");
+        assert_eq!(network["hidden"], true);
+
+        let (status, _) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"security": "wpa2"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(
+                r#"{"portal": {"mode": "both", "url_template": "https://portal.example/?v={code}"}}"#,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(network["portal"]["mode"], "both");
+
+        let (status, _) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"portal": {"mode": "login-only"}}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"low_stock_threshold": 5}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(network["low_stock_threshold"], 5);
+
+        let (_, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"hidden": false}"#),
+        )
+        .await;
+        assert_eq!(network["low_stock_threshold"], 5);
+
+        let (status, network) = send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/networks/{}", id),
+            token,
+            Some(r#"{"low_stock_threshold": null}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(network["low_stock_threshold"].is_null());
+
+        let vouchers_uri = format!("/api/v1/networks/{}/vouchers", id);
+        let (status, vouchers) = send(
+            &app,
+            "POST",
+            &vouchers_uri,
+            token,
+            Some(r#"{"codes": ["A1", " B2 ", "A1", ""]}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(vouchers.as_array().unwrap().len(), 2);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            &vouchers_uri,
+            token,
+            Some(r#"{"codes": ["A1"]}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["error"].is_string());
+
+        let (status, allocated) = send(
+            &app,
+            "POST",
+            &format!("{}/allocate", vouchers_uri),
+            token,
+            Some(r#"{"count": 5}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allocated.as_array().unwrap().len(), 2);
+        assert_eq!(allocated[0]["is_used"], true);
+
+        let voucher_uri = format!("/api/v1/vouchers/{}", allocated[0]["id"].as_str().unwrap());
+        let (status, voucher) = send(
+            &app,
+            "PATCH",
+            &voucher_uri,
+            token,
+            Some(r#"{"is_used": false}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(voucher["is_used"], false);
+
+        let (_, unused) = send(
+            &app,
+            "GET",
+            &format!("{}?status=unused", vouchers_uri),
+            token,
+            None,
+        )
+        .await;
+        assert_eq!(unused.as_array().unwrap().len(), 1);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            &vouchers_uri,
+            token,
+            Some(r#"{"codes": "A1"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].is_string());
+
+        let uri = format!("/api/v1/networks/{}", id);
+        let (status, _) = send(&app, "DELETE", &uri, token, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send(&app, "GET", &uri, token, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Network not found");
+    }
+
+    #[tokio::test]
+    async fn test_redeem() {
+        let (app, token) = app().await;
+        let (_, network) = send(
+            &app,
+            "POST",
+            "/api/v1/networks",
+            Some(&token),
+            Some(r#"{"name": "Lobby", "ssid": "Lobby-Guest", "password": "pw"}"#),
+        )
+        .await;
+        let id = network["id"].as_str().unwrap();
+        let (_, vouchers) = send(
+            &app,
+            "POST",
+            &format!("/api/v1/networks/{}/vouchers", id),
+            Some(&token),
+            Some(r#"{"codes": ["A1", "B2", "C3"], "duration_minutes": 60}"#),
+        )
+        .await;
+        let redeem = |code: &str, mac: &str| {
+            format!(
+                r#"{{"code": "{}", "network_id": "{}", "client_mac": "{}", "client_ip": "10.0.0.7"}}"#,
+                code, id, mac
+            )
+        };
+        let portal = Some(PORTAL_KEY);
+
+        // Codes still in the pool are refused until they are handed out
+        let (status, body) = send(&app, "POST", REDEEM_PATH, portal, Some(&redeem("A1", ""))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"], "the voucher has not been handed out yet");
+        for code in ["A1", "C3"] {
+            send(
+                &app,
+                "PATCH",
+                &format!("/api/v1/vouchers/{}", voucher_id(&vouchers, code)),
+                Some(&token),
+                Some(r#"{"state": "issued"}"#),
+            )
+            .await;
+        }
+
+        let (status, _) = send(&app, "POST", REDEEM_PATH, None, Some(&redeem("A1", ""))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            Some("portal-key-wrong"),
+            Some(&redeem("A1", "")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // The key is only good for redeeming
+        let (status, _) = send(&app, "GET", "/api/v1/networks", portal, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            portal,
+            Some(&redeem("A1", "AA-BB-CC-DD-EE-FF")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["first_login"], true);
+        assert_eq!(body["duration_minutes"], 60);
+        assert!((3590..=3600).contains(&body["remaining_seconds"].as_i64().unwrap()));
+
+        // The same device may log in again, no other device can
+        let (status, body) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            portal,
+            Some(&redeem("A1", "aa:bb:cc:dd:ee:ff")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["first_login"], false);
+        let (status, body) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            portal,
+            Some(&redeem("A1", "11:22:33:44:55:66")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["error"],
+            "the voucher was already used on another device"
+        );
+
+        let (status, voucher) = send(
+            &app,
+            "GET",
+            &format!("/api/v1/vouchers/{}", voucher_id(&vouchers, "A1")),
+            Some(&token),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(voucher["state"], "redeemed");
+        assert_eq!(voucher["client_mac"], "aa:bb:cc:dd:ee:ff");
+        assert_eq!(voucher["client_ip"], "10.0.0.7");
+
+        send(
+            &app,
+            "PATCH",
+            &format!("/api/v1/vouchers/{}", voucher_id(&vouchers, "B2")),
+            Some(&token),
+            Some(r#"{"state": "revoked"}"#),
+        )
+        .await;
+        let (status, _) = send(&app, "POST", REDEEM_PATH, portal, Some(&redeem("B2", ""))).await;
+        assert_eq!(status, StatusCode::GONE);
+        let (status, _) = send(&app, "POST", REDEEM_PATH, portal, Some(&redeem("Z9", ""))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            portal,
+            Some(&redeem("C3", "not-a-mac")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Logged-in users can redeem as well
+        let (status, body) = send(
+            &app,
+            "POST",
+            REDEEM_PATH,
+            Some(&token),
+            Some(&redeem("C3", "")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["code"], "C3");
+    }
+
+    fn voucher_id<'a>(vouchers: &'a serde_json::Value, code: &str) -> &'a str {
+        vouchers
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|voucher| voucher["code"] == code)
+            .and_then(|voucher| voucher["id"].as_str())
+            .unwrap()
+    }
+}
@@ -0,0 +1,378 @@
+use crate::api::{self, ApiError};
+use crate::config::AuthConfig;
+use crate::database::Database;
+use crate::user::User;
+use crate::AppState;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+
+/// Name of the cookie holding the login session token
+pub const SESSION_COOKIE: &str = "voucher_session";
+
+/// The logged-in user, inserted into request extensions by `require_auth`
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub User);
+
+/// Hash a password with Argon2id and a random salt
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::PasswordHash(e.to_string()))
+}
+
+/// Check a password against a stored Argon2 hash
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A hash to check passwords of unknown users against, made with the same
+/// parameters as real ones so the check takes as long
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$qV0jgZ7cgxo2XsVDkZsi3w$fHpgN0qUfdCjuioobV2/K9n9mn05/XDqMdFiud8aRqo";
+
+/// Find the user with this name and password. Unknown names still cost a
+/// password check, so response times don't reveal which usernames exist.
+pub async fn authenticate(
+    database: &Database,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<Option<User>> {
+    match database.get_user_by_username(username.trim()).await? {
+        Some(user) => Ok(verify_password(password, &user.password_hash).then_some(user)),
+        None => {
+            verify_password(password, DUMMY_HASH);
+            Ok(None)
+        }
+    }
+}
+
+/// Generate a random, URL-safe token of `bytes` random bytes
+pub fn generate_token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    general_purpose::URL_SAFE_NO_PAD.encode(buffer)
+}
+
+/// Generate a new session token
+pub fn generate_session_token() -> String {
+    generate_token(32)
+}
+
+/// Build the Set-Cookie value that stores a session token in the browser
+pub fn session_cookie(token: &str, config: &AuthConfig) -> String {
+    build_session_cookie(token, config.session_ttl_hours * 3600, config.cookie_secure)
+}
+
+/// Build the Set-Cookie value that removes the session cookie
+pub fn clear_session_cookie(config: &AuthConfig) -> String {
+    build_session_cookie("", 0, config.cookie_secure)
+}
+
+fn build_session_cookie(value: &str, max_age: i64, secure: bool) -> String {
+    let mut cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE, value, max_age
+    );
+    if secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// Read the session token from an `Authorization: Bearer` header or the Cookie headers
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty());
+    if bearer.is_some() {
+        return bearer;
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Whether a path is reachable without logging in
+pub fn is_public_route(path: &str, public_routes: &[String]) -> bool {
+    public_routes.iter().any(|route| {
+        let route = route.trim_end_matches('/');
+        if route.is_empty() {
+            return path == "/";
+        }
+        path == route
+            || path
+                .strip_prefix(route)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+/// Only allow redirects back to local paths after login
+pub fn safe_redirect_target(next: Option<&str>) -> String {
+    match next {
+        Some(path)
+            if path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\") =>
+        {
+            path.to_string()
+        }
+        _ => "/admin".to_string(),
+    }
+}
+
+/// Middleware that requires a valid session for every non-public route
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let api_login = path == api::LOGIN_PATH && request.method() == Method::POST;
+    // Captive portals redeem vouchers with the configured API key instead of a session
+    let portal = path == api::REDEEM_PATH
+        && session_token(request.headers()).is_some_and(|key| state.redemption.accepts(&key));
+    if api_login || portal || is_public_route(&path, &state.auth.public_routes) {
+        return next.run(request).await;
+    }
+
+    let user = match session_token(request.headers()) {
+        Some(token) => state
+            .database
+            .get_session_user(&token)
+            .await
+            .unwrap_or(None),
+        None => None,
+    };
+
+    match user {
+        Some(user) => {
+            request.extensions_mut().insert(CurrentUser(user));
+            next.run(request).await
+        }
+        None if path.starts_with("/api/") => {
+            ApiError::new(StatusCode::UNAUTHORIZED, "Authentication required").into_response()
+        }
+        None if request.method() == Method::GET => {
+            let target = request
+                .uri()
+                .path_and_query()
+                .map(|pq| pq.as_str().to_string())
+                .unwrap_or(path);
+            let query = serde_urlencoded::to_string([("next", target)]).unwrap_or_default();
+            Redirect::to(&format!("/login?{}", query)).into_response()
+        }
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Reject the request unless the user is an administrator
+pub fn require_admin(user: &User) -> Result<(), StatusCode> {
+    if user.is_admin() {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Reject the request unless the user may print vouchers for the network.
+/// Administrators can print for every network, operators only for assigned ones.
+pub async fn require_network_access(
+    database: &Database,
+    user: &User,
+    network_id: &str,
+) -> Result<(), StatusCode> {
+    if user.is_admin() {
+        return Ok(());
+    }
+
+    match database.user_has_network(&user.id, network_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(StatusCode::FORBIDDEN),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("Password hashing failed: {0}")]
+    PasswordHash(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_hash_and_verify_password() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not-a-hash"));
+    }
+
+    #[test]
+    fn test_session_tokens_are_unique() {
+        let a = generate_session_token();
+        let b = generate_session_token();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 43);
+    }
+
+    #[test]
+    fn test_session_token_from_cookie_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; voucher_session=abc123; other=1"),
+        );
+        assert_eq!(session_token(&headers), Some("abc123".to_string()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer xyz789"),
+        );
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("voucher_session=abc123"),
+        );
+        assert_eq!(session_token(&headers), Some("xyz789".to_string()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("voucher_session="));
+        assert_eq!(session_token(&headers), None);
+        assert_eq!(session_token(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_session_cookie_attributes() {
+        let mut config = AuthConfig::default();
+        let cookie = session_cookie("abc", &config);
+        assert!(cookie.starts_with("voucher_session=abc;"));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("Max-Age=43200"));
+        assert!(!cookie.contains("Secure"));
+
+        config.cookie_secure = true;
+        assert!(session_cookie("abc", &config).ends_with("; Secure"));
+        assert!(clear_session_cookie(&config).contains("Max-Age=0"));
+    }
+
+    #[test]
+    fn test_public_routes() {
+        let routes = vec!["/login".to_string(), "/static/".to_string()];
+        assert!(is_public_route("/login", &routes));
+        assert!(is_public_route("/static/logo.png", &routes));
+        assert!(!is_public_route("/loginx", &routes));
+        assert!(!is_public_route("/admin", &routes));
+        assert!(!is_public_route("/", &routes));
+
+        let routes = vec!["/".to_string()];
+        assert!(is_public_route("/", &routes));
+        assert!(!is_public_route("/admin", &routes));
+    }
+
+    #[test]
+    fn test_require_admin() {
+        use crate::user::Role;
+
+        let admin = User::new("admin".to_string(), String::new(), Role::Admin);
+        let operator = User::new("desk".to_string(), String::new(), Role::Operator);
+        assert!(require_admin(&admin).is_ok());
+        assert_eq!(require_admin(&operator), Err(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn test_require_network_access() {
+        use crate::user::Role;
+
+        let (database, network) = test_util::database_with_lobby().await;
+
+        let operator = User::new("desk".to_string(), String::new(), Role::Operator);
+        database.create_user(&operator).await.unwrap();
+        assert_eq!(
+            require_network_access(&database, &operator, &network.id).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+
+        database
+            .set_user_networks(&operator.id, std::slice::from_ref(&network.id))
+            .await
+            .unwrap();
+        assert!(require_network_access(&database, &operator, &network.id)
+            .await
+            .is_ok());
+
+        let admin = User::new("admin".to_string(), String::new(), Role::Admin);
+        assert!(require_network_access(&database, &admin, "any-network")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        use crate::user::Role;
+
+        let database = test_util::database().await;
+        let hash = hash_password("hunter2").unwrap();
+        let desk = User::new("desk".to_string(), hash.clone(), Role::Operator);
+        database.create_user(&desk).await.unwrap();
+
+        let found = authenticate(&database, " desk ", "hunter2").await.unwrap();
+        assert_eq!(found.map(|user| user.id), Some(desk.id));
+        assert!(authenticate(&database, "desk", "wrong")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(authenticate(&database, "nobody", "hunter2")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Unknown names are checked against a hash as costly as a real one
+        let dummy = PasswordHash::new(DUMMY_HASH).unwrap();
+        let real = PasswordHash::new(&hash).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(dummy.params, real.params);
+    }
+
+    #[test]
+    fn test_safe_redirect_target() {
+        assert_eq!(
+            safe_redirect_target(Some("/generate?network_id=1")),
+            "/generate?network_id=1"
+        );
+        assert_eq!(safe_redirect_target(Some("//evil.example")), "/admin");
+        assert_eq!(safe_redirect_target(Some("/\\evil.example")), "/admin");
+        assert_eq!(safe_redirect_target(Some("https://evil.example")), "/admin");
+        assert_eq!(safe_redirect_target(None), "/admin");
+    }
+}
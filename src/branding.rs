@@ -0,0 +1,190 @@
+//! Per-network look of the printed voucher cards: heading, colours, footer,
+//! contact line and an optional logo.
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_HEADING: &str = "WiFi Access Voucher";
+/// Tailwind's blue-500 and blue-50, the original card colours
+pub const DEFAULT_ACCENT_COLOR: &str = "#3b82f6";
+pub const DEFAULT_BACKGROUND_COLOR: &str = "#eff6ff";
+
+const MAX_HEADING_LEN: usize = 60;
+const MAX_LINE_LEN: usize = 200;
+/// Largest logo accepted for upload
+pub const MAX_LOGO_BYTES: usize = 512 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Branding {
+    pub heading: String,
+    /// Card border and heading icon, as `#rrggbb`
+    pub accent_color: String,
+    /// Access code box, as `#rrggbb`
+    pub background_color: String,
+    pub footer: Option<String>,
+    pub contact: Option<String>,
+    /// Whether a logo has been uploaded; the image itself is stored separately
+    pub has_logo: bool,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            heading: DEFAULT_HEADING.to_string(),
+            accent_color: DEFAULT_ACCENT_COLOR.to_string(),
+            background_color: DEFAULT_BACKGROUND_COLOR.to_string(),
+            footer: None,
+            contact: None,
+            has_logo: false,
+        }
+    }
+}
+
+impl Branding {
+    /// Check lengths and colours before saving
+    pub fn validate(&self) -> Result<(), BrandingError> {
+        let heading_len = self.heading.trim().chars().count();
+        if heading_len == 0 || heading_len > MAX_HEADING_LEN {
+            return Err(BrandingError::Heading(MAX_HEADING_LEN));
+        }
+        for line in [&self.footer, &self.contact].into_iter().flatten() {
+            if line.chars().count() > MAX_LINE_LEN {
+                return Err(BrandingError::TooLong(MAX_LINE_LEN));
+            }
+        }
+        parse_color(&self.accent_color)?;
+        parse_color(&self.background_color)?;
+        Ok(())
+    }
+
+    /// The accent colour as RGB, falling back to the default for invalid values
+    pub fn accent_rgb(&self) -> (u8, u8, u8) {
+        parse_color(&self.accent_color)
+            .or_else(|_| parse_color(DEFAULT_ACCENT_COLOR))
+            .unwrap_or_default()
+    }
+
+    pub fn background_rgb(&self) -> (u8, u8, u8) {
+        parse_color(&self.background_color)
+            .or_else(|_| parse_color(DEFAULT_BACKGROUND_COLOR))
+            .unwrap_or_default()
+    }
+}
+
+/// Parse a `#rrggbb` colour
+pub fn parse_color(value: &str) -> Result<(u8, u8, u8), BrandingError> {
+    let invalid = || BrandingError::Color(value.to_string());
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// An uploaded logo image
+#[derive(Debug, Clone)]
+pub struct Logo {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Logo {
+    /// Accept PNG and JPEG images that decode and are not too large
+    pub fn from_upload(data: Vec<u8>) -> Result<Self, BrandingError> {
+        if data.len() > MAX_LOGO_BYTES {
+            return Err(BrandingError::LogoTooLarge(MAX_LOGO_BYTES / 1024));
+        }
+        let content_type = match image::guess_format(&data) {
+            Ok(image::ImageFormat::Png) => "image/png",
+            Ok(image::ImageFormat::Jpeg) => "image/jpeg",
+            _ => return Err(BrandingError::LogoFormat),
+        };
+        image::load_from_memory(&data).map_err(|_| BrandingError::LogoFormat)?;
+
+        Ok(Self {
+            content_type: content_type.to_string(),
+            data,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BrandingError {
+    #[error("the heading must be between 1 and {0} characters")]
+    Heading(usize),
+    #[error("the footer and contact lines must be at most {0} characters")]
+    TooLong(usize),
+    #[error("'{0}' is not a colour in #rrggbb form")]
+    Color(String),
+    #[error("the logo must be a PNG or JPEG image")]
+    LogoFormat,
+    #[error("the logo must be smaller than {0} KB")]
+    LogoTooLarge(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#3b82f6").unwrap(), (59, 130, 246));
+        assert_eq!(parse_color("#FFFFFF").unwrap(), (255, 255, 255));
+        assert!(parse_color("3b82f6").is_err());
+        assert!(parse_color("#3b82f").is_err());
+        assert!(parse_color("#3b82fg").is_err());
+        assert!(parse_color("red").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Branding::default().validate().is_ok());
+
+        let branding = Branding {
+            heading: " ".to_string(),
+            ..Branding::default()
+        };
+        assert!(matches!(
+            branding.validate(),
+            Err(BrandingError::Heading(_))
+        ));
+
+        let branding = Branding {
+            accent_color: "blue".to_string(),
+            ..Branding::default()
+        };
+        assert!(matches!(branding.validate(), Err(BrandingError::Color(_))));
+        assert_eq!(branding.accent_rgb(), (59, 130, 246));
+
+        let branding = Branding {
+            footer: Some("x".repeat(201)),
+            ..Branding::default()
+        };
+        assert!(matches!(
+            branding.validate(),
+            Err(BrandingError::TooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_logo_upload() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        assert_eq!(Logo::from_upload(png).unwrap().content_type, "image/png");
+
+        assert!(matches!(
+            Logo::from_upload(b"<svg></svg>".to_vec()),
+            Err(BrandingError::LogoFormat)
+        ));
+        assert!(matches!(
+            Logo::from_upload(vec![0; MAX_LOGO_BYTES + 1]),
+            Err(BrandingError::LogoTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use rand::{rngs::OsRng, Rng};
+
+/// Characters that are easy to tell apart when printed (no 0/O, 1/I/L)
+pub const UNAMBIGUOUS_ALPHABET: &str = "ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+pub const ALPHANUMERIC_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+pub const NUMERIC_ALPHABET: &str = "0123456789";
+
+pub const MIN_CODE_LENGTH: usize = 4;
+pub const MAX_CODE_LENGTH: usize = 32;
+pub const MAX_CODES_PER_BATCH: usize = 10_000;
+
+/// How generated voucher codes look
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeFormat {
+    /// Number of random characters, excluding prefix and separators
+    pub length: usize,
+    pub alphabet: Vec<char>,
+    pub prefix: String,
+    /// Insert a `-` every `group_size` random characters (e.g. `ABCD-EFGH`)
+    pub group_size: Option<usize>,
+}
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        Self {
+            length: 8,
+            alphabet: UNAMBIGUOUS_ALPHABET.chars().collect(),
+            prefix: String::new(),
+            group_size: None,
+        }
+    }
+}
+
+impl CodeFormat {
+    /// Look up a named alphabet: `unambiguous`, `alphanumeric` or `numeric`
+    pub fn named_alphabet(name: &str) -> Option<&'static str> {
+        match name {
+            "unambiguous" => Some(UNAMBIGUOUS_ALPHABET),
+            "alphanumeric" => Some(ALPHANUMERIC_ALPHABET),
+            "numeric" => Some(NUMERIC_ALPHABET),
+            _ => None,
+        }
+    }
+
+    /// Use the given characters as the alphabet, ignoring duplicates and whitespace
+    pub fn with_alphabet(mut self, characters: &str) -> Self {
+        let mut alphabet: Vec<char> = Vec::new();
+        for c in characters.chars().filter(|c| !c.is_whitespace()) {
+            if !alphabet.contains(&c) {
+                alphabet.push(c);
+            }
+        }
+        self.alphabet = alphabet;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), CodeGeneratorError> {
+        if !(MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&self.length) {
+            return Err(CodeGeneratorError::InvalidLength(self.length));
+        }
+        if self.alphabet.len() < 2 {
+            return Err(CodeGeneratorError::AlphabetTooSmall);
+        }
+        if self.group_size == Some(0) {
+            return Err(CodeGeneratorError::InvalidGroupSize);
+        }
+        if self.prefix.chars().any(|c| c.is_whitespace() || c == ',') {
+            return Err(CodeGeneratorError::InvalidPrefix);
+        }
+        Ok(())
+    }
+
+    /// Number of distinct codes this format can produce
+    pub fn combinations(&self) -> f64 {
+        (self.alphabet.len() as f64).powi(self.length as i32)
+    }
+
+    /// Generate one random code using the operating system's CSPRNG
+    pub fn generate(&self) -> String {
+        let mut code = self.prefix.clone();
+        for i in 0..self.length {
+            if let Some(group_size) = self.group_size {
+                if i > 0 && i % group_size == 0 {
+                    code.push('-');
+                }
+            }
+            code.push(self.alphabet[OsRng.gen_range(0..self.alphabet.len())]);
+        }
+        code
+    }
+}
+
+/// Check that `count` codes can be generated without exhausting the format
+pub fn check_batch(format: &CodeFormat, count: usize) -> Result<(), CodeGeneratorError> {
+    format.validate()?;
+    if count == 0 || count > MAX_CODES_PER_BATCH {
+        return Err(CodeGeneratorError::InvalidCount(count));
+    }
+    // Keep the code space well above the batch size so retries on collision stay rare
+    if format.combinations() < (count as f64) * 100.0 {
+        return Err(CodeGeneratorError::FormatTooSmall);
+    }
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodeGeneratorError {
+    #[error(
+        "Code length must be between {MIN_CODE_LENGTH} and {MAX_CODE_LENGTH} characters, got {0}"
+    )]
+    InvalidLength(usize),
+    #[error("The alphabet needs at least two distinct characters")]
+    AlphabetTooSmall,
+    #[error("Group size must be greater than zero")]
+    InvalidGroupSize,
+    #[error("The prefix cannot contain whitespace or commas")]
+    InvalidPrefix,
+    #[error("Number of codes must be between 1 and {MAX_CODES_PER_BATCH}, got {0}")]
+    InvalidCount(usize),
+    #[error(
+        "Too few possible codes for this many vouchers; use a longer code or a larger alphabet"
+    )]
+    FormatTooSmall,
+    #[error("Could not find enough unused codes; use a longer code or a larger alphabet")]
+    Exhausted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_uses_alphabet_prefix_and_groups() {
+        let format = CodeFormat {
+            length: 8,
+            prefix: "HTL-".to_string(),
+            group_size: Some(4),
+            ..CodeFormat::default()
+        };
+        let code = format.generate();
+
+        assert_eq!(code.len(), "HTL-".len() + 8 + 1);
+        assert!(code.starts_with("HTL-"));
+        assert_eq!(&code[8..9], "-");
+        assert!(code["HTL-".len()..]
+            .chars()
+            .filter(|c| *c != '-')
+            .all(|c| UNAMBIGUOUS_ALPHABET.contains(c)));
+    }
+
+    #[test]
+    fn test_unambiguous_alphabet_excludes_lookalikes() {
+        for c in ['0', 'O', '1', 'I', 'L'] {
+            assert!(!UNAMBIGUOUS_ALPHABET.contains(c));
+        }
+    }
+
+    #[test]
+    fn test_with_alphabet_removes_duplicates() {
+        let format = CodeFormat::default().with_alphabet("AAB B C");
+        assert_eq!(format.alphabet, vec!['A', 'B', 'C']);
+    }
+
+    #[test]
+    fn test_check_batch() {
+        let format = CodeFormat::default();
+        assert!(check_batch(&format, 100).is_ok());
+        assert!(matches!(
+            check_batch(&format, 0),
+            Err(CodeGeneratorError::InvalidCount(0))
+        ));
+
+        let tiny = CodeFormat {
+            length: 4,
+            ..CodeFormat::default()
+        }
+        .with_alphabet("AB");
+        assert!(matches!(
+            check_batch(&tiny, 10),
+            Err(CodeGeneratorError::FormatTooSmall)
+        ));
+
+        let short = CodeFormat {
+            length: 2,
+            ..CodeFormat::default()
+        };
+        assert!(matches!(
+            short.validate(),
+            Err(CodeGeneratorError::InvalidLength(2))
+        ));
+    }
+}
@@ -1,13 +1,16 @@
+use crate::qr_generator::QrOptions;
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::env;
 use std::fs;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 /// Configuration structure that maps to the config.toml file
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    /// Path to the templates directory (relative to project root)
+    /// Path to the templates directory (relative to project root), served under `/static`.
+    /// Page templates are compiled into the binary at build time.
     pub templates_dir: String,
 
     /// Path for the database file (relative to project root)
@@ -16,10 +19,46 @@ pub struct Config {
     /// Server configuration
     #[serde(default)]
     pub server: ServerConfig,
+
+    /// Authentication configuration
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    /// Encryption-at-rest configuration
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+
+    /// Default QR code rendering, overridable per network
+    #[serde(default)]
+    pub qr: QrOptions,
+
+    /// Printing configuration
+    #[serde(default)]
+    pub printing: PrintingConfig,
+
+    /// ESC/POS thermal receipt printer
+    #[serde(default)]
+    pub receipt_printer: ReceiptPrinterConfig,
+
+    /// Voucher redemption for captive portals
+    #[serde(default)]
+    pub redemption: RedemptionConfig,
+
+    /// Built-in RADIUS server
+    #[serde(default)]
+    pub radius: RadiusConfig,
+
+    /// Where low-stock notifications are sent
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+
+    /// Outgoing mail server for email notifications
+    #[serde(default)]
+    pub smtp: SmtpConfig,
 }
 
 /// Server-specific configuration
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize)]
 pub struct ServerConfig {
     /// Default host address
     #[serde(default = "default_host")]
@@ -30,6 +69,320 @@ pub struct ServerConfig {
     pub default_port: u16,
 }
 
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            default_host: default_host(),
+            default_port: default_port(),
+        }
+    }
+}
+
+/// Authentication and session configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthConfig {
+    /// Routes that can be accessed without logging in (matched by path prefix)
+    #[serde(default = "default_public_routes")]
+    pub public_routes: Vec<String>,
+
+    /// How long a login session stays valid, in hours
+    #[serde(default = "default_session_ttl_hours")]
+    pub session_ttl_hours: i64,
+
+    /// Only send the session cookie over HTTPS
+    #[serde(default)]
+    pub cookie_secure: bool,
+
+    /// Username of the admin account created when the database has no users
+    #[serde(default = "default_initial_admin_username")]
+    pub initial_admin_username: String,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            public_routes: default_public_routes(),
+            session_ttl_hours: default_session_ttl_hours(),
+            cookie_secure: false,
+            initial_admin_username: default_initial_admin_username(),
+        }
+    }
+}
+
+/// Encryption key configuration for secrets stored in the database
+#[derive(Debug, Deserialize, Clone)]
+pub struct EncryptionConfig {
+    /// Base64-encoded 256-bit key. Prefer `key_file` or the
+    /// VOUCHER_ENCRYPTION_KEY environment variable over putting it here.
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// Path to a file containing the base64-encoded key (relative to project root).
+    /// Generated on first start if it does not exist.
+    #[serde(default = "default_key_file")]
+    pub key_file: String,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            key_file: default_key_file(),
+        }
+    }
+}
+
+/// Printing configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct PrintingConfig {
+    /// How long a print job holds its vouchers before they go back to the
+    /// unused pool unless the print is confirmed, in minutes
+    #[serde(default = "default_reservation_minutes")]
+    pub reservation_minutes: i64,
+}
+
+impl Default for PrintingConfig {
+    fn default() -> Self {
+        Self {
+            reservation_minutes: default_reservation_minutes(),
+        }
+    }
+}
+
+impl PrintingConfig {
+    pub fn reservation(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.reservation_minutes)
+    }
+}
+
+fn default_reservation_minutes() -> i64 {
+    30
+}
+
+/// ESC/POS thermal receipt printer configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReceiptPrinterConfig {
+    /// Host and port of a network printer that accepts raw print data,
+    /// e.g. "192.168.1.50:9100"; the port defaults to 9100. Without it,
+    /// receipts can only be downloaded.
+    #[serde(default)]
+    pub address: Option<String>,
+
+    /// Paper roll width in millimetres, 58 or 80
+    #[serde(default = "default_paper_width_mm")]
+    pub paper_width_mm: u32,
+}
+
+impl Default for ReceiptPrinterConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            paper_width_mm: default_paper_width_mm(),
+        }
+    }
+}
+
+impl ReceiptPrinterConfig {
+    pub fn paper_width(&self) -> Result<crate::escpos::PaperWidth, crate::escpos::EscPosError> {
+        crate::escpos::PaperWidth::from_mm(self.paper_width_mm)
+    }
+
+    /// The printer's address with the raw printing port added if none is given
+    pub fn printer_address(&self) -> Option<String> {
+        self.address.as_ref().map(|address| {
+            if address
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+            {
+                address.clone()
+            } else {
+                format!("{}:{}", address, crate::escpos::DEFAULT_PORT)
+            }
+        })
+    }
+}
+
+fn default_paper_width_mm() -> u32 {
+    80
+}
+
+/// Built-in RADIUS server configuration. The server runs when a secret and at
+/// least one address are set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RadiusConfig {
+    /// Address for Access-Requests, e.g. "0.0.0.0:1812"
+    #[serde(default)]
+    pub auth_address: Option<String>,
+
+    /// Address for Accounting-Requests, e.g. "0.0.0.0:1813"
+    #[serde(default)]
+    pub accounting_address: Option<String>,
+
+    /// Shared secret configured on the access points
+    #[serde(default)]
+    pub secret: Option<String>,
+
+    /// Drop Access-Requests without a Message-Authenticator (RFC 3579), which
+    /// guards against forged responses (BlastRADIUS). On unless an access point
+    /// can't send one.
+    #[serde(default = "default_true")]
+    pub require_message_authenticator: bool,
+
+    /// The access points allowed to send requests, each tied to one network
+    #[serde(default)]
+    pub clients: Vec<RadiusClient>,
+}
+
+impl Default for RadiusConfig {
+    fn default() -> Self {
+        Self {
+            auth_address: None,
+            accounting_address: None,
+            secret: None,
+            require_message_authenticator: true,
+            clients: Vec::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// An access point or controller, recognised by the address its requests come
+/// from, the NAS-Identifier it sends, or both. Its guests can only log in with
+/// vouchers of `network_id`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RadiusClient {
+    #[serde(default)]
+    pub address: Option<IpAddr>,
+
+    #[serde(default)]
+    pub nas_identifier: Option<String>,
+
+    pub network_id: String,
+}
+
+impl RadiusClient {
+    pub fn matches(&self, source: IpAddr, nas_identifier: Option<&str>) -> bool {
+        self.address.is_none_or(|address| address == source)
+            && self
+                .nas_identifier
+                .as_deref()
+                .is_none_or(|identifier| Some(identifier) == nas_identifier)
+    }
+}
+
+/// Shortest API key accepted for voucher redemption
+const MIN_API_KEY_LEN: usize = 16;
+
+/// Voucher redemption configuration
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RedemptionConfig {
+    /// Key captive portals send as a bearer token to redeem vouchers without a
+    /// login session. Without it, only logged-in users can redeem.
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+impl RedemptionConfig {
+    /// Whether `key` is the configured API key, compared in constant time
+    pub fn accepts(&self, key: &str) -> bool {
+        let Some(api_key) = &self.api_key else {
+            return false;
+        };
+        api_key.len() == key.len()
+            && api_key
+                .bytes()
+                .zip(key.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Low-stock notification configuration. Alerts are always logged; these
+/// settings send them on as well.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AlertsConfig {
+    /// URL that receives a JSON POST for each alert
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+
+    /// Addresses that receive an email for each alert, sent through [smtp]
+    #[serde(default)]
+    pub email_to: Vec<String>,
+}
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS, usually on port 587
+    #[default]
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// Unencrypted, only for a relay on the local network
+    None,
+}
+
+/// Outgoing mail server configuration
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+
+    #[serde(default)]
+    pub security: SmtpSecurity,
+
+    /// Login for servers that require authentication
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Sender address, e.g. "WiFi Vouchers <vouchers@example.com>"
+    #[serde(default)]
+    pub from: Option<String>,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: default_smtp_port(),
+            security: SmtpSecurity::default(),
+            username: None,
+            password: None,
+            from: None,
+        }
+    }
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_key_file() -> String {
+    "voucher.key".to_string()
+}
+
+fn default_public_routes() -> Vec<String> {
+    vec!["/login".to_string(), "/static".to_string()]
+}
+
+fn default_session_ttl_hours() -> i64 {
+    12
+}
+
+fn default_initial_admin_username() -> String {
+    "admin".to_string()
+}
+
 fn default_host() -> String {
     "127.0.0.1".to_string()
 }
@@ -47,6 +400,57 @@ impl Config {
 
         let config: Config =
             toml::from_str(&config_content).with_context(|| "Failed to parse config.toml")?;
+        config
+            .qr
+            .validate()
+            .context("Invalid [qr] section in config.toml")?;
+        anyhow::ensure!(
+            config.printing.reservation_minutes > 0,
+            "printing.reservation_minutes in config.toml must be at least 1"
+        );
+        config
+            .receipt_printer
+            .paper_width()
+            .context("Invalid [receipt_printer] section in config.toml")?;
+        anyhow::ensure!(
+            config
+                .redemption
+                .api_key
+                .as_ref()
+                .is_none_or(|key| key.len() >= MIN_API_KEY_LEN),
+            "redemption.api_key in config.toml must be at least {} characters",
+            MIN_API_KEY_LEN
+        );
+        let radius = &config.radius;
+        anyhow::ensure!(
+            radius.auth_address.is_none() && radius.accounting_address.is_none()
+                || radius
+                    .secret
+                    .as_ref()
+                    .is_some_and(|secret| !secret.is_empty()),
+            "radius.secret in config.toml is required to run the RADIUS server"
+        );
+        anyhow::ensure!(
+            radius.auth_address.is_none() && radius.accounting_address.is_none()
+                || !radius.clients.is_empty(),
+            "at least one [[radius.clients]] entry in config.toml is required to run the RADIUS server"
+        );
+        anyhow::ensure!(
+            radius
+                .clients
+                .iter()
+                .all(|client| client.address.is_some() || client.nas_identifier.is_some()),
+            "each [[radius.clients]] entry in config.toml needs an address or nas_identifier"
+        );
+        if let Some(webhook_url) = &config.alerts.webhook_url {
+            crate::http_client::check_url(webhook_url)
+                .context("Invalid alerts.webhook_url in config.toml")?;
+        }
+        anyhow::ensure!(
+            config.alerts.email_to.is_empty()
+                || config.smtp.host.is_some() && config.smtp.from.is_some(),
+            "smtp.host and smtp.from in config.toml are required to email alerts"
+        );
 
         Ok(config)
     }
@@ -118,6 +522,42 @@ impl Config {
         Ok(())
     }
 
+    /// Get the absolute path to the encryption key file
+    pub fn key_file_path(&self) -> Result<PathBuf> {
+        let current_dir = env::current_dir().context("Failed to get current directory")?;
+        Ok(current_dir.join(&self.encryption.key_file))
+    }
+
+    /// Resolve the encryption key, in order of precedence: the
+    /// VOUCHER_ENCRYPTION_KEY environment variable, the `key` setting, then
+    /// the key file. A new key file is generated if none of them is set.
+    pub fn encryption_key(&self) -> Result<String> {
+        if let Ok(key) = env::var("VOUCHER_ENCRYPTION_KEY") {
+            if !key.trim().is_empty() {
+                return Ok(key.trim().to_string());
+            }
+        }
+
+        if let Some(key) = &self.encryption.key {
+            return Ok(key.trim().to_string());
+        }
+
+        let key_file = self.key_file_path()?;
+        if key_file.exists() {
+            let key = fs::read_to_string(&key_file).with_context(|| {
+                format!("Failed to read encryption key file: {}", key_file.display())
+            })?;
+            return Ok(key.trim().to_string());
+        }
+
+        let key = crate::crypto::PasswordCipher::generate_key();
+        write_key_file(&key_file, &key)?;
+        println!("Generated new encryption key file: {}", key_file.display());
+        println!("Keep this file out of your database backups and store a copy somewhere safe.");
+
+        Ok(key)
+    }
+
     /// Generate a database URL for SQLx
     pub fn database_url(&self) -> Result<String> {
         let db_path = self.database_file_path()?;
@@ -125,6 +565,25 @@ impl Config {
     }
 }
 
+/// Write a key file readable only by the current user
+pub fn write_key_file(path: &Path, key: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create key file: {}", path.display()))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", key).as_bytes())
+        .with_context(|| format!("Failed to write key file: {}", path.display()))?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,6 +621,219 @@ mod tests {
         assert_eq!(config.server.default_port, 3000);
     }
 
+    #[test]
+    fn test_parse_qr_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [qr]
+            error_correction = "H"
+            quiet_zone = 4
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(
+            config.qr.error_correction,
+            crate::qr_generator::ErrorCorrection::H
+        );
+        assert_eq!(config.qr.quiet_zone, 4);
+        assert_eq!(config.qr.module_size, QrOptions::default().module_size);
+    }
+
+    #[test]
+    fn test_default_auth_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(config.auth.public_routes, vec!["/login", "/static"]);
+        assert_eq!(config.auth.session_ttl_hours, 12);
+        assert!(!config.auth.cookie_secure);
+        assert_eq!(config.auth.initial_admin_username, "admin");
+        assert_eq!(config.printing.reservation_minutes, 30);
+    }
+
+    #[test]
+    fn test_parse_printing_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [printing]
+            reservation_minutes = 5
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(config.printing.reservation(), chrono::Duration::minutes(5));
+    }
+
+    #[test]
+    fn test_parse_receipt_printer_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [receipt_printer]
+            address = "192.168.1.50"
+            paper_width_mm = 58
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(
+            config.receipt_printer.printer_address().unwrap(),
+            "192.168.1.50:9100"
+        );
+        assert_eq!(config.receipt_printer.paper_width_mm, 58);
+
+        let printer = ReceiptPrinterConfig {
+            address: Some("printer.local:9101".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(printer.printer_address().unwrap(), "printer.local:9101");
+        assert!(ReceiptPrinterConfig::default().printer_address().is_none());
+    }
+
+    #[test]
+    fn test_parse_redemption_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [redemption]
+            api_key = "portal-key-0123456789"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert!(config.redemption.accepts("portal-key-0123456789"));
+        assert!(!config.redemption.accepts("portal-key-012345678"));
+        assert!(!config.redemption.accepts(""));
+        assert!(!RedemptionConfig::default().accepts(""));
+    }
+
+    #[test]
+    fn test_parse_alerts_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [alerts]
+            webhook_url = "https://hooks.example.com/vouchers"
+            email_to = ["frontdesk@example.com"]
+
+            [smtp]
+            host = "smtp.example.com"
+            security = "tls"
+            port = 465
+            from = "vouchers@example.com"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(
+            config.alerts.webhook_url.as_deref(),
+            Some("https://hooks.example.com/vouchers")
+        );
+        assert_eq!(config.alerts.email_to, vec!["frontdesk@example.com"]);
+        assert_eq!(config.smtp.security, SmtpSecurity::Tls);
+        assert_eq!(config.smtp.port, 465);
+        assert!(config.smtp.username.is_none());
+
+        let smtp = SmtpConfig::default();
+        assert_eq!(smtp.security, SmtpSecurity::StartTls);
+        assert_eq!(smtp.port, 587);
+    }
+
+    #[test]
+    fn test_parse_radius_clients() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [radius]
+            auth_address = "0.0.0.0:1812"
+            secret = "shared-secret"
+
+            [[radius.clients]]
+            address = "192.168.1.2"
+            network_id = "lobby"
+
+            [[radius.clients]]
+            nas_identifier = "ap-pool"
+            network_id = "pool"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+        let radius = &config.radius;
+
+        assert!(radius.require_message_authenticator);
+        assert_eq!(radius.clients.len(), 2);
+        let lobby: IpAddr = "192.168.1.2".parse().unwrap();
+        let other: IpAddr = "192.168.1.3".parse().unwrap();
+        assert!(radius.clients[0].matches(lobby, Some("anything")));
+        assert!(!radius.clients[0].matches(other, None));
+        assert!(radius.clients[1].matches(other, Some("ap-pool")));
+        assert!(!radius.clients[1].matches(other, Some("ap-lobby")));
+        assert!(RadiusConfig::default().require_message_authenticator);
+    }
+
+    #[test]
+    fn test_parse_auth_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [auth]
+            public_routes = ["/login", "/static", "/vouchers"]
+            session_ttl_hours = 2
+            cookie_secure = true
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert_eq!(config.auth.public_routes.len(), 3);
+        assert_eq!(config.auth.session_ttl_hours, 2);
+        assert!(config.auth.cookie_secure);
+    }
+
+    #[test]
+    fn test_parse_encryption_config() {
+        let config_content = r#"
+            templates_dir = "templates"
+            database_path = "vouchers.db"
+
+            [encryption]
+            key_file = "/etc/voucher_creator/voucher.key"
+        "#;
+
+        let config: Config = toml::from_str(config_content).unwrap();
+
+        assert!(config.encryption.key.is_none());
+        assert_eq!(
+            config.encryption.key_file,
+            "/etc/voucher_creator/voucher.key"
+        );
+    }
+
+    #[test]
+    fn test_write_key_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("test.key");
+
+        write_key_file(&path, "abc").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\n");
+
+        // Never overwrite an existing key
+        assert!(write_key_file(&path, "def").is_err());
+    }
+
     #[test]
     fn test_database_url() {
         let temp_dir = tempdir().unwrap();
@@ -173,6 +845,15 @@ mod tests {
             templates_dir: "templates".to_string(),
             database_path: "data/app.db".to_string(),
             server: ServerConfig::default(),
+            auth: AuthConfig::default(),
+            encryption: EncryptionConfig::default(),
+            qr: QrOptions::default(),
+            printing: PrintingConfig::default(),
+            receipt_printer: ReceiptPrinterConfig::default(),
+            redemption: RedemptionConfig::default(),
+            radius: RadiusConfig::default(),
+            alerts: AlertsConfig::default(),
+            smtp: SmtpConfig::default(),
         };
 
         let db_url = config.database_url().unwrap();
@@ -0,0 +1,151 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose, Engine as _};
+
+/// Prefix marking a value encrypted with version 1 of the scheme (AES-256-GCM)
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// Encrypts secrets such as WiFi passwords before they are written to the database
+#[derive(Clone)]
+pub struct PasswordCipher {
+    cipher: Aes256Gcm,
+}
+
+impl PasswordCipher {
+    /// Build a cipher from a base64-encoded 256-bit key
+    pub fn from_base64(key: &str) -> Result<Self, CryptoError> {
+        let bytes = general_purpose::STANDARD
+            .decode(key.trim())
+            .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+
+        if bytes.len() != KEY_LEN {
+            return Err(CryptoError::InvalidKey(format!(
+                "expected {} bytes, got {}",
+                KEY_LEN,
+                bytes.len()
+            )));
+        }
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)),
+        })
+    }
+
+    /// Generate a new random key, base64-encoded
+    pub fn generate_key() -> String {
+        general_purpose::STANDARD.encode(Aes256Gcm::generate_key(&mut OsRng))
+    }
+
+    /// Whether a stored value was produced by `encrypt`
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    /// Encrypt a value with a fresh random nonce
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, CryptoError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| CryptoError::Encryption)?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            general_purpose::STANDARD.encode(payload)
+        ))
+    }
+
+    /// Decrypt a value produced by `encrypt`
+    pub fn decrypt(&self, value: &str) -> Result<String, CryptoError> {
+        let encoded = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or(CryptoError::NotEncrypted)?;
+        let payload = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| CryptoError::Decryption)?;
+
+        if payload.len() < NONCE_LEN {
+            return Err(CryptoError::Decryption);
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Decryption)?;
+
+        String::from_utf8(plaintext).map_err(|_| CryptoError::Decryption)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("Invalid encryption key: {0}")]
+    InvalidKey(String),
+
+    #[error("Encryption failed")]
+    Encryption,
+
+    #[error("Decryption failed: wrong key or corrupted data")]
+    Decryption,
+
+    #[error("Value is not encrypted")]
+    NotEncrypted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let cipher = PasswordCipher::from_base64(&PasswordCipher::generate_key()).unwrap();
+        let encrypted = cipher.encrypt("hunter2; with \"quotes\"").unwrap();
+
+        assert!(PasswordCipher::is_encrypted(&encrypted));
+        assert!(!encrypted.contains("hunter2"));
+        assert_eq!(
+            cipher.decrypt(&encrypted).unwrap(),
+            "hunter2; with \"quotes\""
+        );
+    }
+
+    #[test]
+    fn test_encryption_uses_fresh_nonce() {
+        let cipher = PasswordCipher::from_base64(&PasswordCipher::generate_key()).unwrap();
+        assert_ne!(
+            cipher.encrypt("same").unwrap(),
+            cipher.encrypt("same").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key_fails() {
+        let cipher = PasswordCipher::from_base64(&PasswordCipher::generate_key()).unwrap();
+        let other = PasswordCipher::from_base64(&PasswordCipher::generate_key()).unwrap();
+        let encrypted = cipher.encrypt("secret").unwrap();
+
+        assert!(matches!(
+            other.decrypt(&encrypted),
+            Err(CryptoError::Decryption)
+        ));
+        assert!(matches!(
+            cipher.decrypt("secret"),
+            Err(CryptoError::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_keys_are_rejected() {
+        assert!(PasswordCipher::from_base64("not base64!").is_err());
+        assert!(PasswordCipher::from_base64(&general_purpose::STANDARD.encode([0u8; 16])).is_err());
+    }
+}
@@ -1,148 +1,218 @@
-use crate::voucher::Voucher;
+use crate::branding::{Branding, Logo};
+use crate::code_generator::{self, CodeFormat, CodeGeneratorError};
+use crate::crypto::PasswordCipher;
+use crate::migrations;
+use crate::pfsense::{PfSenseSettings, RollSettings, VoucherRoll};
+use crate::portal::PortalLogin;
+use crate::print_job::PrintJob;
+use crate::redemption::{Device, RedeemError, Redemption, VoucherSession};
+use crate::user::{Role, User};
+use crate::voucher::{Validity, Voucher, VoucherState, VoucherStateError, VoucherTransition};
+use crate::webhook::{Webhook, WebhookDelivery, WebhookEvent};
 use crate::wifi_network::WiFiNetwork;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use sqlx::{sqlite::SqlitePool, Row};
 
+/// Network columns, plus whether a logo has been uploaded
+const NETWORK_SELECT: &str = r#"
+    SELECT id, name, ssid, password, description, created_at, is_active, layout,
+        brand_heading, brand_accent_color, brand_background_color, brand_footer, brand_contact,
+        qr_options, security, hidden, transition_disable, portal_qr_mode, portal_url,
+        low_stock_threshold,
+        EXISTS (SELECT 1 FROM network_logos WHERE network_id = wifi_networks.id) AS has_logo
+    FROM wifi_networks
+"#;
+
+const VOUCHER_COLUMNS: &str = "id, code, network_id, created_at, is_used, used_at, \
+    state, state_changed_at, duration_minutes, expires_at, data_limit_mb, download_kbps, \
+    upload_kbps, redeemed_at, client_mac, client_ip";
+
+const INSERT_VOUCHER: &str = r#"
+    INSERT INTO vouchers (id, code, network_id, created_at, is_used, used_at,
+        duration_minutes, expires_at, data_limit_mb, download_kbps, upload_kbps, roll_id,
+        state, state_changed_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+"#;
+
+const PRINT_JOB_COLUMNS: &str =
+    "id, network_id, printed_by, created_at, layout, output, status, expires_at, settings";
+
 pub struct Database {
     pool: SqlitePool,
+    cipher: PasswordCipher,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self> {
+    pub async fn new(database_url: &str, cipher: PasswordCipher) -> Result<Self> {
         let pool = SqlitePool::connect(database_url).await?;
 
-        let db = Self { pool };
+        let db = Self { pool, cipher };
         db.migrate().await?;
+        db.encrypt_plaintext_passwords().await?;
 
         Ok(db)
     }
 
-    async fn migrate(&self) -> Result<()> {
-        // Create wifi_networks table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS wifi_networks (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                ssid TEXT NOT NULL,
-                password TEXT NOT NULL,
-                description TEXT,
-                created_at TEXT NOT NULL,
-                is_active BOOLEAN NOT NULL DEFAULT TRUE
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    /// Encrypt network passwords that were stored before encryption at rest existed,
+    /// and make sure the configured key can read the ones that already are, and
+    /// the webhook secrets
+    async fn encrypt_plaintext_passwords(&self) -> Result<usize> {
+        let rows = sqlx::query("SELECT id, password FROM wifi_networks")
+            .fetch_all(&self.pool)
+            .await?;
 
-        // Create vouchers table
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS vouchers (
-                id TEXT PRIMARY KEY,
-                code TEXT NOT NULL UNIQUE,
-                network_id TEXT,
-                created_at TEXT NOT NULL,
-                is_used BOOLEAN NOT NULL DEFAULT FALSE,
-                used_at TEXT,
-                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
-            )
-            "#,
+        let mut tx = self.pool.begin().await?;
+        let mut count = 0;
+
+        for row in rows {
+            let password: String = row.get("password");
+            if PasswordCipher::is_encrypted(&password) {
+                self.cipher.decrypt(&password).context(
+                    "The configured encryption key cannot decrypt the stored network passwords",
+                )?;
+                continue;
+            }
+
+            sqlx::query("UPDATE wifi_networks SET password = ?1 WHERE id = ?2")
+                .bind(self.cipher.encrypt(&password)?)
+                .bind(row.get::<String, _>("id"))
+                .execute(&mut *tx)
+                .await?;
+            count += 1;
+        }
+
+        let secrets = sqlx::query("SELECT secret FROM webhooks LIMIT 1")
+            .fetch_all(&mut *tx)
+            .await?;
+        for row in &secrets {
+            self.cipher
+                .decrypt(&row.get::<String, _>("secret"))
+                .context(
+                    "The configured encryption key cannot decrypt the stored webhook secrets",
+                )?;
+        }
+
+        tx.commit().await?;
+
+        if count > 0 {
+            println!("Encrypted {} plaintext network password(s)", count);
+        }
+
+        Ok(count)
+    }
+
+    /// Re-encrypt every stored network password, pfSense private key and webhook
+    /// secret with a new key. Runs in a single transaction so a failure leaves the
+    /// old key valid.
+    pub async fn rotate_encryption_key(&self, new_cipher: &PasswordCipher) -> Result<usize> {
+        let rows = sqlx::query("SELECT id, password FROM wifi_networks")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        for row in &rows {
+            let password = self.cipher.decrypt(&row.get::<String, _>("password"))?;
+
+            sqlx::query("UPDATE wifi_networks SET password = ?1 WHERE id = ?2")
+                .bind(new_cipher.encrypt(&password)?)
+                .bind(row.get::<String, _>("id"))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let keys = sqlx::query(
+            "SELECT network_id, private_key FROM pfsense_settings WHERE private_key IS NOT NULL",
         )
-        .execute(&self.pool)
+        .fetch_all(&mut *tx)
         .await?;
 
-        // No need to add columns for existing databases since we've removed the columns
+        for row in &keys {
+            let private_key = self.cipher.decrypt(&row.get::<String, _>("private_key"))?;
 
-        // Create indexes for better performance
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_vouchers_network_id ON vouchers(network_id)")
-            .execute(&self.pool)
-            .await?;
+            sqlx::query("UPDATE pfsense_settings SET private_key = ?1 WHERE network_id = ?2")
+                .bind(new_cipher.encrypt(&private_key)?)
+                .bind(row.get::<String, _>("network_id"))
+                .execute(&mut *tx)
+                .await?;
+        }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_vouchers_is_used ON vouchers(is_used)")
-            .execute(&self.pool)
+        let secrets = sqlx::query("SELECT id, secret FROM webhooks")
+            .fetch_all(&mut *tx)
             .await?;
 
-        // Removed is_printed index since we no longer have this column
+        for row in &secrets {
+            let secret = self.cipher.decrypt(&row.get::<String, _>("secret"))?;
+
+            sqlx::query("UPDATE webhooks SET secret = ?1 WHERE id = ?2")
+                .bind(new_cipher.encrypt(&secret)?)
+                .bind(row.get::<String, _>("id"))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(rows.len())
+    }
 
+    async fn migrate(&self) -> Result<()> {
+        migrations::run(&self.pool).await?;
         Ok(())
     }
 
-    // WiFi Network operations
-    pub async fn create_network(&self, network: &WiFiNetwork) -> Result<()> {
+    // User operations
+    pub async fn create_user(&self, user: &User) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO wifi_networks (id, name, ssid, password, description, created_at, is_active)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            INSERT INTO users (id, username, password_hash, role, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
             "#,
         )
-        .bind(&network.id)
-        .bind(&network.name)
-        .bind(&network.ssid)
-        .bind(&network.password)
-        .bind(&network.description)
-        .bind(network.created_at.to_rfc3339())
-        .bind(network.is_active)
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.role.as_str())
+        .bind(timestamp(user.created_at))
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    pub async fn get_network(&self, id: &str) -> Result<Option<WiFiNetwork>> {
+    pub async fn get_user(&self, id: &str) -> Result<Option<User>> {
         let row = sqlx::query(
-            "SELECT id, name, ssid, password, description, created_at, is_active FROM wifi_networks WHERE id = ?1"
+            "SELECT id, username, password_hash, role, created_at FROM users WHERE id = ?1",
         )
         .bind(id)
         .fetch_optional(&self.pool)
         .await?;
 
-        if let Some(row) = row {
-            Ok(Some(WiFiNetwork {
-                id: row.get("id"),
-                name: row.get("name"),
-                ssid: row.get("ssid"),
-                password: row.get("password"),
-                description: row.get("description"),
-                created_at: chrono::DateTime::parse_from_rfc3339(
-                    &row.get::<String, _>("created_at"),
-                )?
-                .with_timezone(&chrono::Utc),
-                is_active: row.get("is_active"),
-            }))
-        } else {
-            Ok(None)
-        }
+        row.map(|row| user_from_row(&row)).transpose()
     }
 
-    pub async fn get_all_networks(&self) -> Result<Vec<WiFiNetwork>> {
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
+        let row = sqlx::query(
+            "SELECT id, username, password_hash, role, created_at FROM users WHERE username = ?1",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| user_from_row(&row)).transpose()
+    }
+
+    pub async fn get_all_users(&self) -> Result<Vec<User>> {
         let rows = sqlx::query(
-            "SELECT id, name, ssid, password, description, created_at, is_active FROM wifi_networks ORDER BY created_at ASC"
+            "SELECT id, username, password_hash, role, created_at FROM users ORDER BY username ASC",
         )
         .fetch_all(&self.pool)
         .await?;
 
-        let mut networks = Vec::new();
-        for row in rows {
-            networks.push(WiFiNetwork {
-                id: row.get("id"),
-                name: row.get("name"),
-                ssid: row.get("ssid"),
-                password: row.get("password"),
-                description: row.get("description"),
-                created_at: chrono::DateTime::parse_from_rfc3339(
-                    &row.get::<String, _>("created_at"),
-                )?
-                .with_timezone(&chrono::Utc),
-                is_active: row.get("is_active"),
-            });
-        }
-
-        Ok(networks)
+        rows.iter().map(user_from_row).collect()
     }
 
-    pub async fn delete_network(&self, id: &str) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM wifi_networks WHERE id = ?1")
+    pub async fn delete_user(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?1")
             .bind(id)
             .execute(&self.pool)
             .await?;
@@ -150,205 +220,2365 @@ impl Database {
         Ok(result.rows_affected() > 0)
     }
 
-    pub async fn create_vouchers(&self, vouchers: &[Voucher]) -> Result<()> {
+    /// Replace the set of networks a user is assigned to
+    pub async fn set_user_networks(&self, user_id: &str, network_ids: &[String]) -> Result<()> {
         let mut tx = self.pool.begin().await?;
 
-        for voucher in vouchers {
-            sqlx::query(
-                r#"
-                INSERT INTO vouchers (id, code, network_id, created_at, is_used, used_at)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-                "#,
-            )
-            .bind(&voucher.id)
-            .bind(&voucher.code)
-            .bind(&voucher.network_id)
-            .bind(voucher.created_at.to_rfc3339())
-            .bind(voucher.is_used)
-            .bind(voucher.used_at.map(|dt| dt.to_rfc3339()))
+        sqlx::query("DELETE FROM user_networks WHERE user_id = ?1")
+            .bind(user_id)
             .execute(&mut *tx)
             .await?;
+
+        for network_id in network_ids {
+            sqlx::query("INSERT INTO user_networks (user_id, network_id) VALUES (?1, ?2)")
+                .bind(user_id)
+                .bind(network_id)
+                .execute(&mut *tx)
+                .await?;
         }
 
         tx.commit().await?;
         Ok(())
     }
 
-    pub async fn get_all_vouchers(&self) -> Result<Vec<Voucher>> {
-        let rows = sqlx::query(
-            "SELECT id, code, network_id, created_at, is_used, used_at FROM vouchers ORDER BY created_at ASC"
-        )
-        .fetch_all(&self.pool)
-        .await?;
-
-        let mut vouchers = Vec::new();
-        for row in rows {
-            vouchers.push(Voucher {
-                id: row.get("id"),
-                code: row.get("code"),
-                network_id: row.get("network_id"),
-                created_at: chrono::DateTime::parse_from_rfc3339(
-                    &row.get::<String, _>("created_at"),
-                )?
-                .with_timezone(&chrono::Utc),
-                is_used: row.get("is_used"),
-                used_at: row
-                    .get::<Option<String>, _>("used_at")
-                    .map(|s| {
-                        chrono::DateTime::parse_from_rfc3339(&s)
-                            .map(|dt| dt.with_timezone(&chrono::Utc))
-                    })
-                    .transpose()?,
-                // Fields is_printed and printed_at removed as they're now combined with is_used/used_at
-            });
-        }
+    pub async fn get_user_network_ids(&self, user_id: &str) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT network_id FROM user_networks WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
 
-        Ok(vouchers)
+        Ok(rows.iter().map(|row| row.get("network_id")).collect())
     }
 
-    pub async fn get_vouchers_for_network(&self, network_id: &str) -> Result<Vec<Voucher>> {
-        let rows = sqlx::query(
-            "SELECT id, code, network_id, created_at, is_used, used_at FROM vouchers WHERE network_id = ?1 ORDER BY created_at ASC"
+    pub async fn user_has_network(&self, user_id: &str, network_id: &str) -> Result<bool> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) as total FROM user_networks WHERE user_id = ?1 AND network_id = ?2",
         )
+        .bind(user_id)
         .bind(network_id)
-        .fetch_all(&self.pool)
+        .fetch_one(&self.pool)
         .await?;
 
-        let mut vouchers = Vec::new();
-        for row in rows {
-            vouchers.push(Voucher {
-                id: row.get("id"),
-                code: row.get("code"),
-                network_id: row.get("network_id"),
-                created_at: chrono::DateTime::parse_from_rfc3339(
-                    &row.get::<String, _>("created_at"),
-                )?
-                .with_timezone(&chrono::Utc),
-                is_used: row.get("is_used"),
-                used_at: row
-                    .get::<Option<String>, _>("used_at")
-                    .map(|s| {
-                        chrono::DateTime::parse_from_rfc3339(&s)
-                            .map(|dt| dt.with_timezone(&chrono::Utc))
-                    })
-                    .transpose()?,
-                // Fields is_printed and printed_at removed as they're now combined with is_used/used_at
-            });
-        }
+        Ok(row.get::<i64, _>("total") > 0)
+    }
 
-        Ok(vouchers)
+    pub async fn count_users(&self) -> Result<usize> {
+        let row = sqlx::query("SELECT COUNT(*) as total FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.get::<i64, _>("total") as usize)
     }
 
-    pub async fn mark_voucher_as_used(&self, voucher_id: &str) -> Result<bool> {
-        let now = chrono::Utc::now().to_rfc3339();
-        let result = sqlx::query(
-            "UPDATE vouchers SET is_used = TRUE, used_at = ?1 WHERE id = ?2 AND is_used = FALSE",
+    // Session operations
+    pub async fn create_session(
+        &self,
+        token: &str,
+        user_id: &str,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (token, user_id, created_at, expires_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
         )
-        .bind(&now)
-        .bind(voucher_id)
+        .bind(token)
+        .bind(user_id)
+        .bind(timestamp(chrono::Utc::now()))
+        .bind(timestamp(expires_at))
         .execute(&self.pool)
         .await?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(())
     }
 
-    pub async fn mark_voucher_as_unused(&self, voucher_id: &str) -> Result<bool> {
-        let result =
-            sqlx::query("UPDATE vouchers SET is_used = FALSE, used_at = NULL WHERE id = ?1")
-                .bind(voucher_id)
-                .execute(&self.pool)
-                .await?;
+    /// Look up the user owning a session token, ignoring expired sessions
+    pub async fn get_session_user(&self, token: &str) -> Result<Option<User>> {
+        let row = sqlx::query(
+            r#"
+            SELECT users.id, users.username, users.password_hash, users.role, users.created_at
+            FROM sessions
+            JOIN users ON users.id = sessions.user_id
+            WHERE sessions.token = ?1 AND sessions.expires_at > ?2
+            "#,
+        )
+        .bind(token)
+        .bind(timestamp(chrono::Utc::now()))
+        .fetch_optional(&self.pool)
+        .await?;
 
-        Ok(result.rows_affected() > 0)
+        row.map(|row| user_from_row(&row)).transpose()
     }
 
-    pub async fn mark_vouchers_as_used(&self, voucher_ids: &[String]) -> Result<usize> {
-        let mut tx = self.pool.begin().await?;
-        let now = chrono::Utc::now().to_rfc3339();
-        let mut count = 0;
+    pub async fn delete_session(&self, token: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM sessions WHERE token = ?1")
+            .bind(token)
+            .execute(&self.pool)
+            .await?;
 
-        for voucher_id in voucher_ids {
-            let result = sqlx::query(
-                "UPDATE vouchers SET is_used = TRUE, used_at = ?1 WHERE id = ?2 AND is_used = FALSE",
-            )
-            .bind(&now)
-            .bind(voucher_id)
-            .execute(&mut *tx)
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_expired_sessions(&self) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= ?1")
+            .bind(timestamp(chrono::Utc::now()))
+            .execute(&self.pool)
             .await?;
-            
-            count += result.rows_affected() as usize;
-        }
 
-        tx.commit().await?;
-        Ok(count)
+        Ok(result.rows_affected() as usize)
     }
 
-    pub async fn get_unused_vouchers_for_network(&self, network_id: &str, limit: Option<usize>) -> Result<Vec<Voucher>> {
-        let query = if let Some(limit) = limit {
-            format!(
-                "SELECT id, code, network_id, created_at, is_used, used_at FROM vouchers WHERE network_id = ?1 AND is_used = FALSE ORDER BY created_at ASC LIMIT {}",
-                limit
+    // WiFi Network operations
+    pub async fn create_network(&self, network: &WiFiNetwork) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO wifi_networks (
+                id, name, ssid, password, description, created_at, is_active, layout,
+                brand_heading, brand_accent_color, brand_background_color, brand_footer, brand_contact,
+                qr_options, security, hidden, transition_disable, portal_qr_mode, portal_url,
+                low_stock_threshold
             )
-        } else {
-            "SELECT id, code, network_id, created_at, is_used, used_at FROM vouchers WHERE network_id = ?1 AND is_used = FALSE ORDER BY created_at ASC".to_string()
-        };
+            VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                ?20
+            )
+            "#,
+        )
+        .bind(&network.id)
+        .bind(&network.name)
+        .bind(&network.ssid)
+        .bind(self.cipher.encrypt(&network.password)?)
+        .bind(&network.description)
+        .bind(timestamp(network.created_at))
+        .bind(network.is_active)
+        .bind(&network.layout)
+        .bind(&network.branding.heading)
+        .bind(&network.branding.accent_color)
+        .bind(&network.branding.background_color)
+        .bind(&network.branding.footer)
+        .bind(&network.branding.contact)
+        .bind(qr_options_json(network)?)
+        .bind(network.security.as_str())
+        .bind(network.hidden)
+        .bind(network.transition_disable)
+        .bind(network.portal.mode.as_str())
+        .bind(&network.portal.url_template)
+        .bind(network.low_stock_threshold)
+        .execute(&self.pool)
+        .await?;
 
-        let rows = sqlx::query(&query)
-            .bind(network_id)
+        Ok(())
+    }
+
+    pub async fn get_network(&self, id: &str) -> Result<Option<WiFiNetwork>> {
+        let row = sqlx::query(&format!("{} WHERE id = ?1", NETWORK_SELECT))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| self.network_from_row(&row)).transpose()
+    }
+
+    pub async fn get_all_networks(&self) -> Result<Vec<WiFiNetwork>> {
+        let rows = sqlx::query(&format!("{} ORDER BY created_at ASC", NETWORK_SELECT))
             .fetch_all(&self.pool)
             .await?;
 
-        let mut vouchers = Vec::new();
-        for row in rows {
-            vouchers.push(Voucher {
-                id: row.get("id"),
-                code: row.get("code"),
-                network_id: row.get("network_id"),
-                created_at: chrono::DateTime::parse_from_rfc3339(
-                    &row.get::<String, _>("created_at"),
-                )?
+        rows.iter().map(|row| self.network_from_row(row)).collect()
+    }
+
+    fn network_from_row(&self, row: &sqlx::sqlite::SqliteRow) -> Result<WiFiNetwork> {
+        Ok(WiFiNetwork {
+            id: row.get("id"),
+            name: row.get("name"),
+            ssid: row.get("ssid"),
+            password: self.cipher.decrypt(&row.get::<String, _>("password"))?,
+            description: row.get("description"),
+            created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
                 .with_timezone(&chrono::Utc),
-                is_used: row.get("is_used"),
-                used_at: row
-                    .get::<Option<String>, _>("used_at")
-                    .map(|s| {
-                        chrono::DateTime::parse_from_rfc3339(&s)
-                            .map(|dt| dt.with_timezone(&chrono::Utc))
-                    })
-                    .transpose()?,
-                // Fields is_printed and printed_at removed as they're now combined with is_used/used_at
-            });
-        }
+            is_active: row.get("is_active"),
+            layout: row.get("layout"),
+            branding: Branding {
+                heading: row.get("brand_heading"),
+                accent_color: row.get("brand_accent_color"),
+                background_color: row.get("brand_background_color"),
+                footer: row.get("brand_footer"),
+                contact: row.get("brand_contact"),
+                has_logo: row.get("has_logo"),
+            },
+            qr_options: row
+                .get::<Option<String>, _>("qr_options")
+                .map(|json| serde_json::from_str(&json))
+                .transpose()
+                .context("Invalid QR options stored for network")?,
+            security: row.get::<String, _>("security").parse()?,
+            hidden: row.get("hidden"),
+            transition_disable: row.get("transition_disable"),
+            portal: PortalLogin {
+                mode: row.get::<String, _>("portal_qr_mode").parse()?,
+                url_template: row.get("portal_url"),
+            },
+            low_stock_threshold: row.get("low_stock_threshold"),
+        })
+    }
 
-        Ok(vouchers)
+    /// Overwrite a network's editable fields
+    pub async fn update_network(&self, network: &WiFiNetwork) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wifi_networks
+            SET name = ?1, ssid = ?2, password = ?3, description = ?4, is_active = ?5, layout = ?6,
+                brand_heading = ?7, brand_accent_color = ?8, brand_background_color = ?9,
+                brand_footer = ?10, brand_contact = ?11, qr_options = ?12, security = ?13,
+                hidden = ?14, transition_disable = ?15, portal_qr_mode = ?16, portal_url = ?17,
+                low_stock_threshold = ?18
+            WHERE id = ?19
+            "#,
+        )
+        .bind(&network.name)
+        .bind(&network.ssid)
+        .bind(self.cipher.encrypt(&network.password)?)
+        .bind(&network.description)
+        .bind(network.is_active)
+        .bind(&network.layout)
+        .bind(&network.branding.heading)
+        .bind(&network.branding.accent_color)
+        .bind(&network.branding.background_color)
+        .bind(&network.branding.footer)
+        .bind(&network.branding.contact)
+        .bind(qr_options_json(network)?)
+        .bind(network.security.as_str())
+        .bind(network.hidden)
+        .bind(network.transition_disable)
+        .bind(network.portal.mode.as_str())
+        .bind(&network.portal.url_template)
+        .bind(network.low_stock_threshold)
+        .bind(&network.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn get_voucher_counts(&self, network_id: &str) -> Result<VoucherCounts> {
-        let row = sqlx::query(
+    /// Record that a network is low on vouchers. Returns false if an alert was
+    /// already raised and not cleared since, so each shortage is notified once.
+    pub async fn raise_stock_alert(&self, network_id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO stock_alerts (network_id, raised_at) VALUES (?1, ?2) \
+             ON CONFLICT (network_id) DO NOTHING",
+        )
+        .bind(network_id)
+        .bind(timestamp(chrono::Utc::now()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// When a network's current low-stock alert was raised, if it has one
+    pub async fn get_stock_alert(
+        &self,
+        network_id: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let raised_at: Option<String> =
+            sqlx::query_scalar("SELECT raised_at FROM stock_alerts WHERE network_id = ?1")
+                .bind(network_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        raised_at
+            .map(|raised_at| {
+                Ok(chrono::DateTime::parse_from_rfc3339(&raised_at)?.with_timezone(&chrono::Utc))
+            })
+            .transpose()
+    }
+
+    /// Clear a network's low-stock alert once it is restocked or the threshold
+    /// is lowered, so the next shortage is notified again
+    pub async fn clear_stock_alert(&self, network_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM stock_alerts WHERE network_id = ?1")
+            .bind(network_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Store or replace a network's logo
+    pub async fn save_logo(&self, network_id: &str, logo: &Logo) -> Result<()> {
+        sqlx::query(
             r#"
-            SELECT
-                COUNT(*) as total,
-                COUNT(CASE WHEN is_used = TRUE THEN 1 END) as used,
-                COUNT(CASE WHEN is_used = FALSE THEN 1 END) as unused
-            FROM vouchers
-            WHERE network_id = ?1
+            INSERT INTO network_logos (network_id, content_type, data, updated_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (network_id) DO UPDATE SET
+                content_type = excluded.content_type,
+                data = excluded.data,
+                updated_at = excluded.updated_at
             "#,
         )
         .bind(network_id)
-        .fetch_one(&self.pool)
+        .bind(&logo.content_type)
+        .bind(&logo.data)
+        .bind(timestamp(chrono::Utc::now()))
+        .execute(&self.pool)
         .await?;
 
-        Ok(VoucherCounts {
-            total: row.get::<i64, _>("total") as usize,
-            used: row.get::<i64, _>("used") as usize,
-            unused: row.get::<i64, _>("unused") as usize,
-        })
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub struct VoucherCounts {
-    pub total: usize,
-    pub used: usize,
-    pub unused: usize,
+    pub async fn get_logo(&self, network_id: &str) -> Result<Option<Logo>> {
+        let row = sqlx::query("SELECT content_type, data FROM network_logos WHERE network_id = ?1")
+            .bind(network_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| Logo {
+            content_type: row.get("content_type"),
+            data: row.get("data"),
+        }))
+    }
+
+    pub async fn delete_logo(&self, network_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM network_logos WHERE network_id = ?1")
+            .bind(network_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_network(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM wifi_networks WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn create_vouchers(&self, vouchers: &[Voucher]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for voucher in vouchers {
+            bind_voucher(sqlx::query(INSERT_VOUCHER), voucher, None)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Generate `count` new random vouchers for a network in one transaction.
+    /// Codes that collide with existing ones are skipped and drawn again.
+    pub async fn generate_vouchers(
+        &self,
+        network_id: &str,
+        format: &CodeFormat,
+        count: usize,
+        validity: &Validity,
+    ) -> Result<Vec<Voucher>> {
+        code_generator::check_batch(format, count)?;
+
+        let insert = format!("{} ON CONFLICT(code) DO NOTHING", INSERT_VOUCHER);
+        let mut tx = self.pool.begin().await?;
+        let mut vouchers = Vec::with_capacity(count);
+        let mut attempts = 0;
+
+        while vouchers.len() < count {
+            attempts += 1;
+            if attempts > count * 10 {
+                return Err(CodeGeneratorError::Exhausted.into());
+            }
+
+            let mut voucher = Voucher::new(format.generate());
+            voucher.network_id = Some(network_id.to_string());
+            voucher.validity = validity.clone();
+
+            let result = bind_voucher(sqlx::query(&insert), &voucher, None)
+                .execute(&mut *tx)
+                .await?;
+
+            if result.rows_affected() == 1 {
+                vouchers.push(voucher);
+            }
+        }
+
+        tx.commit().await?;
+        Ok(vouchers)
+    }
+
+    // pfSense voucher roll operations
+    pub async fn save_pfsense_settings(&self, settings: &PfSenseSettings) -> Result<()> {
+        let private_key = match &settings.private_key {
+            Some(key) => Some(self.cipher.encrypt(key)?),
+            None => None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO pfsense_settings (network_id, roll_bits, ticket_bits, checksum_bits, magic, charset, public_key, private_key, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT(network_id) DO UPDATE SET
+                roll_bits = excluded.roll_bits,
+                ticket_bits = excluded.ticket_bits,
+                checksum_bits = excluded.checksum_bits,
+                magic = excluded.magic,
+                charset = excluded.charset,
+                public_key = excluded.public_key,
+                private_key = excluded.private_key,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&settings.network_id)
+        .bind(settings.roll.roll_bits)
+        .bind(settings.roll.ticket_bits)
+        .bind(settings.roll.checksum_bits)
+        .bind(settings.roll.magic as i64)
+        .bind(&settings.roll.charset)
+        .bind(&settings.public_key)
+        .bind(private_key)
+        .bind(timestamp(settings.updated_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_pfsense_settings(&self, network_id: &str) -> Result<Option<PfSenseSettings>> {
+        let row = sqlx::query(
+            r#"
+            SELECT network_id, roll_bits, ticket_bits, checksum_bits, magic, charset, public_key, private_key, updated_at
+            FROM pfsense_settings WHERE network_id = ?1
+            "#,
+        )
+        .bind(network_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let private_key = match row.get::<Option<String>, _>("private_key") {
+            Some(key) => Some(self.cipher.decrypt(&key)?),
+            None => None,
+        };
+
+        Ok(Some(PfSenseSettings {
+            network_id: row.get("network_id"),
+            roll: RollSettings {
+                roll_bits: row.get("roll_bits"),
+                ticket_bits: row.get("ticket_bits"),
+                checksum_bits: row.get("checksum_bits"),
+                magic: row.get::<i64, _>("magic") as u64,
+                charset: row.get("charset"),
+            },
+            public_key: row.get("public_key"),
+            private_key,
+            updated_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("updated_at"))?
+                .with_timezone(&chrono::Utc),
+        }))
+    }
+
+    /// Store a roll together with its vouchers. Fails without storing anything if the
+    /// roll number is already used on the network or a code already exists.
+    pub async fn create_roll(&self, roll: &VoucherRoll, codes: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO voucher_rolls (id, network_id, roll_number, ticket_count, minutes, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&roll.id)
+        .bind(&roll.network_id)
+        .bind(roll.roll_number as i64)
+        .bind(roll.ticket_count as i64)
+        .bind(roll.minutes)
+        .bind(timestamp(roll.created_at))
+        .execute(&mut *tx)
+        .await
+        .with_context(|| format!("Roll {} already exists for this network", roll.roll_number))?;
+
+        for code in codes {
+            let mut voucher = Voucher::new(code.clone());
+            voucher.network_id = Some(roll.network_id.clone());
+            // pfSense vouchers grant the roll's minutes
+            voucher.validity.duration_minutes = Some(roll.minutes);
+
+            bind_voucher(sqlx::query(INSERT_VOUCHER), &voucher, Some(&roll.id))
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Voucher code {} already exists", code))?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_rolls_for_network(&self, network_id: &str) -> Result<Vec<VoucherRoll>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, network_id, roll_number, ticket_count, minutes, created_at
+            FROM voucher_rolls WHERE network_id = ?1 ORDER BY roll_number ASC
+            "#,
+        )
+        .bind(network_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut rolls = Vec::new();
+        for row in rows {
+            rolls.push(VoucherRoll {
+                id: row.get("id"),
+                network_id: row.get("network_id"),
+                roll_number: row.get::<i64, _>("roll_number") as u64,
+                ticket_count: row.get::<i64, _>("ticket_count") as u64,
+                minutes: row.get("minutes"),
+                created_at: chrono::DateTime::parse_from_rfc3339(
+                    &row.get::<String, _>("created_at"),
+                )?
+                .with_timezone(&chrono::Utc),
+            });
+        }
+
+        Ok(rolls)
+    }
+
+    pub async fn get_voucher(&self, id: &str) -> Result<Option<Voucher>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM vouchers WHERE id = ?1",
+            VOUCHER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| voucher_from_row(&row)).transpose()
+    }
+
+    /// Issue up to `count` of a network's oldest available vouchers and return them.
+    /// A single statement, so concurrent callers never receive the same voucher.
+    pub async fn allocate_vouchers(
+        &self,
+        network_id: &str,
+        count: usize,
+        issued_by: &str,
+    ) -> Result<Vec<Voucher>> {
+        self.expire_lapsed().await?;
+        let rows = sqlx::query(&format!(
+            r#"
+            UPDATE vouchers SET {}
+            WHERE id IN (
+                SELECT id FROM vouchers
+                WHERE network_id = ?3 AND state = 'available'
+                    AND (expires_at IS NULL OR expires_at > ?1)
+                ORDER BY created_at ASC LIMIT ?4
+            )
+            RETURNING {}
+            "#,
+            set_state(VoucherState::Issued),
+            VOUCHER_COLUMNS
+        ))
+        .bind(timestamp(chrono::Utc::now()))
+        .bind(issued_by)
+        .bind(network_id)
+        .bind(count as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut vouchers = rows
+            .iter()
+            .map(voucher_from_row)
+            .collect::<Result<Vec<_>>>()?;
+        vouchers.sort_by_key(|voucher| voucher.created_at);
+        Ok(vouchers)
+    }
+
+    /// Find a network's voucher by its exact code
+    pub async fn find_voucher_by_code(
+        &self,
+        network_id: &str,
+        code: &str,
+    ) -> Result<Option<Voucher>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM vouchers WHERE network_id = ?1 AND code = ?2",
+            VOUCHER_COLUMNS
+        ))
+        .bind(network_id)
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| voucher_from_row(&row)).transpose()
+    }
+
+    /// Find a voucher by its exact code in any network; codes are unique across networks
+    pub async fn get_voucher_by_code(&self, code: &str) -> Result<Option<Voucher>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM vouchers WHERE code = ?1",
+            VOUCHER_COLUMNS
+        ))
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|row| voucher_from_row(&row)).transpose()
+    }
+
+    pub async fn get_all_vouchers(&self) -> Result<Vec<Voucher>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM vouchers ORDER BY created_at ASC",
+            VOUCHER_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(voucher_from_row).collect()
+    }
+
+    pub async fn get_vouchers_for_network(&self, network_id: &str) -> Result<Vec<Voucher>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM vouchers WHERE network_id = ?1 ORDER BY created_at ASC",
+            VOUCHER_COLUMNS
+        ))
+        .bind(network_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(voucher_from_row).collect()
+    }
+
+    /// Move a voucher to `state` if its current state allows it, recording who did it.
+    /// Returns `None` for an unknown voucher; a change the lifecycle does not allow
+    /// fails with a [`VoucherStateError`].
+    pub async fn set_voucher_state(
+        &self,
+        id: &str,
+        state: VoucherState,
+        changed_by: &str,
+    ) -> Result<Option<Voucher>> {
+        self.expire_lapsed().await?;
+        let Some(voucher) = self.get_voucher(id).await? else {
+            return Ok(None);
+        };
+        if voucher.state == state {
+            return Ok(Some(voucher));
+        }
+        if let Some(automatic) = [voucher.state, state]
+            .into_iter()
+            .find(|s| s.is_automatic())
+        {
+            return Err(VoucherStateError::Automatic(automatic).into());
+        }
+        if !voucher.state.can_become(state) {
+            return Err(VoucherStateError::Transition(voucher.state, state).into());
+        }
+
+        // Only from the state checked above, in case it changed in the meantime
+        let row = sqlx::query(&format!(
+            "UPDATE vouchers SET {} WHERE id = ?3 AND state = ?4 RETURNING {}",
+            set_state(state),
+            VOUCHER_COLUMNS
+        ))
+        .bind(timestamp(chrono::Utc::now()))
+        .bind(changed_by)
+        .bind(id)
+        .bind(voucher.state.as_str())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(VoucherStateError::Conflict)?;
+
+        voucher_from_row(&row).map(Some)
+    }
+
+    /// Log a device in with a network's voucher code. The first login redeems the
+    /// code for that device; the same device may log in again until its time is
+    /// up. Refusals fail with a [`RedeemError`].
+    pub async fn redeem_voucher(
+        &self,
+        network_id: &str,
+        code: &str,
+        device: &Device,
+        redeemed_by: &str,
+    ) -> Result<Redemption> {
+        self.expire_lapsed().await?;
+        let now = chrono::Utc::now();
+
+        // Checking and redeeming in one statement, so two devices racing with
+        // the same code cannot both get in. Only codes that were printed or
+        // handed out work; a code still in the pool should not be known to anyone.
+        let row = sqlx::query(&format!(
+            r#"
+            UPDATE vouchers SET {}, redeemed_at = ?1, client_mac = ?5, client_ip = ?6
+            WHERE network_id = ?3 AND code = ?4
+                AND state IN ('printed', 'issued')
+                AND (expires_at IS NULL OR expires_at > ?1)
+            RETURNING {}
+            "#,
+            set_state(VoucherState::Redeemed),
+            VOUCHER_COLUMNS
+        ))
+        .bind(timestamp(now))
+        .bind(redeemed_by)
+        .bind(network_id)
+        .bind(code)
+        .bind(&device.mac)
+        .bind(device.ip.map(|ip| ip.to_string()))
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(row) = row {
+            return Ok(Redemption::new(&voucher_from_row(&row)?, true, now));
+        }
+
+        // Work out why not
+        let voucher = self
+            .find_voucher_by_code(network_id, code)
+            .await?
+            .ok_or(RedeemError::UnknownCode)?;
+        let error = match voucher.state {
+            VoucherState::Redeemed if device.redeemed(&voucher) => {
+                let redemption = Redemption::new(&voucher, false, now);
+                if !redemption.is_over() {
+                    return Ok(redemption);
+                }
+                RedeemError::UsedUp
+            }
+            VoucherState::Redeemed => RedeemError::AlreadyRedeemed,
+            VoucherState::Available | VoucherState::Reserved => RedeemError::NotIssued,
+            VoucherState::Revoked => RedeemError::Revoked,
+            VoucherState::Printed | VoucherState::Issued | VoucherState::Expired => {
+                RedeemError::Expired
+            }
+        };
+        Err(error.into())
+    }
+
+    /// Store an accounting update. Updates for a known session keep its start,
+    /// and its counters only move forward when updates arrive out of order.
+    pub async fn record_voucher_session(&self, session: &VoucherSession) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO voucher_sessions (voucher_id, session_id, nas, client_mac, client_ip,
+                started_at, stopped_at, session_seconds, input_bytes, output_bytes)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT (nas, session_id) DO UPDATE SET
+                client_ip = COALESCE(excluded.client_ip, client_ip),
+                stopped_at = COALESCE(excluded.stopped_at, stopped_at),
+                session_seconds = MAX(excluded.session_seconds, session_seconds),
+                input_bytes = MAX(excluded.input_bytes, input_bytes),
+                output_bytes = MAX(excluded.output_bytes, output_bytes)
+            "#,
+        )
+        .bind(&session.voucher_id)
+        .bind(&session.session_id)
+        .bind(&session.nas)
+        .bind(&session.client_mac)
+        .bind(&session.client_ip)
+        .bind(timestamp(session.started_at))
+        .bind(session.stopped_at.map(timestamp))
+        .bind(session.session_seconds)
+        // SQLite integers are signed
+        .bind(session.input_bytes as i64)
+        .bind(session.output_bytes as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// A voucher's accounting sessions, oldest first
+    pub async fn get_voucher_sessions(&self, voucher_id: &str) -> Result<Vec<VoucherSession>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT voucher_id, session_id, nas, client_mac, client_ip, started_at, stopped_at,
+                session_seconds, input_bytes, output_bytes
+            FROM voucher_sessions WHERE voucher_id = ?1 ORDER BY started_at, id
+            "#,
+        )
+        .bind(voucher_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(VoucherSession {
+                    voucher_id: row.get("voucher_id"),
+                    session_id: row.get("session_id"),
+                    nas: row.get("nas"),
+                    client_mac: row.get("client_mac"),
+                    client_ip: row.get("client_ip"),
+                    started_at: chrono::DateTime::parse_from_rfc3339(
+                        &row.get::<String, _>("started_at"),
+                    )?
+                    .with_timezone(&chrono::Utc),
+                    stopped_at: row
+                        .get::<Option<String>, _>("stopped_at")
+                        .map(|s| {
+                            chrono::DateTime::parse_from_rfc3339(&s)
+                                .map(|dt| dt.with_timezone(&chrono::Utc))
+                        })
+                        .transpose()?,
+                    session_seconds: row.get("session_seconds"),
+                    input_bytes: row.get::<i64, _>("input_bytes") as u64,
+                    output_bytes: row.get::<i64, _>("output_bytes") as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// A voucher's state changes, oldest first
+    pub async fn get_voucher_transitions(&self, id: &str) -> Result<Vec<VoucherTransition>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT from_state, to_state, changed_at, changed_by
+            FROM voucher_transitions WHERE voucher_id = ?1 ORDER BY id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(VoucherTransition {
+                    from: row.get::<String, _>("from_state").parse()?,
+                    to: row.get::<String, _>("to_state").parse()?,
+                    changed_at: chrono::DateTime::parse_from_rfc3339(
+                        &row.get::<String, _>("changed_at"),
+                    )?
+                    .with_timezone(&chrono::Utc),
+                    changed_by: row.get("changed_by"),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn create_webhook(&self, webhook: &Webhook) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO webhooks (id, url, secret, events, is_active, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(&webhook.id)
+        .bind(&webhook.url)
+        .bind(self.cipher.encrypt(&webhook.secret)?)
+        .bind(events_column(&webhook.events))
+        .bind(webhook.is_active)
+        .bind(timestamp(webhook.created_at))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_webhook(&self, id: &str) -> Result<Option<Webhook>> {
+        let row = sqlx::query("SELECT * FROM webhooks WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| self.webhook_from_row(&row)).transpose()
+    }
+
+    pub async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let rows = sqlx::query("SELECT * FROM webhooks ORDER BY created_at ASC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(|row| self.webhook_from_row(row)).collect()
+    }
+
+    fn webhook_from_row(&self, row: &sqlx::sqlite::SqliteRow) -> Result<Webhook> {
+        Ok(Webhook {
+            id: row.get("id"),
+            url: row.get("url"),
+            secret: self.cipher.decrypt(&row.get::<String, _>("secret"))?,
+            events: row
+                .get::<String, _>("events")
+                .split(',')
+                .filter(|event| !event.is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            is_active: row.get("is_active"),
+            created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
+                .with_timezone(&chrono::Utc),
+        })
+    }
+
+    /// Pause or resume a webhook; deliveries for a paused webhook wait until it is resumed
+    pub async fn set_webhook_active(&self, id: &str, is_active: bool) -> Result<bool> {
+        let result = sqlx::query("UPDATE webhooks SET is_active = ?1 WHERE id = ?2")
+            .bind(is_active)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a webhook along with its deliveries
+    pub async fn delete_webhook(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn create_webhook_deliveries(&self, deliveries: &[WebhookDelivery]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for delivery in deliveries {
+            sqlx::query(
+                r#"
+                INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts,
+                    next_attempt_at, response_status, last_error, created_at, delivered_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                "#,
+            )
+            .bind(&delivery.id)
+            .bind(&delivery.webhook_id)
+            .bind(delivery.event.as_str())
+            .bind(&delivery.payload)
+            .bind(delivery.status.as_str())
+            .bind(delivery.attempts)
+            .bind(delivery.next_attempt_at.map(timestamp))
+            .bind(delivery.response_status)
+            .bind(&delivery.last_error)
+            .bind(timestamp(delivery.created_at))
+            .bind(delivery.delivered_at.map(timestamp))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Store the outcome of a delivery attempt, or a delivery queued again
+    pub async fn update_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = ?1, attempts = ?2, next_attempt_at = ?3, response_status = ?4,
+                last_error = ?5, delivered_at = ?6
+            WHERE id = ?7
+            "#,
+        )
+        .bind(delivery.status.as_str())
+        .bind(delivery.attempts)
+        .bind(delivery.next_attempt_at.map(timestamp))
+        .bind(delivery.response_status)
+        .bind(&delivery.last_error)
+        .bind(delivery.delivered_at.map(timestamp))
+        .bind(&delivery.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_webhook_delivery(&self, id: &str) -> Result<Option<WebhookDelivery>> {
+        let row = sqlx::query("SELECT * FROM webhook_deliveries WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        row.map(|row| delivery_from_row(&row)).transpose()
+    }
+
+    /// A webhook's most recent deliveries, newest first
+    pub async fn get_webhook_deliveries(
+        &self,
+        webhook_id: &str,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query(
+            "SELECT * FROM webhook_deliveries WHERE webhook_id = ?1 \
+             ORDER BY created_at DESC, rowid DESC LIMIT ?2",
+        )
+        .bind(webhook_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(delivery_from_row).collect()
+    }
+
+    /// Pending deliveries of active webhooks whose next attempt is due, oldest first
+    pub async fn get_due_webhook_deliveries(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT d.* FROM webhook_deliveries d
+            JOIN webhooks w ON w.id = d.webhook_id
+            WHERE d.status = 'pending' AND d.next_attempt_at <= ?1 AND w.is_active
+            ORDER BY d.next_attempt_at, d.rowid
+            LIMIT ?2
+            "#,
+        )
+        .bind(timestamp(now))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(delivery_from_row).collect()
+    }
+
+    /// Store a reserved print job and hold up to `count` of the network's oldest
+    /// unused vouchers for it. Returns the held vouchers in print order; when
+    /// none are left, nothing is stored and the list is empty.
+    pub async fn reserve_print_job(&self, job: &PrintJob, count: usize) -> Result<Vec<Voucher>> {
+        self.expire_lapsed().await?;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO print_jobs (id, network_id, printed_by, created_at, layout, output, status, expires_at, settings)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+        )
+        .bind(&job.id)
+        .bind(&job.network_id)
+        .bind(&job.printed_by)
+        .bind(timestamp(job.created_at))
+        .bind(&job.layout)
+        .bind(job.output.as_str())
+        .bind(job.status.as_str())
+        .bind(job.expires_at.map(timestamp))
+        .bind(job.settings.as_ref().map(serde_json::to_string).transpose()?)
+        .execute(&mut *tx)
+        .await?;
+
+        // Picking and holding the vouchers in one statement inside the transaction
+        // keeps concurrent jobs from getting the same vouchers
+        let held = sqlx::query(
+            r#"
+            INSERT INTO print_job_vouchers (job_id, position, voucher_id)
+            SELECT ?1, ROW_NUMBER() OVER (ORDER BY created_at ASC) - 1, id FROM (
+                SELECT id, created_at FROM vouchers
+                WHERE network_id = ?2 AND state = 'available'
+                    AND (expires_at IS NULL OR expires_at > ?4)
+                ORDER BY created_at ASC LIMIT ?3
+            )
+            "#,
+        )
+        .bind(&job.id)
+        .bind(&job.network_id)
+        .bind(count as i64)
+        .bind(timestamp(job.created_at))
+        .execute(&mut *tx)
+        .await?;
+
+        if held.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(Vec::new());
+        }
+        sqlx::query(&format!(
+            "UPDATE vouchers SET {} WHERE id IN (SELECT voucher_id FROM print_job_vouchers WHERE job_id = ?3)",
+            set_state(VoucherState::Reserved)
+        ))
+        .bind(timestamp(job.created_at))
+        .bind(&job.printed_by)
+        .bind(&job.id)
+        .execute(&mut *tx)
+        .await?;
+        // Copy the logo, so replacing or removing it later doesn't change reprints
+        sqlx::query(
+            r#"
+            INSERT INTO print_job_logos (job_id, content_type, data)
+            SELECT ?1, content_type, data FROM network_logos WHERE network_id = ?2
+            "#,
+        )
+        .bind(&job.id)
+        .bind(&job.network_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.get_print_job_vouchers(&job.id).await
+    }
+
+    /// The logo a print job was created with, if its network had one then
+    pub async fn get_print_job_logo(&self, job_id: &str) -> Result<Option<Logo>> {
+        let row = sqlx::query("SELECT content_type, data FROM print_job_logos WHERE job_id = ?1")
+            .bind(job_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| Logo {
+            content_type: row.get("content_type"),
+            data: row.get("data"),
+        }))
+    }
+
+    /// Mark a reserved job's vouchers as printed. Returns false if the job is no
+    /// longer reserved, for example because its reservation ran out.
+    pub async fn confirm_print_job(&self, id: &str, confirmed_by: &str) -> Result<bool> {
+        self.expire_lapsed().await?;
+        self.end_print_job(id, "confirmed", VoucherState::Printed, confirmed_by)
+            .await
+    }
+
+    /// Give a reserved job's vouchers back to the unused pool
+    pub async fn release_print_job(&self, id: &str, released_by: &str) -> Result<bool> {
+        self.end_print_job(id, "released", VoucherState::Available, released_by)
+            .await
+    }
+
+    /// Move a reserved job to `status` and its vouchers to `state`
+    async fn end_print_job(
+        &self,
+        id: &str,
+        status: &str,
+        state: VoucherState,
+        changed_by: &str,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE print_jobs SET status = ?1, expires_at = NULL WHERE id = ?2 AND status = 'reserved'",
+        )
+        .bind(status)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+
+        sqlx::query(&format!(
+            r#"
+            UPDATE vouchers SET {}
+            WHERE state = 'reserved' AND id IN (SELECT voucher_id FROM print_job_vouchers WHERE job_id = ?3)
+            "#,
+            set_state(state)
+        ))
+        .bind(timestamp(chrono::Utc::now()))
+        .bind(changed_by)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// End reservations that ran out, returning their vouchers to the pool, then
+    /// mark vouchers no guest redeemed before their expiry date as expired,
+    /// whether still in the pool or already printed or issued. Run periodically by
+    /// [`start_expiry_sweep`], and before writes that pick or change vouchers.
+    pub async fn expire_lapsed(&self) -> Result<()> {
+        let now = timestamp(chrono::Utc::now());
+        let no_user: Option<&str> = None;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(&format!(
+            r#"
+            UPDATE vouchers SET {}
+            WHERE state = 'reserved' AND id IN (
+                SELECT j.voucher_id FROM print_job_vouchers j JOIN print_jobs p ON p.id = j.job_id
+                WHERE p.status = 'reserved' AND p.expires_at <= ?1
+            )
+            "#,
+            set_state(VoucherState::Available)
+        ))
+        .bind(&now)
+        .bind(no_user)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "UPDATE print_jobs SET status = 'expired' WHERE status = 'reserved' AND expires_at <= ?1",
+        )
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "UPDATE vouchers SET {} WHERE {}",
+            set_state(VoucherState::Expired),
+            LAPSED
+        ))
+        .bind(&now)
+        .bind(no_user)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// A network's most recent print jobs, newest first
+    pub async fn get_print_jobs_for_network(
+        &self,
+        network_id: &str,
+        limit: usize,
+    ) -> Result<Vec<PrintJob>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM print_jobs WHERE network_id = ?1 ORDER BY created_at DESC LIMIT ?2",
+            PRINT_JOB_COLUMNS
+        ))
+        .bind(network_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut jobs = Vec::with_capacity(rows.len());
+        for row in rows {
+            jobs.push(self.print_job_from_row(&row).await?);
+        }
+        Ok(jobs)
+    }
+
+    pub async fn get_print_job(&self, id: &str) -> Result<Option<PrintJob>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM print_jobs WHERE id = ?1",
+            PRINT_JOB_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.print_job_from_row(&row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// A print job's vouchers in print order
+    async fn get_print_job_vouchers(&self, job_id: &str) -> Result<Vec<Voucher>> {
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT {}
+            FROM print_job_vouchers j JOIN vouchers v ON v.id = j.voucher_id
+            WHERE j.job_id = ?1 ORDER BY j.position
+            "#,
+            VOUCHER_COLUMNS
+        ))
+        .bind(job_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(voucher_from_row).collect()
+    }
+
+    /// Build a print job from its row, loading its vouchers in print order
+    async fn print_job_from_row(&self, row: &sqlx::sqlite::SqliteRow) -> Result<PrintJob> {
+        let id: String = row.get("id");
+        let vouchers = self.get_print_job_vouchers(&id).await?;
+
+        Ok(PrintJob {
+            id,
+            network_id: row.get("network_id"),
+            printed_by: row.get("printed_by"),
+            created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
+                .with_timezone(&chrono::Utc),
+            layout: row.get("layout"),
+            output: row
+                .get::<String, _>("output")
+                .parse()
+                .map_err(anyhow::Error::msg)?,
+            status: row
+                .get::<String, _>("status")
+                .parse()
+                .map_err(anyhow::Error::msg)?,
+            expires_at: row
+                .get::<Option<String>, _>("expires_at")
+                .map(|s| {
+                    chrono::DateTime::parse_from_rfc3339(&s)
+                        .map(|dt| dt.with_timezone(&chrono::Utc))
+                })
+                .transpose()?,
+            settings: row
+                .get::<Option<String>, _>("settings")
+                .map(|json| serde_json::from_str(&json))
+                .transpose()?,
+            vouchers,
+        })
+    }
+
+    /// Count a network's vouchers by state. Lapsed vouchers count as expired even
+    /// before the sweep marks them, so they never show up as stock.
+    pub async fn get_voucher_counts(&self, network_id: &str) -> Result<VoucherCounts> {
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT CASE WHEN {} THEN 'expired' ELSE state END AS counted_state, COUNT(*) AS count
+            FROM vouchers WHERE network_id = ?2 GROUP BY counted_state
+            "#,
+            LAPSED
+        ))
+        .bind(timestamp(chrono::Utc::now()))
+        .bind(network_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut counts = VoucherCounts::default();
+        for row in rows {
+            let count = row.get::<i64, _>("count") as usize;
+            counts.total += count;
+            *counts.get_mut(row.get::<String, _>("counted_state").parse()?) = count;
+        }
+        Ok(counts)
+    }
+
+    /// Delete a network's vouchers that expired before a guest redeemed them
+    pub async fn delete_expired_vouchers(&self, network_id: &str) -> Result<u64> {
+        self.expire_lapsed().await?;
+        let mut tx = self.pool.begin().await?;
+        let condition = "network_id = ?1 AND state = 'expired'";
+
+        // Past print jobs keep their other vouchers
+        sqlx::query(&format!(
+            "DELETE FROM print_job_vouchers WHERE voucher_id IN (SELECT id FROM vouchers WHERE {})",
+            condition
+        ))
+        .bind(network_id)
+        .execute(&mut *tx)
+        .await?;
+        let result = sqlx::query(&format!("DELETE FROM vouchers WHERE {}", condition))
+            .bind(network_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+fn user_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<User> {
+    Ok(User {
+        id: row.get("id"),
+        username: row.get("username"),
+        password_hash: row.get("password_hash"),
+        role: row.get::<String, _>("role").parse::<Role>()?,
+        created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
+            .with_timezone(&chrono::Utc),
+    })
+}
+
+/// A network's QR override as stored in the `qr_options` column
+fn qr_options_json(network: &WiFiNetwork) -> Result<Option<String>> {
+    Ok(network
+        .qr_options
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?)
+}
+
+fn voucher_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Voucher> {
+    Ok(Voucher {
+        id: row.get("id"),
+        code: row.get("code"),
+        network_id: row.get("network_id"),
+        created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
+            .with_timezone(&chrono::Utc),
+        is_used: row.get("is_used"),
+        used_at: row
+            .get::<Option<String>, _>("used_at")
+            .map(|s| {
+                chrono::DateTime::parse_from_rfc3339(&s).map(|dt| dt.with_timezone(&chrono::Utc))
+            })
+            .transpose()?,
+        state: row.get::<String, _>("state").parse()?,
+        state_changed_at: chrono::DateTime::parse_from_rfc3339(
+            &row.get::<String, _>("state_changed_at"),
+        )?
+        .with_timezone(&chrono::Utc),
+        validity: Validity {
+            duration_minutes: row.get("duration_minutes"),
+            expires_at: row
+                .get::<Option<String>, _>("expires_at")
+                .map(|s| {
+                    chrono::DateTime::parse_from_rfc3339(&s)
+                        .map(|dt| dt.with_timezone(&chrono::Utc))
+                })
+                .transpose()?,
+            // SQLite integers are signed
+            data_limit_mb: row
+                .get::<Option<i64>, _>("data_limit_mb")
+                .map(|mb| mb as u64),
+            download_kbps: row.get("download_kbps"),
+            upload_kbps: row.get("upload_kbps"),
+        },
+        redeemed_at: row
+            .get::<Option<String>, _>("redeemed_at")
+            .map(|s| {
+                chrono::DateTime::parse_from_rfc3339(&s).map(|dt| dt.with_timezone(&chrono::Utc))
+            })
+            .transpose()?,
+        client_mac: row.get("client_mac"),
+        client_ip: row.get("client_ip"),
+    })
+}
+
+/// Subscribed events as stored, comma separated; empty for all events
+fn events_column(events: &[WebhookEvent]) -> String {
+    events
+        .iter()
+        .map(WebhookEvent::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn delivery_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<WebhookDelivery> {
+    let time = |column: &str| {
+        row.get::<Option<String>, _>(column)
+            .map(|s| {
+                chrono::DateTime::parse_from_rfc3339(&s).map(|dt| dt.with_timezone(&chrono::Utc))
+            })
+            .transpose()
+    };
+    Ok(WebhookDelivery {
+        id: row.get("id"),
+        webhook_id: row.get("webhook_id"),
+        event: row.get::<String, _>("event").parse()?,
+        payload: row.get("payload"),
+        status: row.get::<String, _>("status").parse()?,
+        attempts: row.get("attempts"),
+        next_attempt_at: time("next_attempt_at")?,
+        response_status: row.get("response_status"),
+        last_error: row.get("last_error"),
+        created_at: chrono::DateTime::parse_from_rfc3339(&row.get::<String, _>("created_at"))?
+            .with_timezone(&chrono::Utc),
+        delivered_at: time("delivered_at")?,
+    })
+}
+
+/// Bind a voucher to the parameters of [`INSERT_VOUCHER`]
+fn bind_voucher<'q>(
+    query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    voucher: &'q Voucher,
+    roll_id: Option<&'q str>,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    let validity = &voucher.validity;
+    query
+        .bind(&voucher.id)
+        .bind(&voucher.code)
+        .bind(&voucher.network_id)
+        .bind(timestamp(voucher.created_at))
+        .bind(voucher.is_used)
+        .bind(voucher.used_at.map(timestamp))
+        .bind(validity.duration_minutes)
+        .bind(validity.expires_at.map(timestamp))
+        .bind(validity.data_limit_mb.map(|mb| mb as i64))
+        .bind(validity.download_kbps)
+        .bind(validity.upload_kbps)
+        .bind(roll_id)
+        .bind(voucher.state.as_str())
+        .bind(timestamp(voucher.state_changed_at))
+}
+
+/// Vouchers past their expiry date (bound as `?1`) that no guest redeemed
+const LAPSED: &str = "state IN ('available', 'printed', 'issued') AND expires_at <= ?1";
+
+/// How often lapsed reservations and vouchers are expired in the background
+const EXPIRY_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Expire lapsed reservations and vouchers now and then every minute, so reads
+/// never have to write and the counts stay current without any activity
+pub fn start_expiry_sweep(database: std::sync::Arc<Database>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(error) = database.expire_lapsed().await {
+                eprintln!("Expiring lapsed vouchers failed: {:#}", error);
+            }
+        }
+    });
+}
+
+/// Timestamps are stored as RFC 3339 in UTC with exactly three fractional digits,
+/// like SQLite's `strftime('%Y-%m-%dT%H:%M:%fZ')`, so SQL can compare them as strings
+fn timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// SET clause that moves vouchers to `state`, binding the time as ?1 and the
+/// user as ?2. The `voucher_state_transition` trigger logs each change.
+fn set_state(state: VoucherState) -> String {
+    format!(
+        "state = '{}', state_changed_at = ?1, state_changed_by = ?2, is_used = {}, used_at = {}",
+        state.as_str(),
+        if state.is_used() { "TRUE" } else { "FALSE" },
+        if state.is_used() {
+            "COALESCE(used_at, ?1)"
+        } else {
+            "NULL"
+        }
+    )
+}
+
+/// How many of a network's vouchers are in each state
+#[derive(Debug, Default)]
+pub struct VoucherCounts {
+    pub total: usize,
+    pub available: usize,
+    pub reserved: usize,
+    pub printed: usize,
+    pub issued: usize,
+    pub redeemed: usize,
+    pub revoked: usize,
+    pub expired: usize,
+}
+
+impl VoucherCounts {
+    pub fn get(&self, state: &VoucherState) -> usize {
+        match state {
+            VoucherState::Available => self.available,
+            VoucherState::Reserved => self.reserved,
+            VoucherState::Printed => self.printed,
+            VoucherState::Issued => self.issued,
+            VoucherState::Redeemed => self.redeemed,
+            VoucherState::Revoked => self.revoked,
+            VoucherState::Expired => self.expired,
+        }
+    }
+
+    fn get_mut(&mut self, state: VoucherState) -> &mut usize {
+        match state {
+            VoucherState::Available => &mut self.available,
+            VoucherState::Reserved => &mut self.reserved,
+            VoucherState::Printed => &mut self.printed,
+            VoucherState::Issued => &mut self.issued,
+            VoucherState::Redeemed => &mut self.redeemed,
+            VoucherState::Revoked => &mut self.revoked,
+            VoucherState::Expired => &mut self.expired,
+        }
+    }
+
+    /// Vouchers that left the pool, whatever happened to them since
+    pub fn used(&self) -> usize {
+        self.printed + self.issued + self.redeemed + self.revoked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::print_job::{PrintJobStatus, PrintOutput};
+    use crate::test_util;
+    use crate::wifi_network::SecurityType;
+
+    #[tokio::test]
+    async fn test_network_password_encrypted_at_rest() {
+        let database = test_util::database().await;
+        let network = WiFiNetwork {
+            password: "plain-password".to_string(),
+            ..test_util::lobby()
+        };
+        database.create_network(&network).await.unwrap();
+
+        let stored: String = sqlx::query("SELECT password FROM wifi_networks")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap()
+            .get("password");
+        assert!(PasswordCipher::is_encrypted(&stored));
+        assert!(!stored.contains("plain-password"));
+
+        let loaded = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(loaded.password, "plain-password");
+        assert_eq!(
+            database.get_all_networks().await.unwrap()[0].password,
+            "plain-password"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_vouchers_skips_existing_codes() {
+        let (database, network) = test_util::database_with_lobby().await;
+
+        // A small code space makes collisions with the first batch likely
+        let format = CodeFormat {
+            length: 4,
+            ..CodeFormat::default()
+        }
+        .with_alphabet(code_generator::NUMERIC_ALPHABET);
+        let first = database
+            .generate_vouchers(&network.id, &format, 50, &Validity::default())
+            .await
+            .unwrap();
+        let second = database
+            .generate_vouchers(&network.id, &format, 50, &Validity::default())
+            .await
+            .unwrap();
+
+        let mut codes: Vec<&String> = first.iter().chain(&second).map(|v| &v.code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), 100);
+        assert_eq!(
+            database
+                .get_voucher_counts(&network.id)
+                .await
+                .unwrap()
+                .available,
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_network_branding_and_logo() {
+        let (database, mut network) = test_util::database_with_lobby().await;
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.branding, Branding::default());
+
+        network.branding.heading = "Welcome".to_string();
+        network.branding.contact = Some("Dial 9".to_string());
+        network.qr_options = Some(crate::qr_generator::QrOptions {
+            quiet_zone: 4,
+            ..Default::default()
+        });
+        database.update_network(&network).await.unwrap();
+        let logo = Logo {
+            content_type: "image/png".to_string(),
+            data: vec![1, 2, 3],
+        };
+        database.save_logo(&network.id, &logo).await.unwrap();
+        database.save_logo(&network.id, &logo).await.unwrap();
+
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.branding.heading, "Welcome");
+        assert_eq!(stored.branding.contact.as_deref(), Some("Dial 9"));
+        assert_eq!(stored.qr_options, network.qr_options);
+        assert!(stored.branding.has_logo);
+        let stored_logo = database.get_logo(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored_logo.data, vec![1, 2, 3]);
+
+        assert!(database.delete_logo(&network.id).await.unwrap());
+        let stored = database.get_all_networks().await.unwrap();
+        assert!(!stored[0].branding.has_logo);
+        assert!(database.get_logo(&network.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_network_security() {
+        let database = test_util::database().await;
+        let mut network = WiFiNetwork {
+            password: String::new(),
+            ..test_util::lobby()
+        };
+        network.security = SecurityType::Open;
+        network.hidden = true;
+        database.create_network(&network).await.unwrap();
+
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.security, SecurityType::Open);
+        assert!(stored.hidden);
+        assert!(!stored.transition_disable);
+
+        network.security = SecurityType::Wpa3Sae;
+        network.password = "secret".to_string();
+        network.transition_disable = true;
+        database.update_network(&network).await.unwrap();
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.security, SecurityType::Wpa3Sae);
+        assert!(stored.transition_disable);
+        assert_eq!(
+            stored.qr_payload(),
+            "WIFI:T:SAE;S:Lobby-Guest;P:secret;H:true;R:1;;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_print_jobs() {
+        let (database, mut network) = test_util::database_with_lobby().await;
+        let vouchers: Vec<Voucher> = ["B", "A", "C"]
+            .iter()
+            .map(|code| {
+                let mut voucher = Voucher::new(code.to_string());
+                voucher.network_id = Some(network.id.clone());
+                voucher
+            })
+            .collect();
+        database.create_vouchers(&vouchers).await.unwrap();
+
+        let job = |printed_by: &str, output| {
+            PrintJob::reserve(
+                &network,
+                printed_by.to_string(),
+                "a4".to_string(),
+                output,
+                chrono::Duration::minutes(30),
+            )
+        };
+
+        let logo = |data: &[u8]| Logo {
+            content_type: "image/png".to_string(),
+            data: data.to_vec(),
+        };
+        database
+            .save_logo(&network.id, &logo(b"first logo"))
+            .await
+            .unwrap();
+
+        // Reserving holds the oldest vouchers without using them up
+        let first = job("admin", PrintOutput::Pdf);
+        let held = database.reserve_print_job(&first, 2).await.unwrap();
+        let codes: Vec<&str> = held.iter().map(|v| v.code.as_str()).collect();
+        assert_eq!(codes, vec!["B", "A"]);
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!(
+            (counts.used(), counts.reserved, counts.available),
+            (0, 2, 1)
+        );
+
+        // Other jobs and the API only get what is left
+        let allocated = database
+            .allocate_vouchers(&network.id, 5, "api")
+            .await
+            .unwrap();
+        assert_eq!(allocated.len(), 1);
+        assert_eq!(allocated[0].code, "C");
+        let empty = job("desk", PrintOutput::Html);
+        assert!(database
+            .reserve_print_job(&empty, 1)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(database.get_print_job(&empty.id).await.unwrap().is_none());
+
+        // Confirming uses the vouchers up, once
+        assert!(database.confirm_print_job(&first.id, "desk").await.unwrap());
+        assert!(!database.confirm_print_job(&first.id, "desk").await.unwrap());
+        let stored = database.get_print_job(&first.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, PrintJobStatus::Confirmed);
+        assert_eq!(stored.output, PrintOutput::Pdf);
+        assert!(stored.vouchers.iter().all(|v| v.is_used));
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!(
+            (counts.used(), counts.reserved, counts.available),
+            (3, 0, 0)
+        );
+
+        // A reprint after the network was edited looks like the original print
+        let original = network.clone();
+        network.layout = "label".to_string();
+        network.branding.heading = "New heading".to_string();
+        network.qr_options = Some(crate::qr_generator::QrOptions::default());
+        network.portal.url_template = Some("https://portal.test/?code={code}".to_string());
+        database.update_network(&network).await.unwrap();
+        let stored = database.get_print_job(&first.id).await.unwrap().unwrap();
+        let network = database.get_network(&network.id).await.unwrap().unwrap();
+        let printed = stored.network_as_printed(&network);
+        assert_eq!(stored.layout, "a4");
+        assert_eq!(printed.branding, original.branding);
+        assert_eq!(printed.qr_options, original.qr_options);
+        assert_eq!(printed.portal, original.portal);
+        // So is the logo, even once it has been replaced or removed
+        database
+            .save_logo(&network.id, &logo(b"second logo"))
+            .await
+            .unwrap();
+        database.delete_logo(&network.id).await.unwrap();
+        let kept = database
+            .get_print_job_logo(&first.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kept.data, b"first logo");
+        assert!(database
+            .get_print_job_logo(&empty.id)
+            .await
+            .unwrap()
+            .is_none());
+
+        database.delete_network(&network.id).await.unwrap();
+        assert!(database.get_print_job(&first.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_print_job_reservations_end() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let mut voucher = Voucher::new("A".to_string());
+        voucher.network_id = Some(network.id.clone());
+        database.create_vouchers(&[voucher]).await.unwrap();
+        let job = |reservation| {
+            PrintJob::reserve(
+                &network,
+                "desk".to_string(),
+                "a4".to_string(),
+                PrintOutput::Html,
+                reservation,
+            )
+        };
+
+        // Released vouchers can be reserved again
+        let released = job(chrono::Duration::minutes(30));
+        assert_eq!(
+            database
+                .reserve_print_job(&released, 1)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(database
+            .release_print_job(&released.id, "desk")
+            .await
+            .unwrap());
+        assert!(!database
+            .confirm_print_job(&released.id, "desk")
+            .await
+            .unwrap());
+
+        // So can vouchers of a reservation that ran out
+        let expired = job(chrono::Duration::zero());
+        assert_eq!(
+            database.reserve_print_job(&expired, 1).await.unwrap().len(),
+            1
+        );
+        // The background sweep does this every minute
+        database.expire_lapsed().await.unwrap();
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!(
+            (counts.used(), counts.reserved, counts.available),
+            (0, 0, 1)
+        );
+        assert!(!database
+            .confirm_print_job(&expired.id, "desk")
+            .await
+            .unwrap());
+        let stored = database.get_print_job(&expired.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, PrintJobStatus::Expired);
+
+        let jobs = database
+            .get_print_jobs_for_network(&network.id, 10)
+            .await
+            .unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].id, expired.id);
+        assert_eq!(jobs[1].status, PrintJobStatus::Released);
+    }
+
+    #[tokio::test]
+    async fn test_voucher_validity_and_expiry() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let voucher = |code: &str, expires_at: &str| {
+            let mut voucher = Voucher::new(code.to_string());
+            voucher.network_id = Some(network.id.clone());
+            voucher.validity = Validity {
+                duration_minutes: Some(1440),
+                expires_at: crate::voucher::parse_expiry(expires_at),
+                data_limit_mb: Some(5_000_000),
+                download_kbps: Some(10_000),
+                upload_kbps: None,
+            };
+            voucher
+        };
+        let (old, current) = (voucher("OLD", "2020-01-01"), voucher("NEW", "2999-12-31"));
+        database
+            .create_vouchers(&[old.clone(), current.clone()])
+            .await
+            .unwrap();
+
+        let stored = database
+            .get_vouchers_for_network(&network.id)
+            .await
+            .unwrap();
+        assert_eq!(stored[1].validity, current.validity);
+
+        // Expired vouchers are neither printed nor handed out
+        database.expire_lapsed().await.unwrap();
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!((counts.available, counts.expired), (1, 1));
+        let job = PrintJob::reserve(
+            &network,
+            "desk".to_string(),
+            "a4".to_string(),
+            PrintOutput::Html,
+            chrono::Duration::minutes(30),
+        );
+        let held = database.reserve_print_job(&job, 5).await.unwrap();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].code, "NEW");
+        assert!(database
+            .allocate_vouchers(&network.id, 5, "api")
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            database.delete_expired_vouchers(&network.id).await.unwrap(),
+            1
+        );
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!((counts.total, counts.reserved, counts.expired), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_handed_out_vouchers_expire() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let vouchers: Vec<Voucher> = ["POOL", "PRINTED", "ISSUED", "REDEEMED"]
+            .iter()
+            .map(|code| Voucher {
+                network_id: Some(network.id.clone()),
+                ..Voucher::new(code.to_string())
+            })
+            .collect();
+        database.create_vouchers(&vouchers).await.unwrap();
+        for state in ["printed", "issued", "redeemed"] {
+            sqlx::query("UPDATE vouchers SET state = ?1 WHERE code = upper(?1)")
+                .bind(state)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE vouchers SET expires_at = ?1")
+            .bind(timestamp(chrono::Utc::now() - chrono::Duration::minutes(1)))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+
+        // Counted as expired before the sweep gets to them
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!((counts.available, counts.printed, counts.issued), (0, 0, 0));
+        assert_eq!((counts.expired, counts.redeemed), (3, 1));
+
+        database.expire_lapsed().await.unwrap();
+        let states: Vec<VoucherState> = database
+            .get_vouchers_for_network(&network.id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|voucher| voucher.state)
+            .collect();
+        assert_eq!(
+            states,
+            [
+                VoucherState::Expired,
+                VoucherState::Expired,
+                VoucherState::Expired,
+                VoucherState::Redeemed
+            ]
+        );
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!((counts.expired, counts.redeemed), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn test_voucher_lifecycle() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let vouchers: Vec<Voucher> = ["A", "B", "C"]
+            .iter()
+            .map(|code| {
+                let mut voucher = Voucher::new(code.to_string());
+                voucher.network_id = Some(network.id.clone());
+                voucher
+            })
+            .collect();
+        database.create_vouchers(&vouchers).await.unwrap();
+
+        // A goes through a print job, B is handed out through the API
+        let job = PrintJob::reserve(
+            &network,
+            "desk".to_string(),
+            "a4".to_string(),
+            PrintOutput::Html,
+            chrono::Duration::minutes(30),
+        );
+        let held = database.reserve_print_job(&job, 1).await.unwrap();
+        assert_eq!(held[0].state, VoucherState::Reserved);
+        assert!(database
+            .confirm_print_job(&job.id, "manager")
+            .await
+            .unwrap());
+        let issued = database
+            .allocate_vouchers(&network.id, 1, "api")
+            .await
+            .unwrap();
+        assert_eq!(issued[0].code, "B");
+        assert!(issued[0].is_used);
+
+        let redeemed = database
+            .set_voucher_state(&vouchers[0].id, VoucherState::Redeemed, "portal")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(redeemed.state, VoucherState::Redeemed);
+
+        // Forbidden and automatic changes are refused and change nothing
+        let error = database
+            .set_voucher_state(&vouchers[0].id, VoucherState::Available, "admin")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VoucherStateError>(),
+            Some(VoucherStateError::Transition(
+                VoucherState::Redeemed,
+                VoucherState::Available
+            ))
+        ));
+        let error = database
+            .set_voucher_state(&vouchers[2].id, VoucherState::Reserved, "admin")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VoucherStateError>(),
+            Some(VoucherStateError::Automatic(VoucherState::Reserved))
+        ));
+        assert!(database
+            .set_voucher_state("missing", VoucherState::Revoked, "admin")
+            .await
+            .unwrap()
+            .is_none());
+        database
+            .set_voucher_state(&vouchers[2].id, VoucherState::Revoked, "admin")
+            .await
+            .unwrap();
+
+        let transitions = database
+            .get_voucher_transitions(&vouchers[0].id)
+            .await
+            .unwrap();
+        let steps: Vec<_> = transitions
+            .iter()
+            .map(|t| (t.from, t.to, t.changed_by.as_deref()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (
+                    VoucherState::Available,
+                    VoucherState::Reserved,
+                    Some("desk")
+                ),
+                (
+                    VoucherState::Reserved,
+                    VoucherState::Printed,
+                    Some("manager")
+                ),
+                (
+                    VoucherState::Printed,
+                    VoucherState::Redeemed,
+                    Some("portal")
+                ),
+            ]
+        );
+        // Stored to the millisecond
+        assert_eq!(
+            transitions[0].changed_at.timestamp_millis(),
+            job.created_at.timestamp_millis()
+        );
+
+        let counts = database.get_voucher_counts(&network.id).await.unwrap();
+        assert_eq!(
+            (
+                counts.total,
+                counts.available,
+                counts.issued,
+                counts.redeemed,
+                counts.revoked
+            ),
+            (3, 0, 1, 1, 1)
+        );
+        assert_eq!(counts.used(), 3);
+        assert_eq!(counts.get(&VoucherState::Issued), 1);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_voucher_refusals() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let now = chrono::Utc::now();
+        let vouchers: Vec<Voucher> = ["LATE", "USED", "HELD"]
+            .iter()
+            .map(|code| {
+                let mut voucher = Voucher::new(code.to_string());
+                voucher.network_id = Some(network.id.clone());
+                voucher.validity.duration_minutes = Some(30);
+                voucher
+            })
+            .collect();
+        database.create_vouchers(&vouchers).await.unwrap();
+        let device = Device::parse(Some("aa:bb:cc:dd:ee:ff"), None).unwrap();
+        let refusal = |result: Result<Redemption>| {
+            result
+                .unwrap_err()
+                .downcast::<RedeemError>()
+                .unwrap()
+                .to_string()
+        };
+
+        // Printed before its expiry date, but used after it
+        database
+            .set_voucher_state(&vouchers[0].id, VoucherState::Issued, "desk")
+            .await
+            .unwrap();
+        sqlx::query("UPDATE vouchers SET expires_at = ?1 WHERE code = 'LATE'")
+            .bind(timestamp(now - chrono::Duration::minutes(1)))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            refusal(
+                database
+                    .redeem_voucher(&network.id, "LATE", &device, "portal")
+                    .await
+            ),
+            "the voucher has expired"
+        );
+
+        // Even the device that redeemed it cannot log in once its time is up
+        database
+            .set_voucher_state(&vouchers[1].id, VoucherState::Issued, "desk")
+            .await
+            .unwrap();
+        database
+            .redeem_voucher(&network.id, "USED", &device, "portal")
+            .await
+            .unwrap();
+        sqlx::query("UPDATE vouchers SET redeemed_at = ?1 WHERE code = 'USED'")
+            .bind(timestamp(now - chrono::Duration::minutes(31)))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            refusal(
+                database
+                    .redeem_voucher(&network.id, "USED", &device, "portal")
+                    .await
+            ),
+            "the voucher's time is used up"
+        );
+
+        let job = PrintJob::reserve(
+            &network,
+            "desk".to_string(),
+            "a4".to_string(),
+            PrintOutput::Html,
+            chrono::Duration::minutes(30),
+        );
+        database.reserve_print_job(&job, 1).await.unwrap();
+        assert_eq!(
+            refusal(
+                database
+                    .redeem_voucher(&network.id, "HELD", &device, "portal")
+                    .await
+            ),
+            "the voucher has not been handed out yet"
+        );
+
+        // Nor are codes still in the pool, until they are handed out
+        let pool = Voucher {
+            network_id: Some(network.id.clone()),
+            ..Voucher::new("POOL".to_string())
+        };
+        database
+            .create_vouchers(std::slice::from_ref(&pool))
+            .await
+            .unwrap();
+        assert_eq!(
+            refusal(
+                database
+                    .redeem_voucher(&network.id, "POOL", &device, "portal")
+                    .await
+            ),
+            "the voucher has not been handed out yet"
+        );
+        database
+            .set_voucher_state(&pool.id, VoucherState::Issued, "desk")
+            .await
+            .unwrap();
+        assert!(database
+            .redeem_voucher(&network.id, "POOL", &device, "portal")
+            .await
+            .is_ok());
+
+        let other = WiFiNetwork::new(
+            "Bar".to_string(),
+            "Bar-Guest".to_string(),
+            "secret".to_string(),
+            None,
+        );
+        database.create_network(&other).await.unwrap();
+        assert_eq!(
+            refusal(
+                database
+                    .redeem_voucher(&other.id, "HELD", &device, "portal")
+                    .await
+            ),
+            "unknown voucher code"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_network_portal_login() {
+        let (database, mut network) = test_util::database_with_lobby().await;
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.portal, PortalLogin::default());
+
+        network.portal = PortalLogin {
+            mode: crate::portal::PortalQrMode::Both,
+            url_template: Some("https://portal.example/login?voucher={code}".to_string()),
+        };
+        database.update_network(&network).await.unwrap();
+        let stored = database.get_network(&network.id).await.unwrap().unwrap();
+        assert_eq!(stored.portal, network.portal);
+    }
+
+    #[tokio::test]
+    async fn test_pfsense_settings_and_rolls() {
+        let (database, network) = test_util::database_with_lobby().await;
+
+        let settings = PfSenseSettings {
+            network_id: network.id.clone(),
+            roll: RollSettings {
+                magic: u64::MAX,
+                ..RollSettings::default()
+            },
+            public_key: "public".to_string(),
+            private_key: Some("private".to_string()),
+            updated_at: chrono::Utc::now(),
+        };
+        database.save_pfsense_settings(&settings).await.unwrap();
+
+        let stored: String = sqlx::query("SELECT private_key FROM pfsense_settings")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap()
+            .get("private_key");
+        assert!(PasswordCipher::is_encrypted(&stored));
+
+        let loaded = database
+            .get_pfsense_settings(&network.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.roll, settings.roll);
+        assert_eq!(loaded.private_key.as_deref(), Some("private"));
+
+        let roll = VoucherRoll::new(network.id.clone(), 1, 2, 60);
+        let codes = vec!["abc".to_string(), "def".to_string()];
+        database.create_roll(&roll, &codes).await.unwrap();
+
+        // A second roll with the same number stores nothing
+        let duplicate = VoucherRoll::new(network.id.clone(), 1, 1, 60);
+        assert!(database
+            .create_roll(&duplicate, &["ghi".to_string()])
+            .await
+            .is_err());
+        assert!(database
+            .find_voucher_by_code(&network.id, "ghi")
+            .await
+            .unwrap()
+            .is_none());
+
+        let rolls = database.get_rolls_for_network(&network.id).await.unwrap();
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].ticket_count, 2);
+        assert!(database
+            .find_voucher_by_code(&network.id, "def")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_passwords_are_migrated() {
+        let database = test_util::database().await;
+        sqlx::query(
+            "INSERT INTO wifi_networks (id, name, ssid, password, created_at) VALUES ('n1', 'Old', 'Old', 'legacy', ?1)",
+        )
+        .bind(timestamp(chrono::Utc::now()))
+        .execute(&database.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(database.encrypt_plaintext_passwords().await.unwrap(), 1);
+        assert_eq!(database.encrypt_plaintext_passwords().await.unwrap(), 0);
+        assert_eq!(
+            database.get_network("n1").await.unwrap().unwrap().password,
+            "legacy"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotate_encryption_key() {
+        let database = test_util::database().await;
+        let network = WiFiNetwork {
+            password: "rotate-me".to_string(),
+            ..test_util::lobby()
+        };
+        database.create_network(&network).await.unwrap();
+        database
+            .save_pfsense_settings(&PfSenseSettings {
+                network_id: network.id.clone(),
+                roll: RollSettings::default(),
+                public_key: "public".to_string(),
+                private_key: Some("rotate-me-too".to_string()),
+                updated_at: chrono::Utc::now(),
+            })
+            .await
+            .unwrap();
+        let webhook = Webhook::new(
+            "https://hooks.example.com/vouchers".to_string(),
+            "rotate-me-three".to_string(),
+            Vec::new(),
+        )
+        .unwrap();
+        database.create_webhook(&webhook).await.unwrap();
+
+        let new_cipher = test_util::cipher();
+        assert_eq!(
+            database.rotate_encryption_key(&new_cipher).await.unwrap(),
+            1
+        );
+
+        let rotated = Database {
+            pool: database.pool.clone(),
+            cipher: new_cipher,
+        };
+        assert_eq!(
+            rotated
+                .get_network(&network.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .password,
+            "rotate-me"
+        );
+        assert_eq!(
+            rotated
+                .get_pfsense_settings(&network.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .private_key
+                .as_deref(),
+            Some("rotate-me-too")
+        );
+        assert_eq!(
+            rotated.get_webhooks().await.unwrap()[0].secret,
+            "rotate-me-three"
+        );
+        assert!(database.get_network(&network.id).await.is_err());
+        assert!(database.get_webhooks().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_check_covers_webhook_secrets() {
+        let database = test_util::database().await;
+        let webhook = Webhook::new(
+            "https://hooks.example.com/vouchers".to_string(),
+            String::new(),
+            Vec::new(),
+        )
+        .unwrap();
+        database.create_webhook(&webhook).await.unwrap();
+        assert_eq!(database.encrypt_plaintext_passwords().await.unwrap(), 0);
+
+        let wrong_key = Database {
+            pool: database.pool.clone(),
+            cipher: test_util::cipher(),
+        };
+        assert!(wrong_key.encrypt_plaintext_passwords().await.is_err());
+    }
 }
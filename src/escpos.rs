@@ -0,0 +1,381 @@
+//! ESC/POS receipts for thermal printers: one receipt per voucher, with its QR
+//! codes as raster bitmaps, downloaded or sent to the printer's raw TCP port.
+
+use crate::qr_generator::{QrGenerator, QrGeneratorError};
+use crate::voucher::Voucher;
+use crate::wifi_network::WiFiNetwork;
+use image::{Rgb, RgbImage};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Port network printers listen on for raw print data
+pub const DEFAULT_PORT: u16 = 9100;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ESC: u8 = 0x1b;
+const GS: u8 = 0x1d;
+const LF: u8 = b'\n';
+
+/// Tallest image sent in one raster command; some printers reject taller ones
+const RASTER_BAND_ROWS: usize = 255;
+
+/// Width of the paper roll
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaperWidth {
+    Mm58,
+    Mm80,
+}
+
+impl PaperWidth {
+    pub fn from_mm(mm: u32) -> Result<Self, EscPosError> {
+        match mm {
+            58 => Ok(Self::Mm58),
+            80 => Ok(Self::Mm80),
+            _ => Err(EscPosError::PaperWidth(mm)),
+        }
+    }
+
+    /// Printable width in dots at 203 dpi
+    fn dots(&self) -> u32 {
+        match self {
+            Self::Mm58 => 384,
+            Self::Mm80 => 576,
+        }
+    }
+
+    /// Characters per line in the printer's standard 12 × 24 font
+    fn columns(&self) -> usize {
+        match self {
+            Self::Mm58 => 32,
+            Self::Mm80 => 48,
+        }
+    }
+}
+
+/// Print data for one receipt per voucher, each followed by a paper cut
+pub fn render_receipts(
+    network: &WiFiNetwork,
+    vouchers: &[Voucher],
+    qr_generator: &QrGenerator,
+    paper: PaperWidth,
+) -> Result<Vec<u8>, EscPosError> {
+    // Half the paper width keeps the codes easy to scan without wasting paper
+    let qr_width = paper.dots() / 2;
+    let background = {
+        let (r, g, b) = qr_generator.options().background_rgb();
+        Rgb([r, g, b])
+    };
+    let join_qr = qr_generator.generate_raster(&network.qr_payload(), qr_width)?;
+
+    let mut receipt = Receipt::new(paper.columns());
+    for voucher in vouchers {
+        let login_qr = network
+            .portal
+            .login_url(&voucher.code)
+            .map(|url| qr_generator.generate_raster(&url, qr_width))
+            .transpose()?;
+        let show_join_qr = network.portal.shows_join_qr() || login_qr.is_none();
+
+        receipt.command(&[ESC, b'a', 1]);
+        receipt.text(&network.branding.heading, true);
+        if network.name != network.branding.heading {
+            receipt.text(&network.name, false);
+        }
+        receipt.feed();
+        receipt.text("Access code", false);
+        receipt.text(&voucher.code, true);
+        for line in voucher.validity.card_lines() {
+            receipt.text(&line, false);
+        }
+        receipt.feed();
+
+        let paired = show_join_qr && login_qr.is_some();
+        if show_join_qr {
+            if paired {
+                receipt.text("Join the WiFi", false);
+            }
+            receipt.raster(&join_qr, background);
+        }
+        if let Some(login_qr) = &login_qr {
+            if paired {
+                receipt.feed();
+                receipt.text("Log in", false);
+            }
+            receipt.raster(login_qr, background);
+        }
+
+        receipt.feed();
+        receipt.text(network.portal.instructions(), false);
+        receipt.feed();
+        receipt.text(&format!("SSID: {}", network.ssid), false);
+        receipt.text(&network.password_line(), false);
+        for line in [&network.branding.footer, &network.branding.contact]
+            .into_iter()
+            .flatten()
+        {
+            receipt.text(line, false);
+        }
+        receipt.cut();
+    }
+    Ok(receipt.bytes)
+}
+
+/// Send print data to a network printer's raw printing port
+pub async fn send(address: &str, data: &[u8]) -> Result<(), EscPosError> {
+    let send = async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.write_all(data).await?;
+        stream.shutdown().await
+    };
+    tokio::time::timeout(SEND_TIMEOUT, send)
+        .await
+        .map_err(|_| EscPosError::Timeout(address.to_string()))?
+        .map_err(|error| EscPosError::Send(address.to_string(), error))
+}
+
+/// ESC/POS commands for a run of receipts
+struct Receipt {
+    bytes: Vec<u8>,
+    columns: usize,
+}
+
+impl Receipt {
+    fn new(columns: usize) -> Self {
+        // Reset the printer and select code page 437
+        Self {
+            bytes: vec![ESC, b'@', ESC, b't', 0],
+            columns,
+        }
+    }
+
+    fn command(&mut self, command: &[u8]) {
+        self.bytes.extend_from_slice(command);
+    }
+
+    fn feed(&mut self) {
+        self.bytes.push(LF);
+    }
+
+    /// Word-wrapped text; large text is bold at double width and height
+    fn text(&mut self, text: &str, large: bool) {
+        let columns = if large {
+            self.columns / 2
+        } else {
+            self.columns
+        };
+        if large {
+            self.command(&[ESC, b'E', 1, GS, b'!', 0x11]);
+        }
+        for line in wrap(text, columns) {
+            self.bytes.extend(encode_text(&line));
+            self.feed();
+        }
+        if large {
+            self.command(&[ESC, b'E', 0, GS, b'!', 0]);
+        }
+    }
+
+    /// Print an image one bit per dot, marking every pixel that is not `background`
+    fn raster(&mut self, image: &RgbImage, background: Rgb<u8>) {
+        let width = image.width() as usize;
+        let width_bytes = width.div_ceil(8);
+        let height = image.height() as usize;
+
+        for band in (0..height).step_by(RASTER_BAND_ROWS) {
+            let rows = RASTER_BAND_ROWS.min(height - band);
+            self.command(&[
+                GS,
+                b'v',
+                b'0',
+                0,
+                (width_bytes & 0xff) as u8,
+                (width_bytes >> 8) as u8,
+                (rows & 0xff) as u8,
+                (rows >> 8) as u8,
+            ]);
+            for y in band..band + rows {
+                for byte in 0..width_bytes {
+                    let mut bits = 0u8;
+                    for bit in 0..8 {
+                        let x = byte * 8 + bit;
+                        if x < width && *image.get_pixel(x as u32, y as u32) != background {
+                            bits |= 0x80 >> bit;
+                        }
+                    }
+                    self.bytes.push(bits);
+                }
+            }
+        }
+    }
+
+    /// Feed the receipt past the cutter and cut it
+    fn cut(&mut self) {
+        self.command(&[GS, b'V', 66, 3]);
+    }
+}
+
+/// Break text into lines of at most `columns` characters, at spaces where possible
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > columns {
+            lines.push(std::mem::take(&mut line));
+        }
+        // Words longer than a line, like long codes, are split
+        while word.len() > columns {
+            lines.push(word.drain(..columns).collect());
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Encode text for code page 437, replacing characters it lacks with `?`
+fn encode_text(text: &str) -> Vec<u8> {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            ' '..='~' => c as u8,
+            'Ç' => 0x80,
+            'ü' => 0x81,
+            'é' => 0x82,
+            'â' => 0x83,
+            'ä' => 0x84,
+            'à' => 0x85,
+            'å' => 0x86,
+            'ç' => 0x87,
+            'ê' => 0x88,
+            'ë' => 0x89,
+            'è' => 0x8a,
+            'ï' => 0x8b,
+            'î' => 0x8c,
+            'ì' => 0x8d,
+            'Ä' => 0x8e,
+            'Å' => 0x8f,
+            'É' => 0x90,
+            'æ' => 0x91,
+            'Æ' => 0x92,
+            'ô' => 0x93,
+            'ö' => 0x94,
+            'ò' => 0x95,
+            'û' => 0x96,
+            'ù' => 0x97,
+            'ÿ' => 0x98,
+            'Ö' => 0x99,
+            'Ü' => 0x9a,
+            '£' => 0x9c,
+            '¥' => 0x9d,
+            'á' => 0xa0,
+            'í' => 0xa1,
+            'ó' => 0xa2,
+            'ú' => 0xa3,
+            'ñ' => 0xa4,
+            'Ñ' => 0xa5,
+            'ß' => 0xe1,
+            _ => b'?',
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EscPosError {
+    #[error("the receipt paper width must be 58 or 80 mm, not {0}")]
+    PaperWidth(u32),
+    #[error(transparent)]
+    Qr(#[from] QrGeneratorError),
+    #[error("could not send to the receipt printer at {0}: {1}")]
+    Send(String, std::io::Error),
+    #[error("the receipt printer at {0} did not respond in time")]
+    Timeout(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn network() -> WiFiNetwork {
+        test_util::lobby()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_render_receipts() {
+        let mut first = Voucher::new("CODE-1".to_string());
+        first.validity.duration_minutes = Some(1440);
+        let vouchers = vec![first, Voucher::new("CODE-2".to_string())];
+        let data = render_receipts(
+            &network(),
+            &vouchers,
+            &QrGenerator::default(),
+            PaperWidth::Mm80,
+        )
+        .unwrap();
+
+        assert!(data.starts_with(&[ESC, b'@']));
+        assert!(contains(&data, b"CODE-1\n"));
+        assert!(contains(&data, b"Valid for 24 hours\n"));
+        assert!(contains(&data, b"SSID: Lobby-Guest\n"));
+        assert!(contains(&data, b"Password: secret\n"));
+        // One cut per voucher, after the last one
+        let cut = [GS, b'V', 66, 3];
+        assert_eq!(data.windows(4).filter(|w| *w == cut).count(), 2);
+        assert!(data.ends_with(&cut));
+
+        // The WiFi code (29 modules plus a quiet zone of 2 on each side) is scaled
+        // to fit half of the 576 dots: 8 dots per module, 264 dots, 33 bytes wide,
+        // sent as a band of 255 rows and one of 9
+        assert!(contains(&data, &[GS, b'v', b'0', 0, 33, 0, 255, 0]));
+        assert!(contains(&data, &[GS, b'v', b'0', 0, 33, 0, 9, 0]));
+    }
+
+    #[test]
+    fn test_wrap_and_encode() {
+        assert_eq!(
+            wrap("Scan the code to join", 10),
+            vec!["Scan the", "code to", "join"]
+        );
+        assert_eq!(wrap("ABCDEFGHIJKL", 5), vec!["ABCDE", "FGHIJ", "KL"]);
+        assert_eq!(wrap("", 5), vec![""]);
+        assert_eq!(encode_text("Café €5\t"), b"Caf\x82 ?5".to_vec());
+        assert!(PaperWidth::from_mm(100).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let printer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        send(&address, b"\x1b@receipt").await.unwrap();
+        assert_eq!(printer.await.unwrap(), b"\x1b@receipt");
+
+        // Nothing listens on the listener's port any more
+        assert!(matches!(
+            send(&address, b"x").await,
+            Err(EscPosError::Send(..))
+        ));
+    }
+}
@@ -0,0 +1,408 @@
+use crate::voucher::{Validity, Voucher, VoucherState, VALIDITY_COLUMNS};
+use crate::wifi_network::WiFiNetwork;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Which timestamp the date range applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    Created,
+    Used,
+}
+
+/// Export query string, e.g. `?format=json&status=printed&from=2024-01-01&to=2024-01-31`.
+/// Blank values are ignored so the export forms can submit every field.
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+    pub status: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub date_field: Option<String>,
+}
+
+/// Which vouchers to include in an export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportFilter {
+    /// Vouchers in these states only, or all of them
+    pub states: Option<Vec<VoucherState>>,
+    /// First day included, in UTC
+    pub from: Option<NaiveDate>,
+    /// Last day included, in UTC
+    pub to: Option<NaiveDate>,
+    pub date_field: DateField,
+}
+
+impl ExportQuery {
+    pub fn parse(&self) -> Result<(ExportFormat, ExportFilter), ExportError> {
+        let value = |field: &Option<String>| {
+            field
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let date = |field: &Option<String>| {
+            value(field)
+                .map(|date| {
+                    NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                        .map_err(|_| ExportError::InvalidDate(date))
+                })
+                .transpose()
+        };
+
+        let format = match value(&self.format).as_deref() {
+            None | Some("csv") => ExportFormat::Csv,
+            Some("json") => ExportFormat::Json,
+            Some(other) => return Err(ExportError::InvalidFormat(other.to_string())),
+        };
+        // `used` and `unused` are kept from before vouchers had states
+        let states = match value(&self.status).as_deref() {
+            None | Some("all") => None,
+            Some(status @ ("used" | "unused")) => Some(
+                VoucherState::ALL
+                    .into_iter()
+                    .filter(|state| state.is_used() == (status == "used"))
+                    .collect(),
+            ),
+            Some(other) => Some(vec![other
+                .parse()
+                .map_err(|_| ExportError::InvalidStatus(other.to_string()))?]),
+        };
+        let date_field = match value(&self.date_field).as_deref() {
+            None | Some("created") => DateField::Created,
+            Some("used") => DateField::Used,
+            Some(other) => return Err(ExportError::InvalidDateField(other.to_string())),
+        };
+
+        let filter = ExportFilter {
+            states,
+            from: date(&self.from)?,
+            to: date(&self.to)?,
+            date_field,
+        };
+        if let (Some(from), Some(to)) = (filter.from, filter.to) {
+            if from > to {
+                return Err(ExportError::EmptyRange);
+            }
+        }
+
+        Ok((format, filter))
+    }
+}
+
+impl ExportFilter {
+    pub fn matches(&self, voucher: &Voucher) -> bool {
+        if self
+            .states
+            .as_ref()
+            .is_some_and(|states| !states.contains(&voucher.state))
+        {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+
+        let timestamp = match self.date_field {
+            DateField::Created => Some(voucher.created_at),
+            DateField::Used => voucher.used_at,
+        };
+        let Some(day) = timestamp.map(|timestamp| timestamp.date_naive()) else {
+            return false;
+        };
+        self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+    }
+}
+
+/// One exported voucher
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    pub code: String,
+    pub status: &'static str,
+    pub created_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub network_id: Option<String>,
+    pub network_name: Option<String>,
+    pub ssid: Option<String>,
+    #[serde(flatten)]
+    pub validity: Validity,
+}
+
+/// Filter vouchers and attach their network's name and SSID
+pub fn build_rows(
+    vouchers: Vec<Voucher>,
+    networks: &[WiFiNetwork],
+    filter: &ExportFilter,
+) -> Vec<ExportRow> {
+    let networks: HashMap<&str, &WiFiNetwork> = networks
+        .iter()
+        .map(|network| (network.id.as_str(), network))
+        .collect();
+
+    vouchers
+        .into_iter()
+        .filter(|voucher| filter.matches(voucher))
+        .map(|voucher| {
+            let network = voucher
+                .network_id
+                .as_deref()
+                .and_then(|id| networks.get(id));
+            ExportRow {
+                status: voucher.state.as_str(),
+                network_name: network.map(|network| network.name.clone()),
+                ssid: network.map(|network| network.ssid.clone()),
+                code: voucher.code,
+                created_at: voucher.created_at,
+                used_at: voucher.used_at,
+                network_id: voucher.network_id,
+                validity: voucher.validity,
+            }
+        })
+        .collect()
+}
+
+pub fn to_csv(rows: &[ExportRow]) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // The validity columns come last, named as on import
+    writer.write_record(
+        [
+            "code",
+            "status",
+            "created_at",
+            "used_at",
+            "network_id",
+            "network_name",
+            "ssid",
+        ]
+        .into_iter()
+        .chain(VALIDITY_COLUMNS),
+    )?;
+    for row in rows {
+        writer.write_record(
+            [
+                row.code.clone(),
+                row.status.to_string(),
+                row.created_at.to_rfc3339(),
+                row.used_at
+                    .map(|used_at| used_at.to_rfc3339())
+                    .unwrap_or_default(),
+                row.network_id.clone().unwrap_or_default(),
+                row.network_name.clone().unwrap_or_default(),
+                row.ssid.clone().unwrap_or_default(),
+            ]
+            .into_iter()
+            .chain(row.validity.columns()),
+        )?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+pub fn to_json(rows: &[ExportRow]) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(rows)?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Unknown export format '{0}', expected csv or json")]
+    InvalidFormat(String),
+    #[error("Unknown status '{0}', expected all, used, unused or a voucher state")]
+    InvalidStatus(String),
+    #[error("Unknown date field '{0}', expected created or used")]
+    InvalidDateField(String),
+    #[error("Invalid date '{0}', expected YYYY-MM-DD")]
+    InvalidDate(String),
+    #[error("The start date is after the end date")]
+    EmptyRange,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn voucher(code: &str, created_day: u32, used_day: Option<u32>) -> Voucher {
+        let day = |day| Utc.with_ymd_and_hms(2024, 3, day, 12, 0, 0).unwrap();
+        Voucher {
+            network_id: Some("net-1".to_string()),
+            created_at: day(created_day),
+            is_used: used_day.is_some(),
+            state: if used_day.is_some() {
+                VoucherState::Redeemed
+            } else {
+                VoucherState::Available
+            },
+            used_at: used_day.map(day),
+            ..Voucher::new(code.to_string())
+        }
+    }
+
+    fn query(pairs: &str) -> ExportQuery {
+        serde_urlencoded::from_str(pairs).unwrap()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let (format, filter) = query("format=&status=&from=&to=").parse().unwrap();
+        assert_eq!(format, ExportFormat::Csv);
+        assert_eq!(filter.states, None);
+        assert_eq!(filter.from, None);
+
+        let (format, filter) = query("format=json&status=used&from=2024-03-01&date_field=used")
+            .parse()
+            .unwrap();
+        assert_eq!(format, ExportFormat::Json);
+        assert_eq!(
+            filter.states,
+            Some(vec![
+                VoucherState::Printed,
+                VoucherState::Issued,
+                VoucherState::Redeemed,
+                VoucherState::Revoked
+            ])
+        );
+        assert_eq!(filter.from, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert_eq!(filter.date_field, DateField::Used);
+
+        assert!(matches!(
+            query("format=xml").parse(),
+            Err(ExportError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            query("status=lost").parse(),
+            Err(ExportError::InvalidStatus(_))
+        ));
+        assert!(matches!(
+            query("from=03/01/2024").parse(),
+            Err(ExportError::InvalidDate(_))
+        ));
+        assert!(matches!(
+            query("from=2024-03-02&to=2024-03-01").parse(),
+            Err(ExportError::EmptyRange)
+        ));
+    }
+
+    #[test]
+    fn test_filter_by_status_and_date() {
+        let vouchers = vec![
+            voucher("A", 1, None),
+            voucher("B", 5, Some(10)),
+            voucher("C", 10, Some(20)),
+        ];
+        let codes = |pairs: &str| {
+            let (_, filter) = query(pairs).parse().unwrap();
+            build_rows(vouchers.clone(), &[], &filter)
+                .into_iter()
+                .map(|row| row.code)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(codes(""), ["A", "B", "C"]);
+        assert_eq!(codes("status=unused"), ["A"]);
+        assert_eq!(codes("from=2024-03-05&to=2024-03-10"), ["B", "C"]);
+        assert_eq!(
+            codes("from=2024-03-05&to=2024-03-10&date_field=used"),
+            ["B"]
+        );
+    }
+
+    #[test]
+    fn test_exports_each_state() {
+        let vouchers: Vec<Voucher> = VoucherState::ALL
+            .into_iter()
+            .map(|state| Voucher {
+                state,
+                is_used: state.is_used(),
+                ..voucher(state.as_str(), 1, None)
+            })
+            .collect();
+        let rows = |pairs: &str| {
+            let (_, filter) = query(pairs).parse().unwrap();
+            build_rows(vouchers.clone(), &[], &filter)
+                .into_iter()
+                .map(|row| (row.code, row.status))
+                .collect::<Vec<_>>()
+        };
+
+        // Every state is exported as itself, not folded into used or unused
+        assert!(rows("")
+            .iter()
+            .all(|(code, status)| code.as_str() == *status));
+        assert_eq!(rows("").len(), VoucherState::ALL.len());
+        for state in VoucherState::ALL {
+            assert_eq!(
+                rows(&format!("status={}", state)),
+                [(state.to_string(), state.as_str())]
+            );
+        }
+
+        let codes = |pairs: &str| {
+            rows(pairs)
+                .into_iter()
+                .map(|(code, _)| code)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            codes("status=used"),
+            ["printed", "issued", "redeemed", "revoked"]
+        );
+        assert_eq!(codes("status=unused"), ["available", "reserved", "expired"]);
+    }
+
+    #[test]
+    fn test_csv_includes_network_and_quotes_values() {
+        let network = WiFiNetwork {
+            id: "net-1".to_string(),
+            ..WiFiNetwork::new(
+                "Lobby, East".to_string(),
+                "Guest".to_string(),
+                "secret".to_string(),
+                None,
+            )
+        };
+        let (_, filter) = query("").parse().unwrap();
+        let mut voucher = voucher("A", 1, Some(2));
+        voucher.validity.duration_minutes = Some(60);
+        let rows = build_rows(vec![voucher], &[network], &filter);
+        let csv = to_csv(&rows).unwrap();
+        let mut lines = csv.lines();
+
+        assert_eq!(
+            lines.next(),
+            Some("code,status,created_at,used_at,network_id,network_name,ssid,duration_minutes,expires_at,data_limit_mb,download_kbps,upload_kbps")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("A,redeemed,2024-03-01T12:00:00+00:00,2024-03-02T12:00:00+00:00,net-1,\"Lobby, East\",Guest,60,,,,")
+        );
+        assert!(!csv.contains("secret"));
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&rows).unwrap()).unwrap();
+        assert_eq!(json[0]["network_name"], "Lobby, East");
+        assert_eq!(json[0]["used_at"], "2024-03-02T12:00:00Z");
+        assert_eq!(json[0]["duration_minutes"], 60);
+    }
+}
@@ -0,0 +1,186 @@
+//! Posting JSON notifications over HTTP(S) with ureq. Blocking; run it on a
+//! blocking thread (`tokio::task::spawn_blocking`).
+
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+/// How long to wait for connecting, and for the whole request once connected
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check that `url` is an http or https URL with a host
+pub fn check_url(url: &str) -> Result<Url, HttpError> {
+    let parsed = Url::parse(url).map_err(|_| HttpError::InvalidUrl(url.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(HttpError::InvalidUrl(url.to_string()));
+    }
+    Ok(parsed)
+}
+
+/// A client that gives up after `timeout` and doesn't follow redirects, so a
+/// receiver that moved is reported instead of silently re-posted elsewhere
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout(timeout)
+        .redirects(0)
+        .user_agent(concat!("voucher_creator/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+/// POST a JSON body to `url` with extra `headers`, returning the response
+/// status. Statuses outside 2xx are errors.
+pub fn post_json(url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<u16, HttpError> {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    post_json_with(AGENT.get_or_init(|| agent(TIMEOUT)), url, headers, body)
+}
+
+fn post_json_with(
+    agent: &ureq::Agent,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<u16, HttpError> {
+    let url = check_url(url)?;
+    let mut request = agent
+        .post(url.as_str())
+        .set("Content-Type", "application/json");
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    match request.send_bytes(body) {
+        Ok(response) => {
+            let status = response.status();
+            // Read the body so the connection can be reused; its content doesn't matter
+            let _ = response.into_string();
+            if (200..300).contains(&status) {
+                Ok(status)
+            } else {
+                Err(HttpError::Status(status))
+            }
+        }
+        Err(ureq::Error::Status(status, _)) => Err(HttpError::Status(status)),
+        Err(ureq::Error::Transport(error)) => Err(HttpError::Transport(error.to_string())),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("'{0}' is not an http or https URL")]
+    InvalidUrl(String),
+    #[error("{0}")]
+    Transport(String),
+    #[error("the server answered with HTTP status {0}")]
+    Status(u16),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Accept one request and answer it with `status_line`, returning the request
+    fn serve_once(
+        listener: TcpListener,
+        status_line: &'static str,
+    ) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(
+                    format!(
+                        "{}\r\nLocation: http://elsewhere.test/\r\nContent-Length: 0\r\n\r\n",
+                        status_line
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+            request
+        })
+    }
+
+    #[test]
+    fn test_post_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/hooks?source=vouchers",
+            listener.local_addr().unwrap()
+        );
+        let server = serve_once(listener, "HTTP/1.1 204 No Content");
+
+        let status = post_json(&url, &[("X-Event", "low_stock")], br#"{"ok":true}"#).unwrap();
+        assert_eq!(status, 204);
+
+        let request = server.join().unwrap().to_ascii_lowercase();
+        assert!(request.starts_with("post /hooks?source=vouchers http/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        assert!(request.contains("x-event: low_stock\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"ok\":true}"));
+    }
+
+    #[test]
+    fn test_post_json_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = serve_once(listener, "HTTP/1.1 500 Internal Server Error");
+        assert!(matches!(
+            post_json(&url, &[], b"{}"),
+            Err(HttpError::Status(500))
+        ));
+        server.join().unwrap();
+
+        // Redirects are reported, not followed
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = serve_once(listener, "HTTP/1.1 302 Found");
+        assert!(matches!(
+            post_json(&url, &[], b"{}"),
+            Err(HttpError::Status(302))
+        ));
+        server.join().unwrap();
+
+        assert!(matches!(
+            post_json("ftp://example.com/", &[], b"{}"),
+            Err(HttpError::InvalidUrl(_))
+        ));
+        assert!(check_url("not a url").is_err());
+        assert!(check_url("https://hooks.example.com/vouchers").is_ok());
+    }
+
+    #[test]
+    fn test_post_json_times_out() {
+        // A server that accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(3));
+            drop(stream);
+        });
+
+        let started = std::time::Instant::now();
+        let result = post_json_with(&agent(Duration::from_millis(300)), &url, &[], b"{}");
+        assert!(matches!(result, Err(HttpError::Transport(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! Page layouts for printed voucher sheets.
+//!
+//! Each preset fixes the page size, the position of the first card and the card
+//! pitch, so cards line up with pre-cut label and business card stock. All sizes
+//! are in millimetres.
+
+/// Preset used when a network has no layout of its own
+pub const DEFAULT_LAYOUT: &str = "a4";
+
+/// Space between two QR codes on the same card
+pub const QR_GAP: f32 = 2.0;
+
+/// Cards shorter than this drop the heading and instructions to make room for the QR code
+const COMPACT_BELOW_HEIGHT: f32 = 55.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetLayout {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub page_width: f32,
+    pub page_height: f32,
+    /// Distance from the top edge of the page to the first row
+    pub margin_top: f32,
+    /// Distance from the left edge of the page to the first column
+    pub margin_left: f32,
+    pub columns: usize,
+    pub rows: usize,
+    pub card_width: f32,
+    pub card_height: f32,
+    /// Horizontal space between cards
+    pub column_gap: f32,
+    /// Vertical space between cards
+    pub row_gap: f32,
+}
+
+pub const PRESETS: &[SheetLayout] = &[
+    SheetLayout {
+        id: "a4",
+        name: "A4 paper, 8 cards",
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 10.0,
+        margin_left: 10.0,
+        columns: 2,
+        rows: 4,
+        card_width: 92.0,
+        card_height: 64.75,
+        column_gap: 6.0,
+        row_gap: 6.0,
+    },
+    SheetLayout {
+        id: "letter",
+        name: "US Letter paper, 8 cards",
+        page_width: 215.9,
+        page_height: 279.4,
+        margin_top: 10.0,
+        margin_left: 10.0,
+        columns: 2,
+        rows: 4,
+        card_width: 94.95,
+        card_height: 60.35,
+        column_gap: 6.0,
+        row_gap: 6.0,
+    },
+    SheetLayout {
+        id: "avery-l7165",
+        name: "Avery L7165 labels (A4, 99.1 × 67.7 mm, 8 per sheet)",
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 13.1,
+        margin_left: 4.65,
+        columns: 2,
+        rows: 4,
+        card_width: 99.1,
+        card_height: 67.7,
+        column_gap: 2.5,
+        row_gap: 0.0,
+    },
+    SheetLayout {
+        id: "avery-l7163",
+        name: "Avery L7163 labels (A4, 99.1 × 38.1 mm, 14 per sheet)",
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 15.15,
+        margin_left: 4.65,
+        columns: 2,
+        rows: 7,
+        card_width: 99.1,
+        card_height: 38.1,
+        column_gap: 2.5,
+        row_gap: 0.0,
+    },
+    SheetLayout {
+        id: "avery-5163",
+        name: "Avery 5163 labels (Letter, 4 × 2 in, 10 per sheet)",
+        page_width: 215.9,
+        page_height: 279.4,
+        margin_top: 12.7,
+        margin_left: 3.97,
+        columns: 2,
+        rows: 5,
+        card_width: 101.6,
+        card_height: 50.8,
+        column_gap: 4.76,
+        row_gap: 0.0,
+    },
+    SheetLayout {
+        id: "herma-4360",
+        name: "Herma 4360 labels (A4, 70 × 36 mm, 24 per sheet)",
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 4.5,
+        margin_left: 0.0,
+        columns: 3,
+        rows: 8,
+        card_width: 70.0,
+        card_height: 36.0,
+        column_gap: 0.0,
+        row_gap: 0.0,
+    },
+    SheetLayout {
+        id: "business-card-a4",
+        name: "Business cards (A4, 85 × 54 mm, 10 per sheet)",
+        page_width: 210.0,
+        page_height: 297.0,
+        margin_top: 13.5,
+        margin_left: 15.0,
+        columns: 2,
+        rows: 5,
+        card_width: 85.0,
+        card_height: 54.0,
+        column_gap: 10.0,
+        row_gap: 0.0,
+    },
+    SheetLayout {
+        id: "business-card-letter",
+        name: "Business cards (Letter, Avery 8371, 3.5 × 2 in, 10 per sheet)",
+        page_width: 215.9,
+        page_height: 279.4,
+        margin_top: 12.7,
+        margin_left: 19.05,
+        columns: 2,
+        rows: 5,
+        card_width: 88.9,
+        card_height: 50.8,
+        column_gap: 0.0,
+        row_gap: 0.0,
+    },
+];
+
+/// Look up a preset by its id
+pub fn preset(id: &str) -> Option<&'static SheetLayout> {
+    PRESETS.iter().find(|layout| layout.id == id)
+}
+
+impl SheetLayout {
+    pub fn cards_per_page(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Bottom-left corner of the card in the given slot, counting left to right, top to bottom
+    pub fn card_origin(&self, slot: usize) -> (f32, f32) {
+        let column = (slot % self.columns) as f32;
+        let row = (slot / self.columns) as f32;
+        let x = self.margin_left + column * (self.card_width + self.column_gap);
+        let top = self.page_height - self.margin_top - row * (self.card_height + self.row_gap);
+        (x, top - self.card_height)
+    }
+
+    /// Small cards leave out the heading and instructions
+    pub fn compact(&self) -> bool {
+        self.card_height < COMPACT_BELOW_HEIGHT
+    }
+
+    /// Side length of the QR code on a card
+    pub fn qr_size(&self) -> f32 {
+        if self.compact() {
+            (self.card_height - 8.0).min(self.card_width * 0.4)
+        } else {
+            (self.card_height - 35.0).min(self.card_width * 0.42)
+        }
+    }
+
+    /// Side length of each QR code when a card shows two side by side
+    pub fn paired_qr_size(&self) -> f32 {
+        self.qr_size().min((self.card_width * 0.45 - QR_GAP) / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_fit_their_page() {
+        for layout in PRESETS {
+            let (x, y) = layout.card_origin(layout.cards_per_page() - 1);
+            assert!(
+                x + layout.card_width <= layout.page_width + 0.01,
+                "{} is too wide",
+                layout.id
+            );
+            assert!(y >= -0.01, "{} is too tall", layout.id);
+            assert!(layout.qr_size() > 15.0, "{} QR code too small", layout.id);
+            assert!(
+                layout.paired_qr_size() > 12.0,
+                "{} paired QR codes too small",
+                layout.id
+            );
+        }
+    }
+
+    #[test]
+    fn test_preset_ids_are_unique() {
+        for (i, layout) in PRESETS.iter().enumerate() {
+            assert!(PRESETS[i + 1..].iter().all(|other| other.id != layout.id));
+        }
+        assert!(preset(DEFAULT_LAYOUT).is_some());
+        assert!(preset("unknown").is_none());
+    }
+
+    #[test]
+    fn test_card_origin() {
+        let layout = preset("avery-l7163").unwrap();
+        assert_eq!(layout.card_origin(0), (4.65, 297.0 - 15.15 - 38.1));
+        let (x, _) = layout.card_origin(1);
+        assert!((x - (4.65 + 99.1 + 2.5)).abs() < 0.001);
+        assert!(layout.compact());
+        assert!(!preset("a4").unwrap().compact());
+    }
+}
@@ -1,27 +1,67 @@
 use axum::{
-    extract::{Multipart, Path, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{Extension, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
-use clap::Parser;
-use config::Config;
+use clap::{Parser, Subcommand};
+use config::{AuthConfig, Config, PrintingConfig, ReceiptPrinterConfig, RedemptionConfig};
 use serde::Deserialize;
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
 use tower_http::{cors::CorsLayer, services::ServeDir};
 
+mod api;
+mod auth;
+mod branding;
+mod code_generator;
 mod config;
+mod crypto;
 mod database;
+mod escpos;
+mod export;
+mod http_client;
+mod layout;
+mod migrations;
+mod pdf;
+mod pfsense;
+mod portal;
+mod print_job;
 mod qr_generator;
+mod radius;
+mod redemption;
+mod smtp;
+mod stock;
 mod templates;
+#[cfg(test)]
+mod test_util;
+mod user;
 mod voucher;
+mod webhook;
 mod wifi_network;
 
+use auth::CurrentUser;
+use branding::{Branding, Logo};
+use code_generator::{CodeFormat, CodeGeneratorError};
+use crypto::PasswordCipher;
 use database::Database;
-use qr_generator::QrGenerator;
-use voucher::Voucher;
-use wifi_network::WiFiNetwork;
+use export::{ExportFormat, ExportQuery};
+use pfsense::{PfSenseSettings, RollSettings, VoucherRoll};
+use portal::{PortalLogin, PortalQrMode};
+use print_job::{PrintJob, PrintJobStatus, PrintOutput};
+use qr_generator::{ErrorCorrection, QrGenerator, QrImageFormat, QrOptions};
+use stock::StockAlerts;
+use templates::{
+    AdminTemplate, LinkButton, LoginTemplate, NetworkSummary, NetworkVouchersTemplate,
+    NoUnusedVouchersTemplate, NoVouchersTemplate, PrintJobTemplate, PrintJobsTemplate,
+    PrintSelectionTemplate, ResponseTemplate, UserSummary, UsersTemplate, VoucherListTemplate,
+    VouchersTemplate, WebhookTemplate, WebhooksTemplate,
+};
+use user::{Role, User};
+use voucher::{Validity, Voucher, VoucherState, VoucherStateError, VALIDITY_COLUMNS};
+use webhook::{Webhook, WebhookEvent, Webhooks};
+use wifi_network::{SecurityType, WiFiNetwork};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -31,12 +71,31 @@ struct Args {
 
     #[arg(long)]
     host: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-encrypt stored WiFi passwords with a new encryption key
+    RotateKey {
+        /// File to read the new key from; a new key is generated into it if it does not exist
+        #[arg(long)]
+        new_key_file: PathBuf,
+    },
 }
 
 #[derive(Clone)]
 struct AppState {
     database: Arc<Database>,
     qr_generator: QrGenerator,
+    auth: Arc<AuthConfig>,
+    printing: Arc<PrintingConfig>,
+    receipt_printer: Arc<ReceiptPrinterConfig>,
+    redemption: Arc<RedemptionConfig>,
+    stock_alerts: Arc<StockAlerts>,
+    webhooks: Webhooks,
 }
 
 #[derive(Deserialize)]
@@ -44,6 +103,16 @@ struct GenerateQuery {
     network_id: Option<String>, // specific network ID
 }
 
+#[derive(Deserialize)]
+struct VoucherFilterQuery {
+    state: Option<String>, // show only vouchers in this lifecycle state
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load configuration from config.toml
@@ -55,6 +124,12 @@ async fn main() -> anyhow::Result<()> {
     // Parse command line arguments
     let args = Args::parse();
 
+    if let Some(Command::RotateKey { new_key_file }) = &args.command {
+        let cipher = PasswordCipher::from_base64(&config.encryption_key()?)?;
+        let database = Database::new(&config.database_url()?, cipher).await?;
+        return rotate_key(&database, new_key_file).await;
+    }
+
     // Use command line args if provided, otherwise use config defaults
     let host = args
         .host
@@ -66,16 +141,35 @@ async fn main() -> anyhow::Result<()> {
     println!("Using templates directory: {}", config.templates_dir);
     println!("Using database path: {}", config.database_path);
 
-    // Initialize database using configured path
+    // Initialize database using configured path and encryption key
     let database_url = config.database_url()?;
-    let database = Arc::new(Database::new(&database_url).await?);
+    let cipher = PasswordCipher::from_base64(&config.encryption_key()?)?;
+    let database = Arc::new(Database::new(&database_url, cipher).await?);
     println!("Database initialized at: {}", config.database_path);
 
+    // Make sure there is always someone who can log in
+    ensure_initial_admin(&database, &config.auth).await?;
+
     // Initialize application state
     let state = AppState {
-        database,
-        qr_generator: QrGenerator::new(),
+        database: database.clone(),
+        qr_generator: QrGenerator::with_options(config.qr.clone()),
+        auth: Arc::new(config.auth.clone()),
+        printing: Arc::new(config.printing.clone()),
+        receipt_printer: Arc::new(config.receipt_printer.clone()),
+        redemption: Arc::new(config.redemption.clone()),
+        stock_alerts: Arc::new(StockAlerts {
+            alerts: config.alerts.clone(),
+            smtp: config.smtp.clone(),
+        }),
+        webhooks: Webhooks::new(database.clone()),
     };
+    state.webhooks.start();
+    database::start_expiry_sweep(state.database.clone());
+
+    for address in radius::start(&config.radius, state.database.clone()).await? {
+        println!("RADIUS server listening on udp://{}", address);
+    }
 
     let app = Router::new()
         .route(
@@ -85,15 +179,61 @@ async fn main() -> anyhow::Result<()> {
         .route("/upload", post(upload_csv))
         .route("/generate", get(generate_vouchers))
         .route("/print", post(print_vouchers))
+        .route("/networks/:id/print-jobs", get(print_history))
+        .route("/print-jobs/:id", get(print_job_page))
+        .route("/print-jobs/:id/sheet", get(print_job_sheet))
+        .route("/print-jobs/:id/logo", get(print_job_logo))
+        .route("/print-jobs/:id/confirm", post(confirm_print_job))
+        .route("/print-jobs/:id/release", post(release_print_job))
+        .route("/print-jobs/:id/send", post(send_print_job))
         .route("/vouchers", get(list_vouchers))
         .route("/admin", get(admin_page))
         .route("/admin/networks", post(create_network))
         .route("/admin/networks/:id/delete", post(delete_network))
         .route("/admin/upload", post(admin_upload_csv))
         .route("/admin/networks/:id/vouchers", get(network_vouchers))
+        .route("/admin/networks/:id/codes", post(generate_codes))
+        .route("/admin/vouchers/:id/state", post(set_voucher_state))
+        .route(
+            "/admin/networks/:id/vouchers/expired",
+            post(delete_expired_vouchers),
+        )
+        .route("/admin/networks/:id/export", get(export_network_vouchers))
+        .route("/admin/export", get(export_all_vouchers))
+        .route("/admin/networks/:id/branding", post(save_branding))
+        .route("/networks/:id/logo", get(network_logo))
+        .route("/admin/networks/:id/qr", post(save_qr_options))
+        .route("/admin/networks/:id/security", post(save_network_security))
+        .route("/admin/networks/:id/portal", post(save_portal_login))
+        .route("/admin/networks/:id/stock", post(save_stock_alert))
+        .route("/admin/networks/:id/pfsense", post(save_pfsense_settings))
+        .route("/admin/networks/:id/rolls", post(generate_pfsense_roll))
+        .route(
+            "/admin/networks/:id/rolls/verify",
+            post(verify_pfsense_code),
+        )
+        .route("/admin/users", get(users_page).post(create_user))
+        .route("/admin/users/:id/delete", post(delete_user))
+        .route("/admin/users/:id/networks", post(update_user_networks))
+        .route("/admin/webhooks", get(webhooks_page).post(create_webhook))
+        .route("/admin/webhooks/:id", get(webhook_page))
+        .route("/admin/webhooks/:id/active", post(set_webhook_active))
+        .route("/admin/webhooks/:id/delete", post(delete_webhook))
+        .route(
+            "/admin/webhooks/:id/deliveries/:delivery_id/retry",
+            post(retry_webhook_delivery),
+        )
         .route("/vouchers/:id/use", post(mark_voucher_used))
         .route("/vouchers/:id/unuse", post(mark_voucher_unused))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route(api::REDEEM_PATH, post(api::redeem_voucher))
+        .nest("/api/v1", api::router())
         .nest_service("/static", ServeDir::new(&config.templates_dir))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
         .layer(CorsLayer::permissive())
         .with_state(state);
 
@@ -109,45 +249,176 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Re-encrypt all stored secrets with the key stored in `new_key_file`
+async fn rotate_key(database: &Database, new_key_file: &std::path::Path) -> anyhow::Result<()> {
+    if !new_key_file.exists() {
+        config::write_key_file(new_key_file, &PasswordCipher::generate_key())?;
+        println!(
+            "Generated new encryption key file: {}",
+            new_key_file.display()
+        );
+    }
+
+    let new_key = std::fs::read_to_string(new_key_file)?;
+    let new_cipher = PasswordCipher::from_base64(&new_key)?;
+    let count = database.rotate_encryption_key(&new_cipher).await?;
+
+    println!(
+        "Re-encrypted {} network password(s) and the other stored secrets",
+        count
+    );
+    println!(
+        "Point encryption.key_file in config.toml (or VOUCHER_ENCRYPTION_KEY) at {} before restarting",
+        new_key_file.display()
+    );
+
+    Ok(())
+}
+
+/// Create the initial admin account when the database has no users yet
+async fn ensure_initial_admin(database: &Database, auth_config: &AuthConfig) -> anyhow::Result<()> {
+    if database.count_users().await? > 0 {
+        return Ok(());
+    }
+
+    let (password, generated) = match std::env::var("VOUCHER_ADMIN_PASSWORD") {
+        Ok(password) if !password.is_empty() => (password, false),
+        _ => (auth::generate_token(12), true),
+    };
+
+    let user = User::new(
+        auth_config.initial_admin_username.clone(),
+        auth::hash_password(&password)?,
+        Role::Admin,
+    );
+    database.create_user(&user).await?;
+
+    println!("Created initial admin user: {}", user.username);
+    if generated {
+        println!("Generated admin password (shown only once): {}", password);
+    }
+
+    Ok(())
+}
+
+async fn login_page(Query(params): Query<LoginQuery>) -> impl IntoResponse {
+    let next = auth::safe_redirect_target(params.next.as_deref());
+    LoginTemplate {
+        error: None,
+        next: &next,
+    }
+    .into_response()
+}
+
+async fn login(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    let form_data = read_form(&mut multipart).await?;
+
+    let username = form_data.get("username").ok_or(StatusCode::BAD_REQUEST)?;
+    let password = form_data.get("password").ok_or(StatusCode::BAD_REQUEST)?;
+    let next = auth::safe_redirect_target(form_data.get("next").map(String::as_str));
+
+    let user = auth::authenticate(&state.database, username, password)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let Some(user) = user else {
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            LoginTemplate {
+                error: Some("Invalid username or password."),
+                next: &next,
+            },
+        )
+            .into_response());
+    };
+
+    let _ = state.database.delete_expired_sessions().await;
+
+    let token = auth::generate_session_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(state.auth.session_ttl_hours);
+    state
+        .database
+        .create_session(&token, &user.id, expires_at)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            auth::session_cookie(&token, &state.auth),
+        )],
+        axum::response::Redirect::to(&next),
+    )
+        .into_response())
+}
+
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(token) = auth::session_token(&headers) {
+        let _ = state.database.delete_session(&token).await;
+    }
+
+    (
+        [(header::SET_COOKIE, auth::clear_session_cookie(&state.auth))],
+        axum::response::Redirect::to("/login"),
+    )
+}
+
 // New admin functions
-async fn admin_page(State(state): State<AppState>) -> Html<String> {
-    let networks = state.database.get_all_networks().await.unwrap_or_default();
+async fn admin_page(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    let mut networks = state.database.get_all_networks().await.unwrap_or_default();
+
+    // Operators only see the networks they are assigned to
+    if !user.is_admin() {
+        let assigned = state
+            .database
+            .get_user_network_ids(&user.id)
+            .await
+            .unwrap_or_default();
+        networks.retain(|network| assigned.contains(&network.id));
+    }
 
     // Get voucher counts for each network
-    let mut network_counts = Vec::new();
-    for network in &networks {
+    let mut summaries = Vec::new();
+    for network in networks {
         let counts = state
             .database
             .get_voucher_counts(&network.id)
             .await
-            .unwrap_or(database::VoucherCounts {
-                total: 0,
-                used: 0,
-                unused: 0,
-            });
-        network_counts.push(counts);
+            .unwrap_or_default();
+        let stock_alert = state
+            .database
+            .get_stock_alert(&network.id)
+            .await
+            .unwrap_or_default();
+        summaries.push(NetworkSummary {
+            network,
+            counts,
+            stock_alert,
+        });
     }
 
-    Html(templates::admin_template(&networks, &network_counts))
+    AdminTemplate {
+        current_user: &user,
+        networks: summaries,
+        security_types: &SecurityType::ALL,
+    }
+    .into_response()
 }
 
 async fn create_network(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let mut form_data: HashMap<String, String> = HashMap::new();
+    auth::require_admin(&user)?;
 
-    while let Some(field) = multipart
-        .next_field()
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?
-    {
-        if let Some(name) = field.name() {
-            let name = name.to_string();
-            let value = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-            form_data.insert(name, value);
-        }
-    }
+    let form_data = read_form(&mut multipart).await?;
 
     let ssid = form_data
         .get("ssid")
@@ -163,29 +434,71 @@ async fn create_network(
         .clone();
     let description = form_data.get("description").cloned();
 
-    let network = WiFiNetwork::new(name, ssid, password, description);
+    let mut network = WiFiNetwork::new(name, ssid, password, description);
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    network.security = parse_or(field("security"), network.security)?;
+    network.hidden = field("hidden") == "true";
+    network.transition_disable = field("transition_disable") == "true";
+    if let Err(error) = network.validate_security() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            ResponseTemplate::error(
+                "Network Not Created",
+                format!("The network could not be created: {}.", error),
+                vec![LinkButton::new(
+                    "/admin",
+                    "fas fa-cog",
+                    "Back to Admin",
+                    "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+                )],
+            ),
+        )
+            .into_response());
+    }
 
     if state.database.create_network(&network).await.is_err() {
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    state
+        .webhooks
+        .emit(
+            WebhookEvent::NetworkCreated,
+            webhook::network_data(&network, &user.username),
+        )
+        .await;
 
     Ok(axum::response::Redirect::to("/admin").into_response())
 }
 
 async fn delete_network(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     Path(network_id): Path<String>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let network = state.database.get_network(&network_id).await.ok().flatten();
     // Delete the network (which will cascade delete vouchers due to foreign key)
-    let _ = state.database.delete_network(&network_id).await;
+    if let (Some(network), Ok(true)) = (network, state.database.delete_network(&network_id).await) {
+        state
+            .webhooks
+            .emit(
+                WebhookEvent::NetworkDeleted,
+                webhook::network_data(&network, &user.username),
+            )
+            .await;
+    }
 
-    axum::response::Redirect::to("/admin")
+    Ok(axum::response::Redirect::to("/admin"))
 }
 
 async fn admin_upload_csv(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
     let mut network_id = String::new();
     let mut csv_data = Vec::new();
 
@@ -232,6 +545,11 @@ async fn admin_upload_csv(
             {
                 return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
+            state
+                .webhooks
+                .vouchers_imported(Some(&network_id), network_vouchers.len(), "csv")
+                .await;
+            stock::check(&state, &network_id).await;
 
             Ok(axum::response::Redirect::to("/admin").into_response())
         }
@@ -239,364 +557,1910 @@ async fn admin_upload_csv(
     }
 }
 
-async fn network_vouchers(
+/// Generate random voucher codes for a network instead of uploading a CSV
+async fn generate_codes(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     Path(network_id): Path<String>,
-) -> Html<String> {
-    let network = state
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+
+    state
         .database
         .get_network(&network_id)
         .await
-        .unwrap_or(None);
-    let vouchers = state
-        .database
-        .get_vouchers_for_network(&network_id)
-        .await
-        .unwrap_or_default();
-    let voucher_counts = state
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let number = |name: &str| -> Result<Option<usize>, StatusCode> {
+        match form_data.get(name).map(|value| value.trim()) {
+            None | Some("") => Ok(None),
+            Some(value) => value.parse().map(Some).map_err(|_| StatusCode::BAD_REQUEST),
+        }
+    };
+
+    let count = number("count")?.ok_or(StatusCode::BAD_REQUEST)?;
+    let mut format = CodeFormat {
+        length: number("length")?.unwrap_or(CodeFormat::default().length),
+        prefix: form_data
+            .get("prefix")
+            .map(|prefix| prefix.trim().to_string())
+            .unwrap_or_default(),
+        group_size: number("group_size")?.filter(|size| *size > 0),
+        ..CodeFormat::default()
+    };
+    let alphabet = form_data.get("alphabet").map(String::as_str);
+    format = match alphabet {
+        Some("custom") => format.with_alphabet(
+            form_data
+                .get("custom_alphabet")
+                .map(String::as_str)
+                .unwrap_or_default(),
+        ),
+        Some(name) => {
+            format.with_alphabet(CodeFormat::named_alphabet(name).ok_or(StatusCode::BAD_REQUEST)?)
+        }
+        None => format,
+    };
+
+    let mut validity = Validity::default();
+    for column in VALIDITY_COLUMNS {
+        if let Err(error) = validity
+            .set(
+                column,
+                form_data
+                    .get(column)
+                    .map(String::as_str)
+                    .unwrap_or_default(),
+            )
+            .and_then(|_| validity.validate())
+        {
+            return Ok(bad_request_page(
+                "Code Generation Failed",
+                format!("No voucher codes were generated: {}.", error),
+                &network_id,
+            ));
+        }
+    }
+
+    match state
         .database
-        .get_voucher_counts(&network_id)
+        .generate_vouchers(&network_id, &format, count, &validity)
         .await
-        .unwrap_or(database::VoucherCounts {
-            total: 0,
-            used: 0,
-            unused: 0,
-        });
-
-    Html(templates::network_vouchers_template(
-        network.as_ref(),
-        &vouchers,
-        &network_id,
-        &voucher_counts,
-    ))
+    {
+        Ok(vouchers) => {
+            state
+                .webhooks
+                .vouchers_imported(Some(&network_id), vouchers.len(), "generated")
+                .await;
+            stock::check(&state, &network_id).await;
+            Ok(
+                axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+                    .into_response(),
+            )
+        }
+        Err(e) => match e.downcast_ref::<CodeGeneratorError>() {
+            Some(error) => Ok(bad_request_page(
+                "Code Generation Failed",
+                format!("No voucher codes were generated: {}.", error),
+                &network_id,
+            )),
+            None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+    }
 }
 
-async fn upload_csv(
+/// Move a voucher to another lifecycle state from the network's voucher list
+async fn set_voucher_state(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(voucher_id): Path<String>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, StatusCode> {
-    while let Some(field) = multipart
-        .next_field()
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?
-    {
-        if field.name() == Some("csv_file") {
-            let data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-            let csv_content =
-                String::from_utf8(data.to_vec()).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-            match process_csv_data(&csv_content).await {
-                Ok(vouchers) => {
-                    if state.database.create_vouchers(&vouchers).await.is_err() {
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                    }
+    auth::require_admin(&user)?;
 
-                    let buttons = r#"
-                        <a href="/vouchers" class="bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                            <i class="fas fa-list mr-2"></i>View Vouchers
-                        </a>
-                        <a href="/generate" class="bg-gradient-to-r from-emerald-500 to-teal-600 hover:from-emerald-600 hover:to-teal-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                            <i class="fas fa-qrcode mr-2"></i>Generate QR Codes
-                        </a>
-                        <a href="/admin" class="bg-gradient-to-r from-purple-500 to-indigo-600 hover:from-purple-600 hover:to-indigo-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                            <i class="fas fa-cog mr-2"></i>Admin Panel
-                        </a>
-                        "#;
+    let form_data = read_form(&mut multipart).await?;
+    let new_state: VoucherState = form_data
+        .get("state")
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .parse()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
 
-                    return Ok((
-                        StatusCode::OK,
-                        Html(templates::success_response(
-                            "CSV Uploaded Successfully!",
-                            &format!("Your CSV file has been processed and {} voucher codes have been loaded into the system. You can now generate QR code vouchers for printing.", vouchers.len()),
-                            vouchers.len(),
-                            buttons
-                        ))
-                    ));
-                }
-                Err(e) => {
-                    let buttons = r#"
-                        <a href="/" class="bg-gradient-to-r from-gray-500 to-gray-600 hover:from-gray-600 hover:to-gray-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                            <i class="fas fa-arrow-left mr-2"></i>Go Back
-                        </a>
-                        <a href="/admin" class="bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                            <i class="fas fa-cog mr-2"></i>Try Admin Panel
-                        </a>
-                    "#;
+    let voucher = state
+        .database
+        .get_voucher(&voucher_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let network_id = voucher.network_id.unwrap_or_default();
 
-                    return Ok((
-                        StatusCode::BAD_REQUEST,
-                        Html(templates::error_response(
-                            "CSV Processing Failed",
-                            &format!("We encountered an error while processing your CSV file: {}. Please check your file format and try again.", e),
-                            buttons
-                        ))
-                    ));
-                }
-            }
+    match state
+        .database
+        .set_voucher_state(&voucher_id, new_state, &user.username)
+        .await
+    {
+        Ok(Some(changed)) => {
+            state
+                .webhooks
+                .voucher_changed(voucher.state.is_used(), &changed, &user.username)
+                .await;
         }
-    }
-
-    Err(StatusCode::BAD_REQUEST)
-}
-
-async fn process_csv_data(csv_content: &str) -> anyhow::Result<Vec<Voucher>> {
-    let mut reader = csv::Reader::from_reader(csv_content.as_bytes());
-    let mut vouchers = Vec::new();
-
-    for result in reader.records() {
-        let record = result?;
-        if let Some(code) = record.get(0) {
-            let trimmed_code = code.trim();
-            // Skip empty lines and comment lines starting with #
-            if !trimmed_code.is_empty() && !trimmed_code.starts_with('#') {
-                vouchers.push(Voucher::new(trimmed_code.to_string()));
-            }
+        Ok(None) => {}
+        Err(e) => {
+            return match e.downcast_ref::<VoucherStateError>() {
+                Some(error) => Ok(bad_request_page(
+                    "Voucher Not Changed",
+                    format!("{} was not changed: {}.", voucher.code, error),
+                    &network_id,
+                )),
+                None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            };
         }
     }
+    stock::check(&state, &network_id).await;
 
-    if vouchers.is_empty() {
-        anyhow::bail!("No valid voucher codes found in CSV");
-    }
-
-    Ok(vouchers)
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
 }
 
-async fn list_vouchers(State(state): State<AppState>) -> Html<String> {
-    let vouchers = state.database.get_all_vouchers().await.unwrap_or_default();
-
-    if vouchers.is_empty() {
-        return Html(templates::no_vouchers_template());
-    }
+/// Delete a network's unused vouchers that expired before anyone printed them
+async fn delete_expired_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
 
-    let voucher_list = vouchers
-        .iter()
-        .enumerate()
-        .map(|(i, v)| {
-            let status = if v.is_used { "Used" } else { "Available" };
-            let status_class = if v.is_used { "text-danger" } else { "text-success" };
-            let used_at = match &v.used_at {
-                Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
-                None => "".to_string()
-            };
+    state
+        .database
+        .delete_expired_vouchers(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-            format!(
-                r#"<tr>
-                    <td>{}</td>
-                    <td><code>{}</code></td>
-                    <td><span class="{}">{}</span></td>
-                    <td>{}</td>
-                    <td>
-                        {}
-                    </td>
-                </tr>"#,
-                i + 1,
-                v.code,
-                status_class,
-                status,
-                used_at,
-                if v.is_used {
-                    format!(r#"<button class="btn btn-sm btn-warning" onclick="markUnused('{}')">Mark Unused</button>"#, v.id)
-                } else {
-                    format!(r#"<button class="btn btn-sm btn-success" onclick="markUsed('{}')">Mark Used</button>"#, v.id)
-                }
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    Html(format!(
-        r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Voucher List</title>
-            <link href="https://cdn.jsdelivr.net/npm/bootstrap@5.1.3/dist/css/bootstrap.min.css" rel="stylesheet">
-            <script>
-                function markUsed(id) {{
-                    fetch('/vouchers/' + id + '/use', {{ method: 'POST' }})
-                        .then(() => location.reload());
-                }}
-                function markUnused(id) {{
-                    fetch('/vouchers/' + id + '/unuse', {{ method: 'POST' }})
-                        .then(() => location.reload());
-                }}
-            </script>
-        </head>
-        <body>
-            <div class="container mt-4">
-                <h1>All Vouchers</h1>
-                <p class="text-muted">Total: {} vouchers</p>
-
-                <div class="mb-3">
-                    <a href="/admin" class="btn btn-secondary">Back to Admin</a>
-                    <a href="/generate" class="btn btn-success">Generate QR Codes</a>
-                </div>
-
-                <div class="table-responsive">
-                    <table class="table table-striped">
-                        <thead>
-                            <tr>
-                                <th>#</th>
-                                <th>Voucher Code</th>
-                                <th>Status</th>
-                                <th>Used At</th>
-                                <th>Actions</th>
-                            </tr>
-                        </thead>
-                        <tbody>
-                            {}
-                        </tbody>
-                    </table>
-                </div>
-            </div>
-        </body>
-        </html>
-        "#,
-        vouchers.len(),
-        voucher_list
-    ))
+    Ok(axum::response::Redirect::to(&format!(
+        "/admin/networks/{}/vouchers",
+        network_id
+    )))
 }
 
-async fn generate_vouchers(
+/// Save a network's pfSense voucher settings and RSA keys
+async fn save_pfsense_settings(
     State(state): State<AppState>,
-    Query(params): Query<GenerateQuery>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let network_id = match &params.network_id {
-        Some(i) => i,
-        None => return Err(StatusCode::PARTIAL_CONTENT),
-    };
+    auth::require_admin(&user)?;
 
-    // Get network from database
-    let network = state
+    let form_data = read_form(&mut multipart).await?;
+
+    state
         .database
-        .get_network(network_id)
+        .get_network(&network_id)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
         .ok_or(StatusCode::NOT_FOUND)?;
-
-    // Get voucher counts for display
-    let voucher_counts = state
+    let existing = state
         .database
-        .get_voucher_counts(network_id)
+        .get_pfsense_settings(&network_id)
         .await
-        .unwrap_or(database::VoucherCounts {
-            total: 0,
-            used: 0,
-            unused: 0,
-        });
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    if voucher_counts.total == 0 {
-        return Ok(Html(templates::no_vouchers_template()).into_response());
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    let defaults = RollSettings::default();
+    let roll = RollSettings {
+        roll_bits: parse_or(field("roll_bits"), defaults.roll_bits)?,
+        ticket_bits: parse_or(field("ticket_bits"), defaults.ticket_bits)?,
+        checksum_bits: parse_or(field("checksum_bits"), defaults.checksum_bits)?,
+        magic: parse_or(field("magic"), defaults.magic)?,
+        charset: match field("charset") {
+            "" => defaults.charset,
+            charset => charset.to_string(),
+        },
+    };
+    // A blank private key keeps the stored one, so it never has to be pasted again
+    let private_key = match field("private_key") {
+        "" => existing.and_then(|settings| settings.private_key),
+        pem => Some(pem.to_string()),
+    };
+    let public_key = field("public_key").to_string();
+
+    if let Err(error) = pfsense::check_settings(&roll, &public_key, private_key.as_deref()) {
+        return Ok(bad_request_page(
+            "pfSense Settings Not Saved",
+            format!("The pfSense settings were not saved: {}.", error),
+            &network_id,
+        ));
     }
 
-    // Return print selection page
-    let html_content = templates::print_selection_page(&network, &voucher_counts);
-    Ok(Html(html_content).into_response())
+    let settings = PfSenseSettings {
+        network_id: network_id.clone(),
+        roll,
+        public_key,
+        private_key,
+        updated_at: chrono::Utc::now(),
+    };
+    state
+        .database
+        .save_pfsense_settings(&settings)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
 }
 
-async fn print_vouchers(
+/// Save the look of a network's voucher cards, replacing or removing its logo
+async fn save_branding(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let mut network_id = String::new();
-    let mut count = 0usize;
+    auth::require_admin(&user)?;
 
+    let mut form_data = HashMap::new();
+    let mut upload = None;
     while let Some(field) = multipart
         .next_field()
         .await
         .map_err(|_| StatusCode::BAD_REQUEST)?
     {
         match field.name() {
-            Some("network_id") => {
-                network_id = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            Some("logo") => {
+                let data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                // Browsers send an empty file field when no file was chosen
+                if !data.is_empty() {
+                    upload = Some(data.to_vec());
+                }
             }
-            Some("count") => {
-                let count_str = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-                count = count_str.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+            Some(name) => {
+                let name = name.to_string();
+                let value = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                form_data.insert(name, value);
             }
-            _ => {}
+            None => {}
         }
     }
 
-    if network_id.is_empty() || count == 0 {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    // Get network from database
-    let network = state
+    let mut network = state
         .database
         .get_network(&network_id)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
         .ok_or(StatusCode::NOT_FOUND)?;
 
-    // Get unprinted vouchers up to the requested count
-    let vouchers = state
-        .database
-        .get_unused_vouchers_for_network(&network_id, Some(count))
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    let optional = |name: &str| Some(field(name).to_string()).filter(|value| !value.is_empty());
+    network.branding = Branding {
+        heading: field("heading").to_string(),
+        accent_color: field("accent_color").to_lowercase(),
+        background_color: field("background_color").to_lowercase(),
+        footer: optional("footer"),
+        contact: optional("contact"),
+        has_logo: network.branding.has_logo,
+    };
 
-    if vouchers.is_empty() {
-        return Ok(Html(templates::no_unused_vouchers_template()).into_response());
+    let logo = match network
+        .branding
+        .validate()
+        .and_then(|_| upload.map(Logo::from_upload).transpose())
+    {
+        Ok(logo) => logo,
+        Err(error) => {
+            return Ok(bad_request_page(
+                "Branding Not Saved",
+                format!("The branding was not saved: {}.", error),
+                &network_id,
+            ))
+        }
+    };
+
+    state
+        .database
+        .update_network(&network)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let logo_result = match logo {
+        Some(logo) => state.database.save_logo(&network_id, &logo).await,
+        None if field("remove_logo") == "true" => {
+            state.database.delete_logo(&network_id).await.map(|_| ())
+        }
+        None => Ok(()),
+    };
+    logo_result.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Serve a network's logo to anyone who may print its vouchers
+async fn network_logo(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_network_access(&state.database, &user, &network_id).await?;
+
+    let logo = state
+        .database
+        .get_logo(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, logo.content_type),
+            (header::CACHE_CONTROL, "private, max-age=300".to_string()),
+        ],
+        logo.data,
+    ))
+}
+
+/// Save a network's own QR settings, or go back to the global ones
+async fn save_qr_options(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    network.qr_options = if field("custom") == "true" {
+        let defaults = state.qr_generator.options();
+        let options = QrOptions {
+            error_correction: parse_or(field("error_correction"), defaults.error_correction)?,
+            module_size: parse_or(field("module_size"), defaults.module_size)?,
+            quiet_zone: parse_or(field("quiet_zone"), defaults.quiet_zone)?,
+            foreground: field("foreground").to_lowercase(),
+            background: field("background").to_lowercase(),
+            dpi: parse_or(field("dpi"), defaults.dpi)?,
+            image_format: parse_or(field("image_format"), defaults.image_format)?,
+        };
+        if let Err(error) = options.validate() {
+            return Ok(bad_request_page(
+                "QR Settings Not Saved",
+                format!("{}.", error),
+                &network_id,
+            ));
+        }
+        Some(options)
+    } else {
+        None
+    };
+
+    state
+        .database
+        .update_network(&network)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Change how clients join a network; an empty password keeps the current one
+async fn save_network_security(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    network.security = parse_or(field("security"), network.security)?;
+    network.hidden = field("hidden") == "true";
+    network.transition_disable = field("transition_disable") == "true";
+    if let Some(password) = form_data
+        .get("password")
+        .filter(|password| !password.is_empty())
+    {
+        network.password = password.clone();
+    }
+    if let Err(error) = network.validate_security() {
+        return Ok(bad_request_page(
+            "Network Security Not Saved",
+            format!("The network security was not saved: {}.", error),
+            &network_id,
+        ));
+    }
+
+    state
+        .database
+        .update_network(&network)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Save the captive portal login URL and which QR codes the cards show
+async fn save_portal_login(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    network.portal = PortalLogin {
+        mode: parse_or(field("mode"), PortalQrMode::Off)?,
+        url_template: Some(field("url_template").to_string()).filter(|url| !url.is_empty()),
+    };
+    if let Err(error) = network.portal.validate() {
+        return Ok(bad_request_page(
+            "Login QR Codes Not Saved",
+            format!("The login QR settings were not saved: {}.", error),
+            &network_id,
+        ));
+    }
+
+    state
+        .database
+        .update_network(&network)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Save the number of unused vouchers at which the network raises a low-stock alert
+async fn save_stock_alert(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let threshold = form_data
+        .get("low_stock_threshold")
+        .map(|value| value.trim())
+        .unwrap_or("");
+    network.low_stock_threshold = if threshold.is_empty() {
+        None
+    } else {
+        match threshold.parse() {
+            Ok(threshold) => Some(threshold),
+            Err(_) => {
+                return Ok(bad_request_page(
+                    "Alert Not Saved",
+                    format!("'{}' is not a number of vouchers.", threshold),
+                    &network_id,
+                ))
+            }
+        }
+    };
+
+    state
+        .database
+        .update_network(&network)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    stock::check(&state, &network_id).await;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Generate a pfSense voucher roll with the network's private key and store its codes
+async fn generate_pfsense_roll(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    let roll_number: u64 = field("roll_number")
+        .parse()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let count: u64 = field("count")
+        .parse()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let minutes: u32 = parse_or(field("minutes"), 0)?;
+
+    let settings = state
+        .database
+        .get_pfsense_settings(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(private_key) = settings
+        .as_ref()
+        .and_then(|settings| settings.private_key.as_deref())
+    else {
+        return Ok(bad_request_page(
+            "Roll Not Generated",
+            "Save the portal's private key in the pfSense settings before generating rolls."
+                .to_string(),
+            &network_id,
+        ));
+    };
+    let roll_settings = &settings.as_ref().ok_or(StatusCode::NOT_FOUND)?.roll;
+
+    let codes = match pfsense::parse_private_key(private_key)
+        .and_then(|key| pfsense::generate_roll(roll_settings, &key, roll_number, count))
+    {
+        Ok(codes) => codes,
+        Err(error) => {
+            return Ok(bad_request_page(
+                "Roll Not Generated",
+                format!("No vouchers were generated: {}.", error),
+                &network_id,
+            ))
+        }
+    };
+
+    let roll = VoucherRoll::new(network_id.clone(), roll_number, count, minutes);
+    if let Err(e) = state.database.create_roll(&roll, &codes).await {
+        return Ok(bad_request_page(
+            "Roll Not Generated",
+            format!("No vouchers were generated: {}.", e),
+            &network_id,
+        ));
+    }
+    state
+        .webhooks
+        .vouchers_imported(Some(&network_id), codes.len(), "pfsense_roll")
+        .await;
+    stock::check(&state, &network_id).await;
+
+    Ok(
+        axum::response::Redirect::to(&format!("/admin/networks/{}/vouchers", network_id))
+            .into_response(),
+    )
+}
+
+/// Check a voucher code against the network's pfSense public key
+async fn verify_pfsense_code(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let code = form_data
+        .get("code")
+        .map(|code| code.trim().to_string())
+        .unwrap_or_default();
+
+    let settings = state
+        .database
+        .get_pfsense_settings(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let ticket = match pfsense::parse_public_key(&settings.public_key)
+        .and_then(|key| pfsense::verify_code(&settings.roll, &key, &code))
+    {
+        Ok(ticket) => ticket,
+        Err(error) => {
+            return Ok(bad_request_page(
+                "Voucher Not Valid",
+                format!("{} is not a valid voucher: {}.", code, error),
+                &network_id,
+            ))
+        }
+    };
+
+    let stored = match state
+        .database
+        .find_voucher_by_code(&network_id, &code)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        Some(voucher) => format!(
+            "It is stored on this network as {}.",
+            voucher.state.label().to_lowercase()
+        ),
+        None => "It is not stored on this network.".to_string(),
+    };
+
+    Ok(ResponseTemplate::success(
+        "Voucher Valid",
+        format!(
+            "{} is ticket {} of roll {}. {}",
+            code, ticket.ticket, ticket.roll, stored
+        ),
+        0,
+        vec![network_button(&network_id)],
+    )
+    .into_response())
+}
+
+/// Download a network's vouchers as CSV or JSON
+async fn export_network_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let vouchers = state
+        .database
+        .get_vouchers_for_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    export_response(&state, vouchers, &query, &network_id).await
+}
+
+/// Download the vouchers of every network as CSV or JSON
+async fn export_all_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let vouchers = state
+        .database
+        .get_all_vouchers()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    export_response(&state, vouchers, &query, "all").await
+}
+
+async fn export_response(
+    state: &AppState,
+    vouchers: Vec<Voucher>,
+    query: &ExportQuery,
+    file_label: &str,
+) -> Result<Response, StatusCode> {
+    let (format, filter) = match query.parse() {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                ResponseTemplate::error(
+                    "Export Failed",
+                    format!("The vouchers could not be exported: {}.", error),
+                    vec![LinkButton::new(
+                        "/admin",
+                        "fas fa-cog",
+                        "Back to Admin",
+                        "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+                    )],
+                ),
+            )
+                .into_response())
+        }
+    };
+
+    let networks = state
+        .database
+        .get_all_networks()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = export::build_rows(vouchers, &networks, &filter);
+    let body = match format {
+        ExportFormat::Csv => export::to_csv(&rows),
+        ExportFormat::Json => export::to_json(&rows),
+    }
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let filename = format!(
+        "vouchers-{}-{}.{}",
+        file_label,
+        chrono::Utc::now().format("%Y-%m-%d"),
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Collect the text fields of a multipart form
+async fn read_form(multipart: &mut Multipart) -> Result<HashMap<String, String>, StatusCode> {
+    let mut form_data = HashMap::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        if let Some(name) = field.name() {
+            let name = name.to_string();
+            let value = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            form_data.insert(name, value);
+        }
+    }
+
+    Ok(form_data)
+}
+
+/// Parse a form value, falling back to `default` when it is blank
+fn parse_or<T: std::str::FromStr>(value: &str, default: T) -> Result<T, StatusCode> {
+    if value.is_empty() {
+        Ok(default)
+    } else {
+        value.parse().map_err(|_| StatusCode::BAD_REQUEST)
+    }
+}
+
+fn network_button(network_id: &str) -> LinkButton {
+    LinkButton::new(
+        format!("/admin/networks/{}/vouchers", network_id),
+        "fas fa-ticket-alt",
+        "Back to Network",
+        "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+    )
+}
+
+/// A 400 response explaining why a network action failed
+fn bad_request_page(title: &str, message: String, network_id: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        ResponseTemplate::error(title, message, vec![network_button(network_id)]),
+    )
+        .into_response()
+}
+
+async fn network_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+    Query(filter): Query<VoucherFilterQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let state_filter = filter
+        .state
+        .as_deref()
+        .filter(|value| !value.is_empty())
+        .map(str::parse::<VoucherState>)
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .unwrap_or(None);
+    let mut vouchers = state
+        .database
+        .get_vouchers_for_network(&network_id)
+        .await
+        .unwrap_or_default();
+    if let Some(state_filter) = state_filter {
+        vouchers.retain(|voucher| voucher.state == state_filter);
+    }
+    let voucher_counts = state
+        .database
+        .get_voucher_counts(&network_id)
+        .await
+        .unwrap_or_default();
+    let pfsense = state
+        .database
+        .get_pfsense_settings(&network_id)
+        .await
+        .unwrap_or(None);
+    let rolls = state
+        .database
+        .get_rolls_for_network(&network_id)
+        .await
+        .unwrap_or_default();
+
+    let qr_custom = network
+        .as_ref()
+        .is_some_and(|network| network.qr_options.is_some());
+    let network_qr_options = network
+        .as_ref()
+        .and_then(|network| network.qr_options.clone())
+        .unwrap_or_else(|| state.qr_generator.options().clone());
+
+    Ok(NetworkVouchersTemplate {
+        network,
+        network_id: &network_id,
+        vouchers,
+        voucher_counts,
+        voucher_states: &VoucherState::ALL,
+        state_filter,
+        roll_settings: pfsense
+            .as_ref()
+            .map(|settings| settings.roll.clone())
+            .unwrap_or_default(),
+        pfsense,
+        rolls,
+        qr_options: network_qr_options,
+        qr_custom,
+        error_correction_levels: &ErrorCorrection::ALL,
+        image_formats: &QrImageFormat::ALL,
+        security_types: &SecurityType::ALL,
+        portal_modes: &PortalQrMode::ALL,
+    }
+    .into_response())
+}
+
+async fn upload_csv(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        if field.name() == Some("csv_file") {
+            let data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            let csv_content =
+                String::from_utf8(data.to_vec()).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+            match process_csv_data(&csv_content).await {
+                Ok(vouchers) => {
+                    if state.database.create_vouchers(&vouchers).await.is_err() {
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                    state
+                        .webhooks
+                        .vouchers_imported(None, vouchers.len(), "csv")
+                        .await;
+
+                    let buttons = vec![
+                        LinkButton::new(
+                            "/vouchers",
+                            "fas fa-list",
+                            "View Vouchers",
+                            "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+                        ),
+                        LinkButton::new(
+                            "/generate",
+                            "fas fa-qrcode",
+                            "Generate QR Codes",
+                            "bg-gradient-to-r from-emerald-500 to-teal-600 hover:from-emerald-600 hover:to-teal-700",
+                        ),
+                        LinkButton::new(
+                            "/admin",
+                            "fas fa-cog",
+                            "Admin Panel",
+                            "bg-gradient-to-r from-purple-500 to-indigo-600 hover:from-purple-600 hover:to-indigo-700",
+                        ),
+                    ];
+
+                    return Ok((
+                        StatusCode::OK,
+                        ResponseTemplate::success(
+                            "CSV Uploaded Successfully!",
+                            format!("Your CSV file has been processed and {} voucher codes have been loaded into the system. You can now generate QR code vouchers for printing.", vouchers.len()),
+                            vouchers.len(),
+                            buttons,
+                        ),
+                    )
+                        .into_response());
+                }
+                Err(e) => {
+                    let buttons = vec![
+                        LinkButton::new(
+                            "/",
+                            "fas fa-arrow-left",
+                            "Go Back",
+                            "bg-gradient-to-r from-gray-500 to-gray-600 hover:from-gray-600 hover:to-gray-700",
+                        ),
+                        LinkButton::new(
+                            "/admin",
+                            "fas fa-cog",
+                            "Try Admin Panel",
+                            "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+                        ),
+                    ];
+
+                    return Ok((
+                        StatusCode::BAD_REQUEST,
+                        ResponseTemplate::error(
+                            "CSV Processing Failed",
+                            format!("We encountered an error while processing your CSV file: {}. Please check your file format and try again.", e),
+                            buttons,
+                        ),
+                    )
+                        .into_response());
+                }
+            }
+        }
+    }
+
+    Err(StatusCode::BAD_REQUEST)
+}
+
+async fn process_csv_data(csv_content: &str) -> anyhow::Result<Vec<Voucher>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(csv_content.as_bytes());
+    let mut vouchers = Vec::new();
+
+    // Validity columns are found by their header name, in any order after the code
+    let validity_columns: Vec<(usize, &str)> = reader
+        .headers()?
+        .iter()
+        .enumerate()
+        .filter_map(|(index, header)| {
+            VALIDITY_COLUMNS
+                .into_iter()
+                .find(|column| header.trim().eq_ignore_ascii_case(column))
+                .map(|column| (index, column))
+        })
+        .collect();
+
+    for result in reader.records() {
+        let record = result?;
+        if let Some(code) = record.get(0) {
+            let trimmed_code = code.trim();
+            // Skip empty lines and comment lines starting with #
+            if !trimmed_code.is_empty() && !trimmed_code.starts_with('#') {
+                let mut voucher = Voucher::new(trimmed_code.to_string());
+                validity_columns
+                    .iter()
+                    .try_for_each(|(index, column)| {
+                        voucher
+                            .validity
+                            .set(column, record.get(*index).unwrap_or_default())
+                    })
+                    .and_then(|_| voucher.validity.validate())
+                    .map_err(|e| anyhow::anyhow!("voucher {}: {}", trimmed_code, e))?;
+                vouchers.push(voucher);
+            }
+        }
+    }
+
+    if vouchers.is_empty() {
+        anyhow::bail!("No valid voucher codes found in CSV");
+    }
+
+    Ok(vouchers)
+}
+
+async fn list_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let vouchers = state.database.get_all_vouchers().await.unwrap_or_default();
+
+    if vouchers.is_empty() {
+        return Ok(NoVouchersTemplate.into_response());
+    }
+
+    Ok(VoucherListTemplate { vouchers }.into_response())
+}
+
+async fn generate_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Query(params): Query<GenerateQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let network_id = match &params.network_id {
+        Some(i) => i,
+        None => return Err(StatusCode::PARTIAL_CONTENT),
+    };
+
+    auth::require_network_access(&state.database, &user, network_id).await?;
+
+    // Get network from database
+    let network = state
+        .database
+        .get_network(network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Get voucher counts for display
+    let voucher_counts = state
+        .database
+        .get_voucher_counts(network_id)
+        .await
+        .unwrap_or_default();
+
+    if voucher_counts.total == 0 {
+        return Ok(NoVouchersTemplate.into_response());
+    }
+
+    // Return print selection page
+    Ok(PrintSelectionTemplate {
+        network: &network,
+        voucher_counts: &voucher_counts,
+        layouts: layout::PRESETS,
+        can_set_default: user.is_admin(),
+        reservation_minutes: state.printing.reservation_minutes,
+    }
+    .into_response())
+}
+
+async fn print_vouchers(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    let form_data = read_form(&mut multipart).await?;
+    let field = |name: &str| form_data.get(name).map(|value| value.trim()).unwrap_or("");
+    let network_id = field("network_id").to_string();
+    let count: usize = parse_or(field("count"), 0)?;
+    let output = parse_or(field("output"), PrintOutput::default())?;
+    let mut layout_id = field("layout").to_string();
+    let remember_layout = field("remember_layout") == "true";
+
+    if network_id.is_empty() || count == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    auth::require_network_access(&state.database, &user, &network_id).await?;
+
+    // Get network from database
+    let mut network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // An empty layout falls back to the network's default
+    if layout_id.is_empty() {
+        layout_id = network.layout.clone();
+    }
+    let Some(sheet_layout) = layout::preset(&layout_id) else {
+        return Ok(bad_request_page(
+            "Unknown Layout",
+            format!("There is no page layout called '{}'.", layout_id),
+            &network_id,
+        ));
+    };
+    if remember_layout && user.is_admin() && network.layout != sheet_layout.id {
+        network.layout = sheet_layout.id.to_string();
+        state
+            .database
+            .update_network(&network)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    // Hold the vouchers for the job; they are only used up once the print is confirmed
+    let job = PrintJob::reserve(
+        &network,
+        user.username.clone(),
+        sheet_layout.id.to_string(),
+        output,
+        state.printing.reservation(),
+    );
+    let vouchers = state
+        .database
+        .reserve_print_job(&job, count)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    stock::check(&state, &network.id).await;
+
+    if vouchers.is_empty() {
+        return Ok(NoUnusedVouchersTemplate.into_response());
+    }
+    state
+        .webhooks
+        .emit(
+            WebhookEvent::PrintJobCreated,
+            serde_json::json!({
+                "print_job_id": job.id,
+                "network_id": network.id,
+                "count": vouchers.len(),
+                "output": job.output.as_str(),
+                "created_by": user.username,
+            }),
+        )
+        .await;
+
+    // Redirect so refreshing the job page does not reserve another batch
+    Ok(axum::response::Redirect::to(&format!("/print-jobs/{}", job.id)).into_response())
+}
+
+/// Render voucher cards as a printable HTML page or a PDF
+async fn render_print_job(
+    state: &AppState,
+    network: &WiFiNetwork,
+    job: &PrintJob,
+    sheet_layout: &layout::SheetLayout,
+    logo: Option<&Logo>,
+) -> Result<Response, StatusCode> {
+    let vouchers = &job.vouchers;
+    // Generate WiFi QR code
+    let wifi_qr_data = network.qr_payload();
+    let qr_generator = state
+        .qr_generator
+        .with_override(network.qr_options.as_ref());
+    // Captive portal login codes, one per voucher
+    let login_urls: Vec<(String, String)> = vouchers
+        .iter()
+        .filter_map(|voucher| {
+            network
+                .portal
+                .login_url(&voucher.code)
+                .map(|url| (voucher.id.clone(), url))
+        })
+        .collect();
+
+    if job.output == PrintOutput::Escpos {
+        let data = receipt_data(state, network, job)?;
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"vouchers-{}.bin\"",
+                        chrono::Utc::now().format("%Y-%m-%d-%H%M%S")
+                    ),
+                ),
+            ],
+            data,
+        )
+            .into_response());
+    }
+
+    if job.output == PrintOutput::Pdf {
+        let modules = qr_generator
+            .generate_modules(&wifi_qr_data)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let login_qrs = login_urls
+            .into_iter()
+            .map(|(id, url)| Ok((id, qr_generator.generate_modules(&url)?)))
+            .collect::<Result<HashMap<_, _>, qr_generator::QrGeneratorError>>()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let pdf =
+            pdf::render_voucher_sheet(network, vouchers, &modules, &login_qrs, sheet_layout, logo)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/pdf".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "inline; filename=\"vouchers-{}.pdf\"",
+                        chrono::Utc::now().format("%Y-%m-%d-%H%M%S")
+                    ),
+                ),
+            ],
+            pdf,
+        )
+            .into_response());
+    }
+
+    let wifi_qr = match qr_generator.generate_image(&wifi_qr_data) {
+        Ok(qr) => qr,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let login_qrs = login_urls
+        .into_iter()
+        .map(|(id, url)| Ok((id, qr_generator.generate_image(&url)?)))
+        .collect::<Result<HashMap<_, _>, qr_generator::QrGeneratorError>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(VouchersTemplate {
+        network,
+        qr: &wifi_qr,
+        login_qrs: &login_qrs,
+        vouchers,
+        layout: sheet_layout,
+        job_id: &job.id,
+        reserved_job: (job.status == PrintJobStatus::Reserved).then_some(job.id.as_str()),
+    }
+    .into_response())
+}
+
+/// Print jobs shown on a network's history page
+const PRINT_HISTORY_LIMIT: usize = 100;
+
+/// A network's recent print jobs
+async fn print_history(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(network_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_network_access(&state.database, &user, &network_id).await?;
+
+    let network = state
+        .database
+        .get_network(&network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let jobs = state
+        .database
+        .get_print_jobs_for_network(&network_id, PRINT_HISTORY_LIMIT)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(PrintJobsTemplate {
+        network: &network,
+        jobs,
     }
+    .into_response())
+}
+
+/// Load a print job and its network, checking the user may print for it
+async fn load_print_job(
+    state: &AppState,
+    user: &User,
+    job_id: &str,
+) -> Result<(PrintJob, WiFiNetwork), StatusCode> {
+    let job = state
+        .database
+        .get_print_job(job_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    auth::require_network_access(&state.database, user, &job.network_id).await?;
 
-    // Mark these vouchers as used
-    let voucher_ids: Vec<String> = vouchers.iter().map(|v| v.id.clone()).collect();
-    let _ = state.database.mark_vouchers_as_used(&voucher_ids).await;
+    let network = state
+        .database
+        .get_network(&job.network_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok((job, network))
+}
 
-    // Generate WiFi QR code
-    let wifi_qr_data = format!(
-        "WIFI:T:WPA;S:{};P:{};H:false;;",
-        network.ssid, network.password
-    );
+/// A print job's status, with the buttons to open, confirm or release it
+async fn print_job_page(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, network) = load_print_job(&state, &user, &job_id).await?;
 
-    let wifi_qr_base64 = match state.qr_generator.generate_qr_base64(&wifi_qr_data) {
-        Ok(qr) => qr,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    Ok(PrintJobTemplate {
+        network: &network,
+        job: &job,
+        receipt_printer: state.receipt_printer.address.as_deref(),
+    }
+    .into_response())
+}
+
+/// Render a print job's cards. Reserved jobs can be previewed and printed as
+/// often as needed, confirmed ones reprinted without using up new vouchers,
+/// with the layout and card settings they were first printed with.
+async fn print_job_sheet(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, network) = load_print_job(&state, &user, &job_id).await?;
+    let logo = load_print_job_logo(&state, &job, &network).await?;
+    let mut network = job.network_as_printed(&network);
+    network.branding.has_logo = logo.is_some();
+
+    if !job.status.can_print() {
+        return Ok(print_job_ended_page(&job));
+    }
+    if job.vouchers.is_empty() {
+        return Ok(NoVouchersTemplate.into_response());
+    }
+    // Fall back to the network's layout if the job's preset has been removed
+    let sheet_layout = layout::preset(&job.layout)
+        .or_else(|| layout::preset(&network.layout))
+        .or_else(|| layout::preset(layout::DEFAULT_LAYOUT))
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    render_print_job(&state, &network, &job, sheet_layout, logo.as_ref()).await
+}
+
+/// The logo a job's cards are printed with: the copy taken when the job was
+/// created, or the network's current logo for jobs from before copies were kept
+async fn load_print_job_logo(
+    state: &AppState,
+    job: &PrintJob,
+    network: &WiFiNetwork,
+) -> Result<Option<Logo>, StatusCode> {
+    let logo = if job.settings.is_some() {
+        state.database.get_print_job_logo(&job.id).await
+    } else if network.branding.has_logo {
+        state.database.get_logo(&network.id).await
+    } else {
+        Ok(None)
     };
+    logo.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
 
-    // Generate voucher cards using template
-    let voucher_cards = vouchers
-        .iter()
-        .map(|voucher| {
-            templates::generate_voucher_card(
-                &wifi_qr_base64,
-                &network.ssid,
-                &network.name,
-                &network.password,
-                &voucher.code,
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let html_content = templates::generate_vouchers_page(
-        vouchers.len(),
-        &network.name,
-        &network.ssid,
-        &voucher_cards,
+/// Serve the logo shown on a print job's sheet
+async fn print_job_logo(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, network) = load_print_job(&state, &user, &job_id).await?;
+    let logo = load_print_job_logo(&state, &job, &network)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, logo.content_type),
+            (header::CACHE_CONTROL, "private, max-age=300".to_string()),
+        ],
+        logo.data,
+    ))
+}
+
+/// Use up a reserved job's vouchers once the operator has checked the print
+async fn confirm_print_job(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, _) = load_print_job(&state, &user, &job_id).await?;
+
+    // Confirming twice, e.g. from the sheet and the job page, is harmless
+    if job.status != PrintJobStatus::Confirmed {
+        let confirmed = state
+            .database
+            .confirm_print_job(&job.id, &user.username)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if !confirmed {
+            return Ok(print_job_ended_page(&job));
+        }
+    }
+
+    Ok(axum::response::Redirect::to(&format!("/print-jobs/{}", job.id)).into_response())
+}
+
+/// Give a reserved job's vouchers back, e.g. when the print is abandoned
+async fn release_print_job(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, _) = load_print_job(&state, &user, &job_id).await?;
+
+    state
+        .database
+        .release_print_job(&job.id, &user.username)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    stock::check(&state, &job.network_id).await;
+
+    Ok(axum::response::Redirect::to(&format!(
+        "/print-jobs/{}",
+        job.id
+    )))
+}
+
+/// ESC/POS print data for a job's vouchers, one receipt each
+fn receipt_data(
+    state: &AppState,
+    network: &WiFiNetwork,
+    job: &PrintJob,
+) -> Result<Vec<u8>, StatusCode> {
+    let paper = state
+        .receipt_printer
+        .paper_width()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let qr_generator = state
+        .qr_generator
+        .with_override(network.qr_options.as_ref());
+    escpos::render_receipts(network, &job.vouchers, &qr_generator, paper)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Send a receipt job straight to the configured receipt printer
+async fn send_print_job(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (job, network) = load_print_job(&state, &user, &job_id).await?;
+    let network = job.network_as_printed(&network);
+
+    if !job.status.can_print() {
+        return Ok(print_job_ended_page(&job));
+    }
+    if job.output != PrintOutput::Escpos {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let job_button = LinkButton::new(
+        format!("/print-jobs/{}", job.id),
+        "fas fa-print",
+        "Back to Print Job",
+        "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
     );
+    let Some(address) = state.receipt_printer.printer_address() else {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            ResponseTemplate::error(
+                "No Receipt Printer",
+                "No receipt printer is configured. Set address in the [receipt_printer] section of config.toml, or download the receipts instead.",
+                vec![job_button],
+            ),
+        )
+            .into_response());
+    };
+
+    let data = receipt_data(&state, &network, &job)?;
+    if let Err(error) = escpos::send(&address, &data).await {
+        return Ok((
+            StatusCode::BAD_GATEWAY,
+            ResponseTemplate::error("Printing Failed", error.to_string(), vec![job_button]),
+        )
+            .into_response());
+    }
+
+    let message = match job.status {
+        PrintJobStatus::Reserved => format!(
+            "The receipts were sent to the printer at {}. Once they have printed, click \"Printed OK\" on the print job.",
+            address
+        ),
+        _ => format!("The receipts were sent to the printer at {}.", address),
+    };
+    Ok(ResponseTemplate::success(
+        "Sent to Printer",
+        message,
+        job.vouchers.len(),
+        vec![job_button],
+    )
+    .into_response())
+}
 
-    Ok(Html(html_content).into_response())
+/// Explains that a job's vouchers went back to the pool and cannot be printed
+fn print_job_ended_page(job: &PrintJob) -> Response {
+    let message = match job.status {
+        PrintJobStatus::Released => "This print job was released and its vouchers went back to the unused pool. Print a new batch instead.",
+        _ => "Nobody confirmed this print job in time, so its vouchers went back to the unused pool. Print a new batch instead.",
+    };
+    (
+        StatusCode::CONFLICT,
+        ResponseTemplate::error(
+            "Reservation Ended",
+            message,
+            vec![LinkButton::new(
+                format!("/generate?network_id={}", job.network_id),
+                "fas fa-print",
+                "Print Vouchers",
+                "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+            )],
+        ),
+    )
+        .into_response()
 }
 
 // Handler for marking voucher as used
 async fn mark_voucher_used(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     Path(voucher_id): Path<String>,
-) -> impl IntoResponse {
-    let _ = state.database.mark_voucher_as_used(&voucher_id).await;
-    axum::response::Redirect::to("/vouchers")
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    set_voucher_used(&state, &voucher_id, VoucherState::Issued, &user.username).await;
+    Ok(axum::response::Redirect::to("/vouchers"))
 }
 
 // Handler for marking voucher as unused
 async fn mark_voucher_unused(
     State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
     Path(voucher_id): Path<String>,
-) -> impl IntoResponse {
-    let _ = state.database.mark_voucher_as_unused(&voucher_id).await;
-    axum::response::Redirect::to("/vouchers")
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    set_voucher_used(&state, &voucher_id, VoucherState::Available, &user.username).await;
+    Ok(axum::response::Redirect::to("/vouchers"))
+}
+
+/// Move a voucher from the voucher list to `new_state`, ignoring changes its
+/// current state does not allow
+async fn set_voucher_used(state: &AppState, voucher_id: &str, new_state: VoucherState, by: &str) {
+    let Ok(Some(voucher)) = state.database.get_voucher(voucher_id).await else {
+        return;
+    };
+    if let Ok(Some(changed)) = state
+        .database
+        .set_voucher_state(voucher_id, new_state, by)
+        .await
+    {
+        state
+            .webhooks
+            .voucher_changed(voucher.state.is_used(), &changed, by)
+            .await;
+        if let Some(network_id) = &changed.network_id {
+            stock::check(state, network_id).await;
+        }
+    }
+}
+
+// User management handlers
+async fn users_page(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let users = state
+        .database
+        .get_all_users()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let networks = state.database.get_all_networks().await.unwrap_or_default();
+
+    let mut summaries = Vec::new();
+    for listed_user in users {
+        let network_ids = state
+            .database
+            .get_user_network_ids(&listed_user.id)
+            .await
+            .unwrap_or_default();
+        summaries.push(UserSummary {
+            user: listed_user,
+            network_ids,
+        });
+    }
+
+    Ok(UsersTemplate {
+        current_user: &user,
+        users: summaries,
+        networks,
+    }
+    .into_response())
+}
+
+/// Read a form, collecting the repeated `list_field` checkboxes separately
+async fn read_checkbox_form(
+    mut multipart: Multipart,
+    list_field: &str,
+) -> Result<(HashMap<String, String>, Vec<String>), StatusCode> {
+    let mut form_data: HashMap<String, String> = HashMap::new();
+    let mut checked = Vec::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        if let Some(name) = field.name() {
+            let name = name.to_string();
+            let value = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            if name == list_field {
+                checked.push(value);
+            } else {
+                form_data.insert(name, value);
+            }
+        }
+    }
+
+    Ok((form_data, checked))
+}
+
+async fn create_user(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let (form_data, network_ids) = read_checkbox_form(multipart, "network_ids").await?;
+
+    let username = form_data
+        .get("username")
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let password = form_data
+        .get("password")
+        .filter(|password| !password.is_empty())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let role = form_data
+        .get("role")
+        .map(|role| role.parse::<Role>())
+        .unwrap_or(Ok(Role::Operator))
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let password_hash =
+        auth::hash_password(password).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let new_user = User::new(username, password_hash, role);
+
+    if state.database.create_user(&new_user).await.is_err() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if role == Role::Operator
+        && state
+            .database
+            .set_user_networks(&new_user.id, &network_ids)
+            .await
+            .is_err()
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok(axum::response::Redirect::to("/admin/users"))
+}
+
+async fn delete_user(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(user_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    // Never let an administrator lock themselves out
+    if user_id == user.id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let _ = state.database.delete_user(&user_id).await;
+
+    Ok(axum::response::Redirect::to("/admin/users"))
+}
+
+async fn update_user_networks(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(user_id): Path<String>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let (_, network_ids) = read_checkbox_form(multipart, "network_ids").await?;
+
+    state
+        .database
+        .get_user(&user_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    state
+        .database
+        .set_user_networks(&user_id, &network_ids)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(axum::response::Redirect::to("/admin/users"))
+}
+
+// Webhook management handlers
+async fn webhooks_page(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let webhooks = state
+        .database
+        .get_webhooks()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(WebhooksTemplate {
+        current_user: &user,
+        webhooks,
+        events: &WebhookEvent::ALL,
+    }
+    .into_response())
+}
+
+fn webhooks_button() -> LinkButton {
+    LinkButton::new(
+        "/admin/webhooks",
+        "fas fa-satellite-dish",
+        "Back to Webhooks",
+        "bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700",
+    )
+}
+
+async fn create_webhook(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    multipart: Multipart,
+) -> Result<Response, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let (form_data, events) = read_checkbox_form(multipart, "events").await?;
+    let url = form_data
+        .get("url")
+        .map(|url| url.trim().to_string())
+        .unwrap_or_default();
+    let secret = form_data.get("secret").cloned().unwrap_or_default();
+    let events = events
+        .iter()
+        .map(|event| event.parse::<WebhookEvent>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let webhook = match Webhook::new(url, secret, events) {
+        Ok(webhook) => webhook,
+        Err(error) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                ResponseTemplate::error(
+                    "Invalid Webhook",
+                    error.to_string(),
+                    vec![webhooks_button()],
+                ),
+            )
+                .into_response())
+        }
+    };
+    state
+        .database
+        .create_webhook(&webhook)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(axum::response::Redirect::to(&format!("/admin/webhooks/{}", webhook.id)).into_response())
+}
+
+async fn webhook_page(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(webhook_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let webhook = state
+        .database
+        .get_webhook(&webhook_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let deliveries = state
+        .database
+        .get_webhook_deliveries(&webhook_id, WEBHOOK_LOG_SIZE)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(WebhookTemplate {
+        current_user: &user,
+        webhook,
+        deliveries,
+    }
+    .into_response())
+}
+
+/// How many recent deliveries the webhook page lists
+const WEBHOOK_LOG_SIZE: usize = 100;
+
+/// Pause or resume a webhook; a paused webhook gets no new events and its
+/// queued deliveries wait
+async fn set_webhook_active(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(webhook_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let form_data = read_form(&mut multipart).await?;
+    let is_active = form_data.get("active").is_some_and(|value| value == "true");
+    if !state
+        .database
+        .set_webhook_active(&webhook_id, is_active)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if is_active {
+        state.webhooks.wake();
+    }
+
+    Ok(axum::response::Redirect::to(&format!(
+        "/admin/webhooks/{}",
+        webhook_id
+    )))
+}
+
+async fn delete_webhook(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path(webhook_id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let _ = state.database.delete_webhook(&webhook_id).await;
+
+    Ok(axum::response::Redirect::to("/admin/webhooks"))
+}
+
+/// Queue a delivery again right away, with a fresh set of attempts
+async fn retry_webhook_delivery(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user)): Extension<CurrentUser>,
+    Path((webhook_id, delivery_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&user)?;
+
+    let mut delivery = state
+        .database
+        .get_webhook_delivery(&delivery_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .filter(|delivery| delivery.webhook_id == webhook_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    delivery.retry(chrono::Utc::now());
+    state
+        .database
+        .update_webhook_delivery(&delivery)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks.wake();
+
+    Ok(axum::response::Redirect::to(&format!(
+        "/admin/webhooks/{}",
+        webhook_id
+    )))
 }
 
 #[cfg(test)]
@@ -631,6 +2495,25 @@ mod tests {
         assert_eq!(vouchers[1].code, "VOUCHER003");
     }
 
+    #[tokio::test]
+    async fn test_csv_processing_validity_columns() {
+        let csv_data =
+            "code,Expires_At,duration_minutes\nHOTEL-001,2026-12-31,1440\n# Comment\nHOTEL-002,,\n";
+
+        let vouchers = process_csv_data(csv_data).await.unwrap();
+        assert_eq!(vouchers.len(), 2);
+        assert_eq!(
+            vouchers[0].validity.card_lines(),
+            vec!["Valid for 24 hours", "Use by 31 Dec 2026"]
+        );
+        assert!(vouchers[1].validity.is_empty());
+
+        let error = process_csv_data("code,duration_minutes\nHOTEL-001,0")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("HOTEL-001"));
+    }
+
     #[tokio::test]
     async fn test_csv_processing_only_comments() {
         let csv_data = "# Comment 1\n# Comment 2\n# Comment 3";
@@ -0,0 +1,577 @@
+//! Versioned schema migrations.
+//!
+//! Every schema change is appended to `MIGRATIONS` with the next version number and
+//! is never edited once released. Each migration runs in its own transaction together
+//! with the `schema_version` row that records it, so a failed upgrade leaves the
+//! database at the last good version.
+
+use anyhow::{bail, Context, Result};
+use sqlx::{sqlite::SqlitePool, Executor, Row};
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create networks and vouchers",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS wifi_networks (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                ssid TEXT NOT NULL,
+                password TEXT NOT NULL,
+                description TEXT,
+                created_at TEXT NOT NULL,
+                is_active BOOLEAN NOT NULL DEFAULT TRUE
+            );
+
+            CREATE TABLE IF NOT EXISTS vouchers (
+                id TEXT PRIMARY KEY,
+                code TEXT NOT NULL UNIQUE,
+                network_id TEXT,
+                created_at TEXT NOT NULL,
+                is_used BOOLEAN NOT NULL DEFAULT FALSE,
+                used_at TEXT,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_vouchers_network_id ON vouchers(network_id);
+            CREATE INDEX IF NOT EXISTS idx_vouchers_is_used ON vouchers(is_used);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "Drop the removed is_printed and printed_at voucher columns",
+        sql: r#"
+            DROP INDEX IF EXISTS idx_vouchers_is_printed;
+
+            CREATE TABLE vouchers_new (
+                id TEXT PRIMARY KEY,
+                code TEXT NOT NULL UNIQUE,
+                network_id TEXT,
+                created_at TEXT NOT NULL,
+                is_used BOOLEAN NOT NULL DEFAULT FALSE,
+                used_at TEXT,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+
+            INSERT INTO vouchers_new (id, code, network_id, created_at, is_used, used_at)
+            SELECT id, code, network_id, created_at, is_used, used_at FROM vouchers;
+
+            DROP TABLE vouchers;
+            ALTER TABLE vouchers_new RENAME TO vouchers;
+
+            CREATE INDEX idx_vouchers_network_id ON vouchers(network_id);
+            CREATE INDEX idx_vouchers_is_used ON vouchers(is_used);
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "Create users, sessions and operator network assignments",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id TEXT PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL DEFAULT 'admin',
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS user_networks (
+                user_id TEXT NOT NULL,
+                network_id TEXT NOT NULL,
+                PRIMARY KEY (user_id, network_id),
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE IF NOT EXISTS sessions (
+                token TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            );
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "Add pfSense voucher settings and rolls",
+        sql: r#"
+            CREATE TABLE pfsense_settings (
+                network_id TEXT PRIMARY KEY,
+                roll_bits INTEGER NOT NULL,
+                ticket_bits INTEGER NOT NULL,
+                checksum_bits INTEGER NOT NULL,
+                magic INTEGER NOT NULL,
+                charset TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                private_key TEXT,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE voucher_rolls (
+                id TEXT PRIMARY KEY,
+                network_id TEXT NOT NULL,
+                roll_number INTEGER NOT NULL,
+                ticket_count INTEGER NOT NULL,
+                minutes INTEGER NOT NULL,
+                created_at TEXT NOT NULL,
+                UNIQUE (network_id, roll_number),
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+
+            ALTER TABLE vouchers ADD COLUMN roll_id TEXT
+                REFERENCES voucher_rolls (id) ON DELETE CASCADE;
+            CREATE INDEX idx_vouchers_roll_id ON vouchers(roll_id);
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "Add a default print layout per network",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN layout TEXT NOT NULL DEFAULT 'a4';
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "Add voucher card branding per network",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN brand_heading TEXT NOT NULL DEFAULT 'WiFi Access Voucher';
+            ALTER TABLE wifi_networks ADD COLUMN brand_accent_color TEXT NOT NULL DEFAULT '#3b82f6';
+            ALTER TABLE wifi_networks ADD COLUMN brand_background_color TEXT NOT NULL DEFAULT '#eff6ff';
+            ALTER TABLE wifi_networks ADD COLUMN brand_footer TEXT;
+            ALTER TABLE wifi_networks ADD COLUMN brand_contact TEXT;
+
+            CREATE TABLE network_logos (
+                network_id TEXT PRIMARY KEY,
+                content_type TEXT NOT NULL,
+                data BLOB NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "Add per-network QR code options",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN qr_options TEXT;
+        "#,
+    },
+    Migration {
+        version: 8,
+        description: "Add network security type and hidden SSID flag",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN security TEXT NOT NULL DEFAULT 'wpa2';
+            ALTER TABLE wifi_networks ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT FALSE;
+            ALTER TABLE wifi_networks ADD COLUMN transition_disable BOOLEAN NOT NULL DEFAULT FALSE;
+        "#,
+    },
+    Migration {
+        version: 9,
+        description: "Add captive portal login QR codes",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN portal_qr_mode TEXT NOT NULL DEFAULT 'off';
+            ALTER TABLE wifi_networks ADD COLUMN portal_url TEXT;
+        "#,
+    },
+    Migration {
+        version: 10,
+        description: "Add print job history",
+        sql: r#"
+            CREATE TABLE print_jobs (
+                id TEXT PRIMARY KEY,
+                network_id TEXT NOT NULL,
+                printed_by TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                layout TEXT NOT NULL,
+                output TEXT NOT NULL,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_print_jobs_network_id ON print_jobs(network_id, created_at);
+
+            CREATE TABLE print_job_vouchers (
+                job_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                voucher_id TEXT NOT NULL,
+                PRIMARY KEY (job_id, position),
+                FOREIGN KEY (job_id) REFERENCES print_jobs (id) ON DELETE CASCADE,
+                FOREIGN KEY (voucher_id) REFERENCES vouchers (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_print_job_vouchers_voucher_id ON print_job_vouchers(voucher_id);
+        "#,
+    },
+    Migration {
+        version: 11,
+        description: "Reserve print job vouchers until the print is confirmed",
+        sql: r#"
+            -- Jobs printed before this version used their vouchers right away
+            ALTER TABLE print_jobs ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+            ALTER TABLE print_jobs ADD COLUMN expires_at TEXT;
+            CREATE INDEX idx_print_jobs_status ON print_jobs(status, expires_at);
+        "#,
+    },
+    Migration {
+        version: 12,
+        description: "Add voucher validity and limits",
+        sql: r#"
+            ALTER TABLE vouchers ADD COLUMN duration_minutes INTEGER;
+            ALTER TABLE vouchers ADD COLUMN expires_at TEXT;
+            ALTER TABLE vouchers ADD COLUMN data_limit_mb INTEGER;
+            ALTER TABLE vouchers ADD COLUMN download_kbps INTEGER;
+            ALTER TABLE vouchers ADD COLUMN upload_kbps INTEGER;
+            CREATE INDEX idx_vouchers_expires_at ON vouchers(expires_at);
+        "#,
+    },
+    Migration {
+        version: 13,
+        description: "Track voucher lifecycle states and their transitions",
+        sql: r#"
+            ALTER TABLE vouchers ADD COLUMN state TEXT NOT NULL DEFAULT 'available';
+            ALTER TABLE vouchers ADD COLUMN state_changed_at TEXT;
+            ALTER TABLE vouchers ADD COLUMN state_changed_by TEXT;
+
+            -- Used vouchers were either printed or handed out through the API
+            UPDATE vouchers SET state = 'printed'
+            WHERE is_used = TRUE AND id IN (
+                SELECT j.voucher_id FROM print_job_vouchers j JOIN print_jobs p ON p.id = j.job_id
+                WHERE p.status = 'confirmed'
+            );
+            UPDATE vouchers SET state = 'issued' WHERE is_used = TRUE AND state = 'available';
+            UPDATE vouchers SET state = 'reserved'
+            WHERE is_used = FALSE AND id IN (
+                SELECT j.voucher_id FROM print_job_vouchers j JOIN print_jobs p ON p.id = j.job_id
+                WHERE p.status = 'reserved'
+            );
+            UPDATE vouchers SET state_changed_at = COALESCE(used_at, created_at);
+            CREATE INDEX idx_vouchers_state ON vouchers(network_id, state);
+
+            CREATE TABLE voucher_transitions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                voucher_id TEXT NOT NULL,
+                from_state TEXT NOT NULL,
+                to_state TEXT NOT NULL,
+                changed_at TEXT NOT NULL,
+                changed_by TEXT,
+                FOREIGN KEY (voucher_id) REFERENCES vouchers (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_voucher_transitions_voucher_id ON voucher_transitions(voucher_id, id);
+
+            -- Every state change is logged, whichever statement makes it
+            CREATE TRIGGER voucher_state_transition AFTER UPDATE OF state ON vouchers
+            WHEN OLD.state <> NEW.state
+            BEGIN
+                INSERT INTO voucher_transitions (voucher_id, from_state, to_state, changed_at, changed_by)
+                VALUES (NEW.id, OLD.state, NEW.state, NEW.state_changed_at, NEW.state_changed_by);
+            END;
+        "#,
+    },
+    Migration {
+        version: 14,
+        description: "Record when and by which device vouchers are redeemed",
+        sql: r#"
+            ALTER TABLE vouchers ADD COLUMN redeemed_at TEXT;
+            ALTER TABLE vouchers ADD COLUMN client_mac TEXT;
+            ALTER TABLE vouchers ADD COLUMN client_ip TEXT;
+        "#,
+    },
+    Migration {
+        version: 15,
+        description: "Record RADIUS accounting sessions of redeemed vouchers",
+        sql: r#"
+            CREATE TABLE voucher_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                voucher_id TEXT NOT NULL,
+                session_id TEXT NOT NULL,
+                nas TEXT NOT NULL,
+                client_mac TEXT,
+                client_ip TEXT,
+                started_at TEXT NOT NULL,
+                stopped_at TEXT,
+                session_seconds INTEGER NOT NULL DEFAULT 0,
+                input_bytes INTEGER NOT NULL DEFAULT 0,
+                output_bytes INTEGER NOT NULL DEFAULT 0,
+                UNIQUE (nas, session_id),
+                FOREIGN KEY (voucher_id) REFERENCES vouchers (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_voucher_sessions_voucher_id ON voucher_sessions(voucher_id);
+        "#,
+    },
+    Migration {
+        version: 16,
+        description: "Add low-stock alert thresholds and raised alerts",
+        sql: r#"
+            ALTER TABLE wifi_networks ADD COLUMN low_stock_threshold INTEGER;
+
+            CREATE TABLE stock_alerts (
+                network_id TEXT PRIMARY KEY,
+                raised_at TEXT NOT NULL,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+        "#,
+    },
+    Migration {
+        version: 17,
+        description: "Add outgoing webhooks and their delivery queue",
+        sql: r#"
+            CREATE TABLE webhooks (
+                id TEXT PRIMARY KEY,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                events TEXT NOT NULL DEFAULT '',
+                is_active BOOLEAN NOT NULL DEFAULT TRUE,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE webhook_deliveries (
+                id TEXT PRIMARY KEY,
+                webhook_id TEXT NOT NULL,
+                event TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TEXT,
+                response_status INTEGER,
+                last_error TEXT,
+                created_at TEXT NOT NULL,
+                delivered_at TEXT,
+                FOREIGN KEY (webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+            CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, created_at);
+        "#,
+    },
+    Migration {
+        version: 18,
+        description: "Store timestamps as UTC with milliseconds so they compare as strings",
+        sql: r#"
+            UPDATE wifi_networks SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at);
+            UPDATE vouchers SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at),
+                used_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', used_at), used_at),
+                expires_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', expires_at), expires_at),
+                state_changed_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', state_changed_at), state_changed_at),
+                redeemed_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', redeemed_at), redeemed_at);
+            UPDATE users SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at);
+            UPDATE sessions SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at),
+                expires_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', expires_at), expires_at);
+            UPDATE pfsense_settings SET
+                updated_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', updated_at), updated_at);
+            UPDATE voucher_rolls SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at);
+            UPDATE network_logos SET
+                updated_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', updated_at), updated_at);
+            UPDATE print_jobs SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at),
+                expires_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', expires_at), expires_at);
+            UPDATE voucher_transitions SET
+                changed_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', changed_at), changed_at);
+            UPDATE voucher_sessions SET
+                started_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', started_at), started_at),
+                stopped_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', stopped_at), stopped_at);
+            UPDATE stock_alerts SET
+                raised_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', raised_at), raised_at);
+            UPDATE webhooks SET
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at);
+            UPDATE webhook_deliveries SET
+                next_attempt_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', next_attempt_at), next_attempt_at),
+                created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', created_at), created_at),
+                delivered_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%fZ', delivered_at), delivered_at);
+        "#,
+    },
+    Migration {
+        version: 19,
+        description: "Record the card settings each print job was created with",
+        sql: r#"
+            ALTER TABLE print_jobs ADD COLUMN settings TEXT;
+        "#,
+    },
+    Migration {
+        version: 20,
+        description: "Keep a copy of the logo each print job was created with",
+        sql: r#"
+            CREATE TABLE print_job_logos (
+                job_id TEXT PRIMARY KEY,
+                content_type TEXT NOT NULL,
+                data BLOB NOT NULL,
+                FOREIGN KEY (job_id) REFERENCES print_jobs (id) ON DELETE CASCADE
+            );
+        "#,
+    },
+];
+
+/// The schema version this build expects
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// The schema version recorded in the database, 0 for a new or unversioned database
+pub async fn current_version(pool: &SqlitePool) -> Result<i64> {
+    pool.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .await?;
+
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.get("version"))
+}
+
+/// Apply every migration newer than the database's version.
+/// Refuses to touch a database written by a newer build.
+pub async fn run(pool: &SqlitePool) -> Result<usize> {
+    let current = current_version(pool).await?;
+    let latest = latest_version();
+
+    if current > latest {
+        bail!(
+            "Database schema version {} is newer than this build supports (version {}). \
+             Upgrade voucher_creator before using this database.",
+            current,
+            latest
+        );
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+
+    for migration in &pending {
+        let mut tx = pool.begin().await?;
+
+        (&mut *tx).execute(migration.sql).await.with_context(|| {
+            format!(
+                "Failed to apply migration {}: {}",
+                migration.version, migration.description
+            )
+        })?;
+
+        sqlx::query(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+        )
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        println!(
+            "Applied migration {}: {}",
+            migration.version, migration.description
+        );
+    }
+
+    Ok(pending.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_versions_are_sequential() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_is_idempotent() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        assert_eq!(run(&pool).await.unwrap(), MIGRATIONS.len());
+        assert_eq!(run(&pool).await.unwrap(), 0);
+        assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_legacy_voucher_table() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        pool.execute(
+            r#"
+            CREATE TABLE wifi_networks (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                ssid TEXT NOT NULL,
+                password TEXT NOT NULL,
+                description TEXT,
+                created_at TEXT NOT NULL,
+                is_active BOOLEAN NOT NULL DEFAULT TRUE
+            );
+            CREATE TABLE vouchers (
+                id TEXT PRIMARY KEY,
+                code TEXT NOT NULL UNIQUE,
+                network_id TEXT,
+                created_at TEXT NOT NULL,
+                is_used BOOLEAN NOT NULL DEFAULT FALSE,
+                used_at TEXT, is_printed BOOLEAN NOT NULL DEFAULT FALSE, printed_at TEXT,
+                FOREIGN KEY (network_id) REFERENCES wifi_networks (id) ON DELETE CASCADE
+            );
+            CREATE INDEX idx_vouchers_is_printed ON vouchers(is_printed);
+            INSERT INTO vouchers (id, code, network_id, created_at, is_used, is_printed)
+            VALUES ('v1', 'ABC123', NULL, '2024-01-01T00:00:00Z', TRUE, TRUE);
+            "#,
+        )
+        .await
+        .unwrap();
+
+        run(&pool).await.unwrap();
+
+        let columns: Vec<String> = sqlx::query("PRAGMA table_info(vouchers)")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        assert!(!columns.contains(&"is_printed".to_string()));
+        assert!(!columns.contains(&"printed_at".to_string()));
+
+        let row = sqlx::query(
+            "SELECT code, is_used, state, state_changed_at FROM vouchers WHERE id = 'v1'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.get::<String, _>("code"), "ABC123");
+        assert!(row.get::<bool, _>("is_used"));
+        // Used outside of any print job, so it was handed out
+        assert_eq!(row.get::<String, _>("state"), "issued");
+        // Rewritten in the fixed timestamp format
+        assert_eq!(
+            row.get::<String, _>("state_changed_at"),
+            "2024-01-01T00:00:00.000Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_schema() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, 'future', '')",
+        )
+        .bind(latest_version() + 1)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let error = run(&pool).await.unwrap_err();
+        assert!(error.to_string().contains("newer than this build supports"));
+    }
+}
@@ -0,0 +1,597 @@
+//! Server-side PDF rendering of voucher sheets.
+//!
+//! Cards are drawn with the PDF base fonts and the QR code as vector squares, so
+//! the output looks the same in every viewer and on every printer.
+
+use crate::branding::{Branding, Logo};
+use crate::layout::{SheetLayout, QR_GAP};
+use crate::qr_generator::QrModules;
+use crate::voucher::Voucher;
+use crate::wifi_network::WiFiNetwork;
+use image::{DynamicImage, RgbImage};
+use printpdf::path::PaintMode;
+use printpdf::{
+    BuiltinFont, Color, Image, ImageTransform, IndirectFontRef, Line, Mm, PdfDocument,
+    PdfDocumentReference, PdfLayerReference, Point, Rect, Rgb,
+};
+use std::collections::HashMap;
+
+/// Millimetres per typographic point
+const MM_PER_PT: f32 = 0.3528;
+
+/// Tailwind's gray-900, gray-800 and gray-700, as used by the HTML cards
+const TEXT: (u8, u8, u8) = (17, 24, 39);
+const HEADING: (u8, u8, u8) = (31, 41, 55);
+const LABEL: (u8, u8, u8) = (55, 65, 81);
+
+/// Longest side of the logo as embedded in the PDF
+const LOGO_MAX_PIXELS: u32 = 300;
+
+struct Fonts {
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    mono: IndirectFontRef,
+    mono_bold: IndirectFontRef,
+}
+
+impl Fonts {
+    fn load(document: &PdfDocumentReference) -> Result<Self, PdfError> {
+        let font = |font| document.add_builtin_font(font).map_err(PdfError::from);
+        Ok(Self {
+            regular: font(BuiltinFont::Helvetica)?,
+            bold: font(BuiltinFont::HelveticaBold)?,
+            mono: font(BuiltinFont::Courier)?,
+            mono_bold: font(BuiltinFont::CourierBold)?,
+        })
+    }
+}
+
+/// Render one card per voucher, laid out on as many pages as needed. `login_qrs`
+/// holds the captive portal login code of each voucher, by voucher id.
+pub fn render_voucher_sheet(
+    network: &WiFiNetwork,
+    vouchers: &[Voucher],
+    qr: &QrModules,
+    login_qrs: &HashMap<String, QrModules>,
+    layout: &SheetLayout,
+    logo: Option<&Logo>,
+) -> Result<Vec<u8>, PdfError> {
+    if layout.columns == 0 || layout.rows == 0 || layout.qr_size() <= 0.0 {
+        return Err(PdfError::InvalidLayout);
+    }
+    let logo = logo.map(prepare_logo).transpose()?;
+
+    let (document, first_page, first_layer) = PdfDocument::new(
+        format!("WiFi Vouchers - {}", network.name),
+        Mm(layout.page_width),
+        Mm(layout.page_height),
+        "Vouchers",
+    );
+    let fonts = Fonts::load(&document)?;
+
+    for (page, cards) in vouchers.chunks(layout.cards_per_page()).enumerate() {
+        let layer = if page == 0 {
+            document.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page, layer) =
+                document.add_page(Mm(layout.page_width), Mm(layout.page_height), "Vouchers");
+            document.get_page(page).get_layer(layer)
+        };
+
+        for (slot, voucher) in cards.iter().enumerate() {
+            let (x, y) = layout.card_origin(slot);
+            let mut codes = Vec::with_capacity(2);
+            if network.portal.shows_join_qr() {
+                codes.push(qr);
+            }
+            codes.extend(login_qrs.get(&voucher.id));
+            let card = Card {
+                layer: &layer,
+                fonts: &fonts,
+                branding: &network.branding,
+                x,
+                y,
+                width: layout.card_width,
+                height: layout.card_height,
+                qr_size: if codes.len() > 1 {
+                    layout.paired_qr_size()
+                } else {
+                    layout.qr_size()
+                },
+            };
+            card.draw_border();
+            if layout.compact() {
+                card.draw_compact(network, voucher, &codes);
+            } else {
+                card.draw(network, voucher, &codes, logo.as_ref());
+            }
+        }
+    }
+
+    Ok(document.save_to_bytes()?)
+}
+
+/// Decode the logo, flatten any transparency onto white and shrink it to
+/// `LOGO_MAX_PIXELS`, since every card embeds its own copy
+fn prepare_logo(logo: &Logo) -> Result<RgbImage, PdfError> {
+    let image = image::load_from_memory(&logo.data)
+        .map_err(|error| PdfError::Render(error.to_string()))?
+        .thumbnail(LOGO_MAX_PIXELS, LOGO_MAX_PIXELS)
+        .to_rgba8();
+
+    Ok(RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend =
+            |channel: u8| ((channel as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    }))
+}
+
+/// One card's area on a page; coordinates are millimetres from the bottom-left corner
+struct Card<'a> {
+    layer: &'a PdfLayerReference,
+    fonts: &'a Fonts,
+    branding: &'a Branding,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    qr_size: f32,
+}
+
+impl Card<'_> {
+    const PADDING: f32 = 4.0;
+    const HEADER: f32 = 9.0;
+    const LOGO_HEIGHT: f32 = 6.5;
+
+    fn draw_border(&self) {
+        let (r, g, b) = self.branding.accent_rgb();
+        self.layer.set_outline_color(rgb(r, g, b));
+        self.layer.set_outline_thickness(1.5);
+        self.layer.add_rect(
+            Rect::new(
+                Mm(self.x),
+                Mm(self.y),
+                Mm(self.x + self.width),
+                Mm(self.y + self.height),
+            )
+            .with_mode(PaintMode::Stroke),
+        );
+    }
+
+    fn draw(
+        &self,
+        network: &WiFiNetwork,
+        voucher: &Voucher,
+        codes: &[&QrModules],
+        logo: Option<&RgbImage>,
+    ) {
+        let pad = Self::PADDING;
+        let top = self.y + self.height;
+        let fonts = self.fonts;
+
+        // Heading, with the logo on the right
+        let logo_width = logo
+            .map(|logo| self.logo(logo, top - pad - Self::LOGO_HEIGHT + 0.5))
+            .unwrap_or(0.0);
+        let heading_width = self.width - 2.0 * pad - logo_width - 2.0;
+        let heading = fit(&self.branding.heading, heading_width, 12.0, 0.55);
+        self.text(&heading, 12.0, pad, top - pad - 4.5, &fonts.bold, HEADING);
+        self.layer.set_outline_color(rgb(229, 231, 235));
+        self.layer.set_outline_thickness(0.5);
+        self.line(
+            self.x + pad,
+            top - pad - 7.0,
+            self.x + self.width - pad,
+            top - pad - 7.0,
+        );
+
+        // QR codes on the right, network and code on the left
+        let body_top = top - Self::HEADER - pad;
+        let codes_width = self.qr_codes(codes, body_top);
+
+        let text_width = self.width - 3.0 * pad - codes_width;
+        self.text("Network:", 8.0, pad, body_top - 3.0, &fonts.bold, LABEL);
+        let name = fit(&network.name, text_width, 11.0, 0.5);
+        self.text(&name, 11.0, pad, body_top - 8.0, &fonts.regular, TEXT);
+        self.text(
+            "Access Code:",
+            8.0,
+            pad,
+            body_top - 14.0,
+            &fonts.bold,
+            LABEL,
+        );
+        // As many code lines as fit above the validity lines and the instructions
+        let validity = voucher.validity.card_lines();
+        let lowest_baseline = self.y + pad + 18.5;
+        let code_lowest = lowest_baseline + validity.len() as f32 * 3.2;
+        let max_lines = (((body_top - 20.0 - code_lowest) / 5.2).floor() as usize + 1).clamp(1, 3);
+        let code_lines = wrap_chars(&voucher.code, text_width, 13.0, max_lines);
+        let last_baseline = body_top - 20.0 - (code_lines.len() - 1) as f32 * 5.2;
+        self.code_background(pad, last_baseline - 1.8, text_width, body_top - 15.5);
+        for (i, line) in code_lines.iter().enumerate() {
+            let baseline = body_top - 20.0 - i as f32 * 5.2;
+            self.text(line, 13.0, pad, baseline, &fonts.mono_bold, TEXT);
+        }
+        for (i, line) in validity.iter().enumerate() {
+            let baseline = last_baseline - 5.0 - i as f32 * 3.2;
+            if baseline < lowest_baseline - 1.5 {
+                break;
+            }
+            let line = fit(line, text_width, 7.0, 0.5);
+            self.text(&line, 7.0, pad, baseline, &fonts.regular, LABEL);
+        }
+
+        // Instructions, credentials and the network's footer along the bottom
+        let footer_width = self.width - 2.0 * pad;
+        for (i, line) in wrap_words(network.portal.instructions(), footer_width, 6.5, 0.5)
+            .iter()
+            .take(2)
+            .enumerate()
+        {
+            let baseline = self.y + pad + 15.0 - i as f32 * 3.0;
+            self.text(line, 6.5, pad, baseline, &fonts.regular, LABEL);
+        }
+        let ssid = fit(&format!("SSID: {}", network.ssid), footer_width, 8.0, 0.6);
+        self.text(&ssid, 8.0, pad, self.y + pad + 7.5, &fonts.mono, TEXT);
+        let password = fit(&network.password_line(), footer_width, 8.0, 0.6);
+        self.text(&password, 8.0, pad, self.y + pad + 3.5, &fonts.mono, TEXT);
+
+        let footer = [&self.branding.footer, &self.branding.contact]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("  |  ");
+        if !footer.is_empty() {
+            let footer = fit(&footer, footer_width, 6.5, 0.5);
+            self.text(&footer, 6.5, pad, self.y + pad - 0.5, &fonts.regular, LABEL);
+        }
+    }
+
+    /// Label-sized card: no heading or instructions, the QR codes take the full height
+    fn draw_compact(&self, network: &WiFiNetwork, voucher: &Voucher, codes: &[&QrModules]) {
+        let pad = Self::PADDING;
+        let top = self.y + self.height - pad;
+        let fonts = self.fonts;
+        let codes_width = self.qr_codes(codes, top);
+
+        let text_width = self.width - 3.0 * pad - codes_width;
+        self.text("Network:", 7.0, pad, top - 2.5, &fonts.bold, LABEL);
+        let name = fit(&network.name, text_width, 9.0, 0.5);
+        self.text(&name, 9.0, pad, top - 6.5, &fonts.regular, TEXT);
+        self.text("Access Code:", 7.0, pad, top - 10.5, &fonts.bold, LABEL);
+        let code_lines = wrap_chars(&voucher.code, text_width, 11.0, 2);
+        let last_baseline = top - 15.0 - (code_lines.len() - 1) as f32 * 4.4;
+        self.code_background(pad, last_baseline - 1.5, text_width, top - 11.5);
+        for (i, line) in code_lines.iter().enumerate() {
+            let baseline = top - 15.0 - i as f32 * 4.4;
+            self.text(line, 11.0, pad, baseline, &fonts.mono_bold, TEXT);
+        }
+        // Only the duration fits between the code and the credentials
+        if let Some(duration) = voucher.validity.duration_label() {
+            let baseline = last_baseline - 4.0;
+            if baseline > self.y + pad + 6.5 {
+                let duration = fit(&duration, text_width, 7.0, 0.5);
+                self.text(&duration, 7.0, pad, baseline, &fonts.regular, LABEL);
+            }
+        }
+
+        let ssid = fit(&format!("SSID: {}", network.ssid), text_width, 7.0, 0.6);
+        self.text(&ssid, 7.0, pad, self.y + pad + 3.5, &fonts.mono, TEXT);
+        let password = fit(&network.password_line(), text_width, 7.0, 0.6);
+        self.text(&password, 7.0, pad, self.y + pad, &fonts.mono, TEXT);
+    }
+
+    /// Fill the box behind the access code with the network's background colour
+    fn code_background(&self, dx: f32, bottom: f32, width: f32, top: f32) {
+        let (r, g, b) = self.branding.background_rgb();
+        self.layer.set_fill_color(rgb(r, g, b));
+        self.layer.add_rect(
+            Rect::new(
+                Mm(self.x + dx - 1.0),
+                Mm(bottom),
+                Mm(self.x + dx + width),
+                Mm(top),
+            )
+            .with_mode(PaintMode::Fill),
+        );
+    }
+
+    /// Place the logo against the card's right edge, `LOGO_HEIGHT` tall with its
+    /// bottom at `y`, and return its width
+    fn logo(&self, logo: &RgbImage, y: f32) -> f32 {
+        let dpi = 300.0;
+        let natural_width = logo.width() as f32 / dpi * 25.4;
+        let natural_height = logo.height() as f32 / dpi * 25.4;
+        let scale = (Self::LOGO_HEIGHT / natural_height).min(self.width * 0.4 / natural_width);
+        let width = natural_width * scale;
+
+        Image::from_dynamic_image(&DynamicImage::ImageRgb8(logo.clone())).add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(self.x + self.width - Self::PADDING - width)),
+                translate_y: Some(Mm(y)),
+                scale_x: Some(scale),
+                scale_y: Some(scale),
+                dpi: Some(dpi),
+                ..Default::default()
+            },
+        );
+        width
+    }
+
+    /// Write text `dx` millimetres from the card's left edge
+    fn text(
+        &self,
+        text: &str,
+        size: f32,
+        dx: f32,
+        baseline: f32,
+        font: &IndirectFontRef,
+        (r, g, b): (u8, u8, u8),
+    ) {
+        self.layer.set_fill_color(rgb(r, g, b));
+        self.layer
+            .use_text(text, size, Mm(self.x + dx), Mm(baseline), font);
+    }
+
+    fn line(&self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(x1), Mm(y1)), false),
+                (Point::new(Mm(x2), Mm(y2)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    /// Draw the QR codes side by side against the right edge with their tops at
+    /// `top`, and return the width they take up
+    fn qr_codes(&self, codes: &[&QrModules], top: f32) -> f32 {
+        let size = self.qr_size;
+        let mut right = self.x + self.width - Self::PADDING;
+        for qr in codes.iter().rev() {
+            self.qr(qr, right - size, top - size, size);
+            right -= size + QR_GAP;
+        }
+        (codes.len() as f32 * (size + QR_GAP) - QR_GAP).max(0.0)
+    }
+
+    /// Draw the QR code as filled squares, merging runs of dark modules per row
+    fn qr(&self, qr: &QrModules, x: f32, y: f32, size: f32) {
+        let module = size / qr.size() as f32;
+        let origin_x = x + qr.quiet_zone as f32 * module;
+        let origin_top = y + size - qr.quiet_zone as f32 * module;
+
+        // Background including the quiet zone, then the dark modules
+        let (r, g, b) = qr.background;
+        self.layer.set_fill_color(rgb(r, g, b));
+        self.layer.add_rect(
+            Rect::new(Mm(x), Mm(y), Mm(x + size), Mm(y + size)).with_mode(PaintMode::Fill),
+        );
+        let (r, g, b) = qr.foreground;
+        self.layer.set_fill_color(rgb(r, g, b));
+        for (row, start, end) in qr.dark_runs() {
+            let top = origin_top - row as f32 * module;
+            self.layer.add_rect(
+                Rect::new(
+                    Mm(origin_x + start as f32 * module),
+                    Mm(top - module),
+                    Mm(origin_x + end as f32 * module),
+                    Mm(top),
+                )
+                .with_mode(PaintMode::Fill),
+            );
+        }
+    }
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color::Rgb(Rgb::new(
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0,
+        None,
+    ))
+}
+
+/// Approximate number of characters that fit in `width` mm, given the font's
+/// average character width as a fraction of the font size
+fn chars_that_fit(width: f32, size: f32, char_em: f32) -> usize {
+    ((width / (size * char_em * MM_PER_PT)).floor() as usize).max(1)
+}
+
+/// Shorten text to fit on one line, ending in "..." when cut
+fn fit(text: &str, width: f32, size: f32, char_em: f32) -> String {
+    let max = chars_that_fit(width, size, char_em);
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(max.saturating_sub(3)).collect();
+    short.push_str("...");
+    short
+}
+
+/// Break monospaced text into at most `max_lines` lines that fit in `width` mm
+fn wrap_chars(text: &str, width: f32, size: f32, max_lines: usize) -> Vec<String> {
+    let per_line = chars_that_fit(width, size, 0.6);
+    let chars: Vec<char> = text.chars().collect();
+    let mut lines: Vec<String> = chars
+        .chunks(per_line)
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            *last = fit(&format!("{}...", last), width, size, 0.6);
+        }
+    }
+    lines
+}
+
+/// Break text into lines at word boundaries
+fn wrap_words(text: &str, width: f32, size: f32, char_em: f32) -> Vec<String> {
+    let max = chars_that_fit(width, size, char_em);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PdfError {
+    #[error("The page layout leaves no room for cards")]
+    InvalidLayout,
+    #[error("PDF rendering failed: {0}")]
+    Render(String),
+}
+
+impl From<printpdf::Error> for PdfError {
+    fn from(error: printpdf::Error) -> Self {
+        PdfError::Render(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portal::{PortalLogin, PortalQrMode};
+    use crate::qr_generator::QrGenerator;
+    use crate::test_util;
+
+    fn network() -> WiFiNetwork {
+        test_util::lobby()
+    }
+
+    #[test]
+    fn test_render_multiple_pages() {
+        let qr = QrGenerator::default()
+            .generate_modules("WIFI:T:WPA;S:Lobby-Guest;P:secret;H:false;;")
+            .unwrap();
+        let vouchers: Vec<Voucher> = (0..9).map(|i| Voucher::new(format!("CODE{}", i))).collect();
+
+        let layout = crate::layout::preset("a4").unwrap();
+        let pdf = render_voucher_sheet(&network(), &vouchers, &qr, &HashMap::new(), layout, None)
+            .unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+
+        let document = printpdf::lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(document.get_pages().len(), 2);
+
+        // 24 labels per sheet fit all nine on one page
+        let layout = crate::layout::preset("herma-4360").unwrap();
+        let pdf = render_voucher_sheet(&network(), &vouchers, &qr, &HashMap::new(), layout, None)
+            .unwrap();
+        let document = printpdf::lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(document.get_pages().len(), 1);
+    }
+
+    #[test]
+    fn test_render_with_branding_and_logo() {
+        let qr = QrGenerator::default()
+            .generate_modules("WIFI:T:WPA;S:Cafe;P:x;;")
+            .unwrap();
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgba8(600, 200)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let logo = Logo::from_upload(png).unwrap();
+        let mut network = network();
+        network.branding = Branding {
+            heading: "Welcome to the Cafe".to_string(),
+            accent_color: "#b91c1c".to_string(),
+            footer: Some("Enjoy your stay".to_string()),
+            contact: Some("Front desk: dial 9".to_string()),
+            has_logo: true,
+            ..Branding::default()
+        };
+
+        let layout = crate::layout::preset("a4").unwrap();
+        let pdf = render_voucher_sheet(
+            &network,
+            &[Voucher::new("CODE".to_string())],
+            &qr,
+            &HashMap::new(),
+            layout,
+            Some(&logo),
+        )
+        .unwrap();
+        let document = printpdf::lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(document.get_pages().len(), 1);
+
+        assert_eq!(prepare_logo(&logo).unwrap().dimensions(), (300, 100));
+        // Transparent pixels become white
+        assert_eq!(
+            prepare_logo(&logo).unwrap().get_pixel(0, 0).0,
+            [255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_render_login_qr_codes() {
+        let generator = QrGenerator::default();
+        let qr = generator
+            .generate_modules("WIFI:T:WPA;S:Lobby;P:x;;")
+            .unwrap();
+        let vouchers: Vec<Voucher> = (0..3).map(|i| Voucher::new(format!("CODE{}", i))).collect();
+        let mut network = network();
+        network.portal = PortalLogin {
+            mode: PortalQrMode::Both,
+            url_template: Some("https://portal.example/login?voucher={code}".to_string()),
+        };
+        let login_qrs: HashMap<String, QrModules> = vouchers
+            .iter()
+            .map(|voucher| {
+                let url = network.portal.login_url(&voucher.code).unwrap();
+                (
+                    voucher.id.clone(),
+                    generator.generate_modules(&url).unwrap(),
+                )
+            })
+            .collect();
+
+        for layout in ["a4", "herma-4360"] {
+            let layout = crate::layout::preset(layout).unwrap();
+            let without =
+                render_voucher_sheet(&network, &vouchers, &qr, &HashMap::new(), layout, None)
+                    .unwrap();
+            let with =
+                render_voucher_sheet(&network, &vouchers, &qr, &login_qrs, layout, None).unwrap();
+            assert!(with.len() > without.len());
+        }
+    }
+
+    #[test]
+    fn test_text_wrapping() {
+        assert_eq!(fit("Short", 50.0, 10.0, 0.5), "Short");
+        assert!(fit(&"x".repeat(200), 20.0, 10.0, 0.5).ends_with("..."));
+
+        // 30mm holds 10 characters of 13pt Courier
+        let lines = wrap_chars(&"A".repeat(25), 30.0, 13.0, 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.concat(), "A".repeat(25));
+        let lines = wrap_chars(&"A".repeat(40), 30.0, 13.0, 3);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].ends_with("..."));
+
+        let lines = wrap_words("one two three four", 12.0, 10.0, 0.5);
+        assert!(lines.iter().all(|line| !line.starts_with(' ')));
+        assert_eq!(lines.join(" "), "one two three four");
+    }
+}
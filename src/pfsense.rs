@@ -0,0 +1,548 @@
+//! pfSense captive portal voucher rolls.
+//!
+//! A pfSense voucher is a small integer packing, from the least significant bit up,
+//! the roll number, the ticket number, a checksum over both and the portal's magic
+//! number. The integer is raised to the RSA private exponent (textbook RSA, no
+//! padding) and written out in the portal's character set. The portal only needs the
+//! public key to check a code, so rolls can be generated here and the private key
+//! never has to be entered on the firewall.
+
+use chrono::{DateTime, Utc};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    traits::{PrivateKeyParts, PublicKeyParts},
+    BigUint, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The character set pfSense proposes for new voucher rolls
+pub const DEFAULT_CHARSET: &str = "2345678abcdefhijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Bit layout and alphabet of a portal's vouchers, as set on pfSense's voucher page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollSettings {
+    pub roll_bits: u32,
+    pub ticket_bits: u32,
+    pub checksum_bits: u32,
+    pub magic: u64,
+    pub charset: String,
+}
+
+impl Default for RollSettings {
+    fn default() -> Self {
+        Self {
+            roll_bits: 16,
+            ticket_bits: 10,
+            checksum_bits: 5,
+            magic: 0,
+            charset: DEFAULT_CHARSET.to_string(),
+        }
+    }
+}
+
+/// A ticket recovered from a valid voucher code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket {
+    pub roll: u64,
+    pub ticket: u64,
+}
+
+impl RollSettings {
+    /// Check the settings against the size of the RSA modulus in bits
+    pub fn validate(&self, key_bits: usize) -> Result<(), PfSenseError> {
+        if !(1..=31).contains(&self.roll_bits) {
+            return Err(PfSenseError::InvalidSettings(
+                "roll bits must be between 1 and 31".to_string(),
+            ));
+        }
+        if !(1..=16).contains(&self.ticket_bits) {
+            return Err(PfSenseError::InvalidSettings(
+                "ticket bits must be between 1 and 16".to_string(),
+            ));
+        }
+        if self.checksum_bits > 31 {
+            return Err(PfSenseError::InvalidSettings(
+                "checksum bits must be between 0 and 31".to_string(),
+            ));
+        }
+        if self.used_bits() as usize > key_bits.saturating_sub(1) {
+            return Err(PfSenseError::InvalidSettings(format!(
+                "roll, ticket and checksum bits must add up to less than the {}-bit key",
+                key_bits
+            )));
+        }
+
+        let chars: Vec<char> = self.charset.chars().collect();
+        if chars.len() < 2 {
+            return Err(PfSenseError::InvalidSettings(
+                "the character set needs at least two characters".to_string(),
+            ));
+        }
+        for (i, c) in chars.iter().enumerate() {
+            if c.is_whitespace() || *c == '"' || *c == ',' || chars[..i].contains(c) {
+                return Err(PfSenseError::InvalidSettings(format!(
+                    "the character set contains an invalid or repeated character '{}'",
+                    c
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn used_bits(&self) -> u32 {
+        self.roll_bits + self.ticket_bits + self.checksum_bits
+    }
+
+    /// Bits left over for the magic number; the top bit stays clear so the value is below the modulus
+    pub fn magic_bits(&self, key_bits: usize) -> usize {
+        key_bits.saturating_sub(1 + self.used_bits() as usize)
+    }
+
+    pub fn max_roll(&self) -> u64 {
+        (1u64 << self.roll_bits as usize) - 1
+    }
+
+    pub fn max_tickets(&self) -> u64 {
+        (1u64 << self.ticket_bits as usize) - 1
+    }
+
+    fn checksum(&self, roll: u64, ticket: u64) -> u64 {
+        (roll + ticket) & ((1u64 << self.checksum_bits as usize) - 1)
+    }
+
+    fn magic(&self, key_bits: usize) -> BigUint {
+        let magic_bits = self.magic_bits(key_bits);
+        let mask = (BigUint::from(1u8) << magic_bits) - 1u8;
+        BigUint::from(self.magic) & mask
+    }
+
+    /// Pack a roll and ticket number into the clear-text voucher value
+    fn pack(&self, roll: u64, ticket: u64, key_bits: usize) -> BigUint {
+        let mut clear = self.magic(key_bits);
+        clear = (clear << self.checksum_bits as usize) | BigUint::from(self.checksum(roll, ticket));
+        clear = (clear << self.ticket_bits as usize) | BigUint::from(ticket);
+        (clear << self.roll_bits as usize) | BigUint::from(roll)
+    }
+
+    /// Split a clear-text voucher value, checking its checksum and magic number
+    fn unpack(&self, clear: &BigUint, key_bits: usize) -> Result<Ticket, PfSenseError> {
+        let field = |value: &BigUint, bits: u32| -> u64 {
+            let mask = (BigUint::from(1u8) << bits as usize) - 1u8;
+            low_u64(&(value & mask))
+        };
+
+        let roll = field(clear, self.roll_bits);
+        let rest = clear >> self.roll_bits as usize;
+        let ticket = field(&rest, self.ticket_bits);
+        let rest = rest >> self.ticket_bits as usize;
+        let checksum = field(&rest, self.checksum_bits);
+        let magic = rest >> self.checksum_bits as usize;
+
+        if checksum != self.checksum(roll, ticket) || magic != self.magic(key_bits) {
+            return Err(PfSenseError::InvalidCode);
+        }
+
+        Ok(Ticket { roll, ticket })
+    }
+
+    fn encode(&self, value: &BigUint) -> String {
+        let chars: Vec<char> = self.charset.chars().collect();
+        let base = BigUint::from(chars.len());
+        let mut value = value.clone();
+        let mut digits = Vec::new();
+
+        loop {
+            let digit = low_u64(&(&value % &base));
+            digits.push(chars[digit as usize]);
+            value /= &base;
+            if value == BigUint::from(0u8) {
+                break;
+            }
+        }
+
+        digits.iter().rev().collect()
+    }
+
+    fn decode(&self, code: &str) -> Result<BigUint, PfSenseError> {
+        let chars: Vec<char> = self.charset.chars().collect();
+        let base = BigUint::from(chars.len());
+        let code = code.trim().trim_matches('"').trim();
+        if code.is_empty() {
+            return Err(PfSenseError::InvalidCode);
+        }
+
+        let mut value = BigUint::from(0u8);
+        for c in code.chars() {
+            let digit = chars
+                .iter()
+                .position(|candidate| *candidate == c)
+                .ok_or(PfSenseError::InvalidCharacter(c))?;
+            value = value * &base + BigUint::from(digit);
+        }
+        Ok(value)
+    }
+}
+
+/// The lowest 64 bits of a big integer
+fn low_u64(value: &BigUint) -> u64 {
+    value
+        .to_bytes_le()
+        .iter()
+        .take(8)
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+}
+
+/// Parse an RSA private key in PKCS#1 (`BEGIN RSA PRIVATE KEY`) or PKCS#8 PEM form
+pub fn parse_private_key(pem: &str) -> Result<RsaPrivateKey, PfSenseError> {
+    let pem = pem.trim();
+    RsaPrivateKey::from_pkcs1_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+        .map_err(|e| PfSenseError::InvalidKey(e.to_string()))
+}
+
+/// Parse an RSA public key in SPKI (`BEGIN PUBLIC KEY`) or PKCS#1 PEM form
+pub fn parse_public_key(pem: &str) -> Result<RsaPublicKey, PfSenseError> {
+    let pem = pem.trim();
+    RsaPublicKey::from_public_key_pem(pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+        .map_err(|e| PfSenseError::InvalidKey(e.to_string()))
+}
+
+/// Check that a private key belongs to a public key
+pub fn keys_match(private_key: &RsaPrivateKey, public_key: &RsaPublicKey) -> bool {
+    private_key.n() == public_key.n() && private_key.e() == public_key.e()
+}
+
+/// Check settings before saving them: the keys must parse, belong together and fit the bit layout
+pub fn check_settings(
+    settings: &RollSettings,
+    public_key: &str,
+    private_key: Option<&str>,
+) -> Result<(), PfSenseError> {
+    let public_key = parse_public_key(public_key)?;
+    if let Some(private_key) = private_key {
+        if !keys_match(&parse_private_key(private_key)?, &public_key) {
+            return Err(PfSenseError::KeyMismatch);
+        }
+    }
+    settings.validate(public_key.n().bits())
+}
+
+/// Generate tickets 1..=count of a roll, in ticket order
+pub fn generate_roll(
+    settings: &RollSettings,
+    private_key: &RsaPrivateKey,
+    roll: u64,
+    count: u64,
+) -> Result<Vec<String>, PfSenseError> {
+    let key_bits = private_key.n().bits();
+    settings.validate(key_bits)?;
+
+    if roll == 0 || roll > settings.max_roll() {
+        return Err(PfSenseError::RollOutOfRange(settings.max_roll()));
+    }
+    if count == 0 || count > settings.max_tickets() {
+        return Err(PfSenseError::TooManyTickets(settings.max_tickets()));
+    }
+
+    Ok((1..=count)
+        .map(|ticket| {
+            let clear = settings.pack(roll, ticket, key_bits);
+            let cipher = clear.modpow(private_key.d(), private_key.n());
+            settings.encode(&cipher)
+        })
+        .collect())
+}
+
+/// Check a voucher code with the portal's public key and recover its roll and ticket
+pub fn verify_code(
+    settings: &RollSettings,
+    public_key: &RsaPublicKey,
+    code: &str,
+) -> Result<Ticket, PfSenseError> {
+    let key_bits = public_key.n().bits();
+    settings.validate(key_bits)?;
+
+    let cipher = settings.decode(code)?;
+    if &cipher >= public_key.n() {
+        return Err(PfSenseError::InvalidCode);
+    }
+
+    let clear = cipher.modpow(public_key.e(), public_key.n());
+    settings.unpack(&clear, key_bits)
+}
+
+/// A network's pfSense voucher configuration
+#[derive(Debug, Clone)]
+pub struct PfSenseSettings {
+    pub network_id: String,
+    pub roll: RollSettings,
+    pub public_key: String,
+    /// Only needed to generate rolls; stored encrypted like network passwords
+    pub private_key: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PfSenseSettings {
+    pub fn has_private_key(&self) -> bool {
+        self.private_key.is_some()
+    }
+}
+
+/// A generated roll of pfSense vouchers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoucherRoll {
+    pub id: String,
+    pub network_id: String,
+    pub roll_number: u64,
+    pub ticket_count: u64,
+    pub minutes: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl VoucherRoll {
+    pub fn new(network_id: String, roll_number: u64, ticket_count: u64, minutes: u32) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            network_id,
+            roll_number,
+            ticket_count,
+            minutes,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PfSenseError {
+    #[error("Invalid RSA key: {0}")]
+    InvalidKey(String),
+    #[error("The private key does not belong to the public key")]
+    KeyMismatch,
+    #[error("Invalid voucher settings: {0}")]
+    InvalidSettings(String),
+    #[error("Roll number must be between 1 and {0}")]
+    RollOutOfRange(u64),
+    #[error("Ticket count must be between 1 and {0}")]
+    TooManyTickets(u64),
+    #[error("Voucher code contains '{0}', which is not in the character set")]
+    InvalidCharacter(char),
+    #[error("Voucher code is not valid for this portal")]
+    InvalidCode,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1::{EncodeRsaPrivateKey, LineEnding};
+    use rsa::pkcs8::EncodePublicKey;
+
+    fn test_key() -> RsaPrivateKey {
+        // 64-bit keys, like the ones pfSense generates, are far too small for real
+        // encryption, but are what the portal expects
+        RsaPrivateKey::from_p_q(
+            BigUint::from(4_294_967_291u64),
+            BigUint::from(4_294_967_279u64),
+            BigUint::from(65_537u32),
+        )
+        .unwrap()
+    }
+
+    fn settings() -> RollSettings {
+        RollSettings {
+            magic: 1_234_567_890,
+            ..RollSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_generated_codes_verify() {
+        let key = test_key();
+        let public_key = key.to_public_key();
+        let codes = generate_roll(&settings(), &key, 7, 20).unwrap();
+
+        assert_eq!(codes.len(), 20);
+        for (i, code) in codes.iter().enumerate() {
+            assert!(code.chars().all(|c| DEFAULT_CHARSET.contains(c)));
+            let ticket = verify_code(&settings(), &public_key, code).unwrap();
+            assert_eq!(
+                ticket,
+                Ticket {
+                    roll: 7,
+                    ticket: i as u64 + 1
+                }
+            );
+        }
+    }
+
+    /// Fixed vectors for the layout described at the top of this file, worked out
+    /// separately with plain integer arithmetic. They pin the field order, checksum,
+    /// magic placement and digit order; parity with pfSense itself is checked by
+    /// `test_matches_pfsense_roll`.
+    #[test]
+    fn test_known_answers() {
+        let key = test_key();
+        // magic 0x499602d2 | checksum (7 + ticket) | ticket | roll 7, from the top bit down
+        let packed = [
+            (1, 0x24cb_0169_2001_0007u64, "F3PPpPzG5HT"),
+            (2, 0x24cb_0169_2402_0007, "7REpZq3xE7W"),
+            (3, 0x24cb_0169_2803_0007, "FpFy5E5WR7V"),
+        ];
+        for (ticket, clear, _) in packed {
+            assert_eq!(settings().pack(7, ticket, 64), BigUint::from(clear));
+        }
+
+        let codes = generate_roll(&settings(), &key, 7, 3).unwrap();
+        let expected: Vec<&str> = packed.iter().map(|(_, _, code)| *code).collect();
+        assert_eq!(codes, expected);
+        assert_eq!(
+            verify_code(&settings(), &key.to_public_key(), "FpFy5E5WR7V").unwrap(),
+            Ticket { roll: 7, ticket: 3 }
+        );
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let key = test_key();
+        assert_eq!(
+            generate_roll(&settings(), &key, 3, 5).unwrap(),
+            generate_roll(&settings(), &key, 3, 5).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rejects_codes_from_other_portals() {
+        let key = test_key();
+        let public_key = key.to_public_key();
+        let code = &generate_roll(&settings(), &key, 1, 1).unwrap()[0];
+
+        let other = RollSettings {
+            magic: 42,
+            ..settings()
+        };
+        assert!(matches!(
+            verify_code(&other, &public_key, code),
+            Err(PfSenseError::InvalidCode)
+        ));
+        assert!(matches!(
+            verify_code(&settings(), &public_key, "  2345-abc "),
+            Err(PfSenseError::InvalidCharacter('-'))
+        ));
+    }
+
+    #[test]
+    fn test_limits() {
+        let key = test_key();
+        assert!(matches!(
+            generate_roll(&settings(), &key, 0, 1),
+            Err(PfSenseError::RollOutOfRange(65535))
+        ));
+        assert!(matches!(
+            generate_roll(&settings(), &key, 1, 1024),
+            Err(PfSenseError::TooManyTickets(1023))
+        ));
+
+        let too_wide = RollSettings {
+            roll_bits: 31,
+            ticket_bits: 16,
+            checksum_bits: 31,
+            ..settings()
+        };
+        assert!(too_wide.validate(64).is_err());
+        assert_eq!(settings().magic_bits(64), 32);
+    }
+
+    #[test]
+    fn test_parse_pem_keys() {
+        let key = test_key();
+        let private_pem = key.to_pkcs1_pem(LineEnding::LF).unwrap();
+        let public_pem = key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+
+        let private_key = parse_private_key(&private_pem).unwrap();
+        let public_key = parse_public_key(&public_pem).unwrap();
+        assert!(keys_match(&private_key, &public_key));
+        assert!(parse_public_key("not a key").is_err());
+    }
+
+    /// A roll exported from a real pfSense portal: its voucher settings, both keys
+    /// as shown on the portal's voucher page, a roll number and the codes pfSense
+    /// printed for tickets `1..=codes.len()` of that roll
+    #[derive(Deserialize)]
+    struct PfSenseFixture {
+        #[serde(flatten)]
+        settings: RollSettings,
+        public_key: String,
+        private_key: String,
+        roll: u64,
+        codes: Vec<String>,
+    }
+
+    /// Known-answer check against pfSense itself. The other tests only check the
+    /// generator against its own verifier, so they can't catch a wrong digit order
+    /// or checksum. Export a roll from a pfSense portal into the fixture to run it.
+    #[test]
+    #[ignore = "needs tests/fixtures/pfsense-roll.json exported from a real pfSense portal"]
+    fn test_matches_pfsense_roll() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/pfsense-roll.json"
+        );
+        let fixture: PfSenseFixture = serde_json::from_str(
+            &std::fs::read_to_string(path).unwrap_or_else(|_| panic!("{} is missing", path)),
+        )
+        .unwrap();
+        let private_key = parse_private_key(&fixture.private_key).unwrap();
+        let public_key = parse_public_key(&fixture.public_key).unwrap();
+
+        let generated = generate_roll(
+            &fixture.settings,
+            &private_key,
+            fixture.roll,
+            fixture.codes.len() as u64,
+        )
+        .unwrap();
+        assert_eq!(generated, fixture.codes);
+
+        for (i, code) in fixture.codes.iter().enumerate() {
+            assert_eq!(
+                verify_code(&fixture.settings, &public_key, code).unwrap(),
+                Ticket {
+                    roll: fixture.roll,
+                    ticket: i as u64 + 1
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_settings() {
+        let key = test_key();
+        let private_pem = key.to_pkcs1_pem(LineEnding::LF).unwrap();
+        let public_pem = key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        assert!(check_settings(&settings(), &public_pem, Some(&private_pem)).is_ok());
+        assert!(check_settings(&settings(), &public_pem, None).is_ok());
+
+        let other = RsaPrivateKey::from_p_q(
+            BigUint::from(4_294_967_231u64),
+            BigUint::from(4_294_967_197u64),
+            BigUint::from(65_537u32),
+        )
+        .unwrap()
+        .to_pkcs1_pem(LineEnding::LF)
+        .unwrap();
+        assert!(matches!(
+            check_settings(&settings(), &public_pem, Some(&other)),
+            Err(PfSenseError::KeyMismatch)
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+//! Login QR codes that open the captive portal with the voucher code filled in,
+//! so guests scan once to join the network and once to log in.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Replaced with the voucher code in the login URL template
+pub const CODE_PLACEHOLDER: &str = "{code}";
+
+const MAX_URL_LEN: usize = 500;
+
+const JOIN_INSTRUCTIONS: &str = "First scan the QR code or connect with the WiFi details below, then enter the access code when prompted";
+const BOTH_INSTRUCTIONS: &str =
+    "Scan the left QR code to join the WiFi, then the right one to log in with your access code";
+const LOGIN_INSTRUCTIONS: &str =
+    "Connect with the WiFi details below, then scan the QR code to log in with your access code";
+
+/// Which QR codes a voucher card shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortalQrMode {
+    /// Only the network join code
+    #[default]
+    Off,
+    /// The join code and a login code next to it
+    Both,
+    /// Only the login code, for networks guests join without a QR code
+    LoginOnly,
+}
+
+impl PortalQrMode {
+    pub const ALL: [PortalQrMode; 3] = [Self::Off, Self::Both, Self::LoginOnly];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Both => "both",
+            Self::LoginOnly => "login-only",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Off => "Off: WiFi join code only",
+            Self::Both => "Join code and login code",
+            Self::LoginOnly => "Login code only",
+        }
+    }
+}
+
+impl fmt::Display for PortalQrMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PortalQrMode {
+    type Err = PortalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str() == s)
+            .ok_or_else(|| PortalError::UnknownMode(s.to_string()))
+    }
+}
+
+/// A network's captive portal login QR settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortalLogin {
+    pub mode: PortalQrMode,
+    /// Login URL with `{code}` where the voucher code goes
+    pub url_template: Option<String>,
+}
+
+impl PortalLogin {
+    /// Check the URL template before saving; it is required unless the mode is off
+    pub fn validate(&self) -> Result<(), PortalError> {
+        let Some(template) = &self.url_template else {
+            return match self.mode {
+                PortalQrMode::Off => Ok(()),
+                _ => Err(PortalError::MissingUrl),
+            };
+        };
+        if !(template.starts_with("https://") || template.starts_with("http://")) {
+            return Err(PortalError::Scheme);
+        }
+        if !template.contains(CODE_PLACEHOLDER) {
+            return Err(PortalError::Placeholder);
+        }
+        if template.len() > MAX_URL_LEN || template.chars().any(char::is_whitespace) {
+            return Err(PortalError::InvalidUrl(MAX_URL_LEN));
+        }
+        Ok(())
+    }
+
+    /// The URL that logs in with `code`, or `None` when login codes are off
+    pub fn login_url(&self, code: &str) -> Option<String> {
+        if self.mode == PortalQrMode::Off {
+            return None;
+        }
+        self.url_template
+            .as_ref()
+            .map(|template| template.replace(CODE_PLACEHOLDER, &encode_component(code)))
+    }
+
+    /// Whether cards still show the WiFi join code
+    pub fn shows_join_qr(&self) -> bool {
+        self.mode != PortalQrMode::LoginOnly || self.url_template.is_none()
+    }
+
+    /// Instructions printed on the card for the codes it shows
+    pub fn instructions(&self) -> &'static str {
+        match (self.mode, &self.url_template) {
+            (PortalQrMode::Both, Some(_)) => BOTH_INSTRUCTIONS,
+            (PortalQrMode::LoginOnly, Some(_)) => LOGIN_INSTRUCTIONS,
+            _ => JOIN_INSTRUCTIONS,
+        }
+    }
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+fn encode_component(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortalError {
+    #[error("unknown login QR mode '{0}'")]
+    UnknownMode(String),
+    #[error("a login URL is needed to print login QR codes")]
+    MissingUrl,
+    #[error("the login URL must start with https:// or http://")]
+    Scheme,
+    #[error("the login URL must contain {{code}} where the voucher code goes")]
+    Placeholder,
+    #[error("the login URL must be at most {0} characters without spaces")]
+    InvalidUrl(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal(mode: PortalQrMode, url: &str) -> PortalLogin {
+        PortalLogin {
+            mode,
+            url_template: Some(url.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_login_url() {
+        let login = portal(
+            PortalQrMode::Both,
+            "https://portal.example/login?voucher={code}",
+        );
+        assert_eq!(
+            login.login_url("ABCD-1234").unwrap(),
+            "https://portal.example/login?voucher=ABCD-1234"
+        );
+        assert_eq!(
+            login.login_url("a b&c=d").unwrap(),
+            "https://portal.example/login?voucher=a%20b%26c%3Dd"
+        );
+        assert!(login.shows_join_qr());
+
+        let off = portal(PortalQrMode::Off, "https://portal.example/{code}");
+        assert!(off.login_url("ABCD").is_none());
+        assert_eq!(off.instructions(), JOIN_INSTRUCTIONS);
+
+        let login_only = portal(PortalQrMode::LoginOnly, "https://portal.example/{code}");
+        assert!(!login_only.shows_join_qr());
+        assert_eq!(login_only.instructions(), LOGIN_INSTRUCTIONS);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(PortalLogin::default().validate().is_ok());
+        assert!(
+            portal(PortalQrMode::Both, "https://portal.example/?v={code}")
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            PortalLogin {
+                mode: PortalQrMode::Both,
+                url_template: None,
+            }
+            .validate(),
+            Err(PortalError::MissingUrl)
+        ));
+        assert!(matches!(
+            portal(PortalQrMode::Both, "javascript:alert({code})").validate(),
+            Err(PortalError::Scheme)
+        ));
+        assert!(matches!(
+            portal(PortalQrMode::Both, "https://portal.example/login").validate(),
+            Err(PortalError::Placeholder)
+        ));
+        assert!(matches!(
+            portal(PortalQrMode::Both, "https://portal.example/ {code}").validate(),
+            Err(PortalError::InvalidUrl(_))
+        ));
+        assert_eq!(
+            "login-only".parse::<PortalQrMode>().unwrap(),
+            PortalQrMode::LoginOnly
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! A record of each batch of vouchers handed out for printing, so a batch lost
+//! to a printer jam can be printed again without using up new codes.
+//!
+//! Printing happens in two steps: a new job only reserves its vouchers for a
+//! while, and they are used up once the operator confirms the sheet printed.
+//! Reservations that are released or run out go back to the unused pool.
+
+use crate::branding::Branding;
+use crate::portal::PortalLogin;
+use crate::qr_generator::QrOptions;
+use crate::voucher::Voucher;
+use crate::wifi_network::WiFiNetwork;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// How a batch was rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintOutput {
+    /// Printable HTML page, printed from the browser
+    #[default]
+    Html,
+    Pdf,
+    /// ESC/POS print data for thermal receipt printers, one receipt per voucher
+    Escpos,
+}
+
+impl PrintOutput {
+    pub const ALL: [PrintOutput; 3] = [Self::Html, Self::Pdf, Self::Escpos];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Pdf => "pdf",
+            Self::Escpos => "escpos",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Html => "Browser",
+            Self::Pdf => "PDF",
+            Self::Escpos => "Receipt printer",
+        }
+    }
+}
+
+impl fmt::Display for PrintOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PrintOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|output| output.as_str() == s)
+            .ok_or_else(|| format!("unknown print output '{}'", s))
+    }
+}
+
+/// Where a print job is in the reserve and confirm cycle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintJobStatus {
+    /// Vouchers are held for the job until it is confirmed or the reservation ends
+    #[default]
+    Reserved,
+    /// The sheet printed and its vouchers are used
+    Confirmed,
+    /// The operator gave the vouchers back
+    Released,
+    /// Nobody confirmed in time, so the vouchers went back to the pool
+    Expired,
+}
+
+impl PrintJobStatus {
+    pub const ALL: [PrintJobStatus; 4] = [
+        Self::Reserved,
+        Self::Confirmed,
+        Self::Released,
+        Self::Expired,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Reserved => "reserved",
+            Self::Confirmed => "confirmed",
+            Self::Released => "released",
+            Self::Expired => "expired",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Reserved => "Reserved",
+            Self::Confirmed => "Printed",
+            Self::Released => "Released",
+            Self::Expired => "Expired",
+        }
+    }
+
+    /// Whether the job's cards may be shown, which is not the case once its
+    /// vouchers went back to the pool and may be handed out again
+    pub fn can_print(&self) -> bool {
+        matches!(self, Self::Reserved | Self::Confirmed)
+    }
+}
+
+impl fmt::Display for PrintJobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PrintJobStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("unknown print job status '{}'", s))
+    }
+}
+
+/// The network's card settings when a job was created, so reprints look the
+/// same as the original print even after the network is edited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintSettings {
+    pub branding: Branding,
+    pub qr_options: Option<QrOptions>,
+    pub portal: PortalLogin,
+}
+
+impl PrintSettings {
+    pub fn of(network: &WiFiNetwork) -> Self {
+        Self {
+            branding: network.branding.clone(),
+            qr_options: network.qr_options.clone(),
+            portal: network.portal.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrintJob {
+    pub id: String,
+    pub network_id: String,
+    /// Username of whoever printed the batch
+    pub printed_by: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Id of the page layout preset the cards were printed with
+    pub layout: String,
+    pub output: PrintOutput,
+    pub status: PrintJobStatus,
+    /// When a reserved job's vouchers go back to the pool unless it is confirmed
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Card settings to render with; jobs from before they were recorded have none
+    pub settings: Option<PrintSettings>,
+    /// The batch's vouchers, in the order they were printed
+    pub vouchers: Vec<Voucher>,
+}
+
+impl PrintJob {
+    /// A job that holds its vouchers for `reservation` before they go back to the pool,
+    /// recording the network's current card settings. The vouchers themselves are
+    /// picked when the job is stored.
+    pub fn reserve(
+        network: &WiFiNetwork,
+        printed_by: String,
+        layout: String,
+        output: PrintOutput,
+        reservation: chrono::Duration,
+    ) -> Self {
+        let created_at = chrono::Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            network_id: network.id.clone(),
+            printed_by,
+            created_at,
+            layout,
+            output,
+            status: PrintJobStatus::Reserved,
+            expires_at: Some(created_at + reservation),
+            settings: Some(PrintSettings::of(network)),
+            vouchers: Vec::new(),
+        }
+    }
+
+    /// The network to render the job's cards for, with the settings it had when
+    /// the job was created
+    pub fn network_as_printed(&self, network: &WiFiNetwork) -> WiFiNetwork {
+        match &self.settings {
+            Some(settings) => WiFiNetwork {
+                branding: settings.branding.clone(),
+                qr_options: settings.qr_options.clone(),
+                portal: settings.portal.clone(),
+                ..network.clone()
+            },
+            None => network.clone(),
+        }
+    }
+
+    /// Name of the layout preset, or its id if the preset no longer exists
+    pub fn layout_name(&self) -> &str {
+        crate::layout::preset(&self.layout)
+            .map(|layout| layout.name)
+            .unwrap_or(&self.layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_print_output() {
+        assert_eq!("pdf".parse::<PrintOutput>().unwrap(), PrintOutput::Pdf);
+        assert_eq!("html".parse::<PrintOutput>().unwrap(), PrintOutput::Html);
+        assert!("docx".parse::<PrintOutput>().is_err());
+        assert_eq!(PrintOutput::Pdf.to_string(), "pdf");
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut network = test_util::lobby();
+        network.branding.heading = "Welcome".to_string();
+        let job = PrintJob::reserve(
+            &network,
+            "desk".to_string(),
+            "a4".to_string(),
+            PrintOutput::Html,
+            chrono::Duration::minutes(30),
+        );
+        assert_eq!(job.status, PrintJobStatus::Reserved);
+        assert_eq!(
+            job.expires_at.unwrap() - job.created_at,
+            chrono::Duration::minutes(30)
+        );
+        assert!(job.status.can_print());
+        assert!(!PrintJobStatus::Expired.can_print());
+        assert_eq!(
+            "released".parse::<PrintJobStatus>().unwrap(),
+            PrintJobStatus::Released
+        );
+
+        // Later edits to the network don't change the job's cards
+        network.branding.heading = "Hello".to_string();
+        network.portal.url_template = Some("https://portal.test/?code={code}".to_string());
+        let printed = job.network_as_printed(&network);
+        assert_eq!(printed.branding.heading, "Welcome");
+        assert_eq!(printed.portal, PortalLogin::default());
+        assert_eq!(printed.id, network.id);
+        let old = PrintJob {
+            settings: None,
+            ..job
+        };
+        assert_eq!(old.network_as_printed(&network).branding.heading, "Hello");
+    }
+}
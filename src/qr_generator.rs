@@ -1,60 +1,421 @@
+use crate::branding::parse_color;
 use base64::{engine::general_purpose, Engine as _};
 use image::{ImageBuffer, Rgb, RgbImage};
 use qrcode::{Color, EcLevel, QrCode};
-use std::io::Cursor;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone)]
-pub struct QrGenerator;
+/// Lowest WCAG contrast ratio between the two QR colours that phone cameras read reliably
+const MIN_CONTRAST: f32 = 3.0;
+
+/// How much of a damaged or covered code can be recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCorrection {
+    /// About 7% of the code
+    L,
+    /// About 15% of the code
+    M,
+    /// About 25% of the code
+    Q,
+    /// About 30% of the code
+    H,
+}
+
+impl ErrorCorrection {
+    pub const ALL: [ErrorCorrection; 4] = [Self::L, Self::M, Self::Q, Self::H];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::L => "L",
+            Self::M => "M",
+            Self::Q => "Q",
+            Self::H => "H",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::L => "Low (7%)",
+            Self::M => "Medium (15%)",
+            Self::Q => "Quartile (25%)",
+            Self::H => "High (30%)",
+        }
+    }
+
+    fn ec_level(&self) -> EcLevel {
+        match self {
+            Self::L => EcLevel::L,
+            Self::M => EcLevel::M,
+            Self::Q => EcLevel::Q,
+            Self::H => EcLevel::H,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorCorrection {
+    type Err = QrGeneratorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                QrGeneratorError::InvalidOptions(format!(
+                    "unknown error correction level '{}', expected L, M, Q or H",
+                    s
+                ))
+            })
+    }
+}
+
+/// Image format of the QR code on HTML voucher sheets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrImageFormat {
+    /// Vector image, sharp at any size
+    Svg,
+    /// Bitmap rendered with the module size and DPI
+    Png,
+}
+
+impl QrImageFormat {
+    pub const ALL: [QrImageFormat; 2] = [Self::Svg, Self::Png];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Png => "png",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Svg => "SVG (vector)",
+            Self::Png => "PNG (bitmap)",
+        }
+    }
+}
+
+impl fmt::Display for QrImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QrImageFormat {
+    type Err = QrGeneratorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                QrGeneratorError::InvalidOptions(format!(
+                    "unknown image format '{}', expected svg or png",
+                    s
+                ))
+            })
+    }
+}
+
+/// How QR codes are drawn. Set globally in the `[qr]` section of config.toml
+/// and optionally overridden per network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QrOptions {
+    pub error_correction: ErrorCorrection,
+    /// Pixels per module in the PNG image
+    pub module_size: u32,
+    /// Light border around the code, in modules
+    pub quiet_zone: u32,
+    /// Colour of the dark modules, as `#rrggbb`
+    pub foreground: String,
+    /// Colour of the light modules and quiet zone, as `#rrggbb`
+    pub background: String,
+    /// Resolution recorded in the PNG image, in dots per inch
+    pub dpi: u32,
+    /// Format used on HTML voucher sheets; PDFs are always vector
+    pub image_format: QrImageFormat,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            error_correction: ErrorCorrection::M,
+            module_size: 4,
+            quiet_zone: 2,
+            foreground: "#000000".to_string(),
+            background: "#ffffff".to_string(),
+            dpi: 300,
+            image_format: QrImageFormat::Svg,
+        }
+    }
+}
+
+impl QrOptions {
+    pub fn validate(&self) -> Result<(), QrGeneratorError> {
+        let invalid = |message: String| Err(QrGeneratorError::InvalidOptions(message));
+        if !(1..=40).contains(&self.module_size) {
+            return invalid("the module size must be between 1 and 40 pixels".to_string());
+        }
+        if self.quiet_zone > 10 {
+            return invalid("the quiet zone must be at most 10 modules".to_string());
+        }
+        if !(72..=2400).contains(&self.dpi) {
+            return invalid("the DPI must be between 72 and 2400".to_string());
+        }
+        let foreground = parse_color(&self.foreground)
+            .map_err(|error| QrGeneratorError::InvalidOptions(error.to_string()))?;
+        let background = parse_color(&self.background)
+            .map_err(|error| QrGeneratorError::InvalidOptions(error.to_string()))?;
+        if luminance(foreground) >= luminance(background)
+            || contrast(foreground, background) < MIN_CONTRAST
+        {
+            return invalid(
+                "the foreground must be clearly darker than the background".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn foreground_rgb(&self) -> (u8, u8, u8) {
+        parse_color(&self.foreground).unwrap_or((0, 0, 0))
+    }
+
+    pub fn background_rgb(&self) -> (u8, u8, u8) {
+        parse_color(&self.background).unwrap_or((255, 255, 255))
+    }
+}
+
+/// WCAG relative luminance
+fn luminance((r, g, b): (u8, u8, u8)) -> f32 {
+    let channel = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(r) + 0.7152 * channel(g) + 0.0722 * channel(b)
+}
+
+fn contrast(a: (u8, u8, u8), b: (u8, u8, u8)) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+#[derive(Clone, Default)]
+pub struct QrGenerator {
+    options: QrOptions,
+}
+
+/// The modules of a QR code, row by row, without the quiet zone, plus how to draw them
+#[derive(Debug, Clone)]
+pub struct QrModules {
+    pub width: usize,
+    dark: Vec<bool>,
+    pub quiet_zone: usize,
+    pub foreground: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+}
+
+impl QrModules {
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    /// Horizontal runs of dark modules as `(row, first column, column after the run)`,
+    /// so a code can be drawn with one rectangle per run instead of per module
+    pub fn dark_runs(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..self.width).flat_map(move |row| {
+            let mut runs = Vec::new();
+            let mut column = 0;
+            while column < self.width {
+                if !self.is_dark(column, row) {
+                    column += 1;
+                    continue;
+                }
+                let start = column;
+                while column < self.width && self.is_dark(column, row) {
+                    column += 1;
+                }
+                runs.push((row, start, column));
+            }
+            runs
+        })
+    }
+
+    /// Side length including the quiet zone, in modules
+    pub fn size(&self) -> usize {
+        self.width + 2 * self.quiet_zone
+    }
+
+    /// SVG path data for the dark modules, one unit per module, offset by the quiet zone
+    pub fn svg_path(&self) -> String {
+        let mut path = String::new();
+        for (row, start, end) in self.dark_runs() {
+            path.push_str(&format!(
+                "M{} {}h{}v1h-{}z",
+                start + self.quiet_zone,
+                row + self.quiet_zone,
+                end - start,
+                end - start
+            ));
+        }
+        path
+    }
+
+    pub fn foreground_hex(&self) -> String {
+        hex(self.foreground)
+    }
+
+    pub fn background_hex(&self) -> String {
+        hex(self.background)
+    }
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// The WiFi QR code as embedded in the HTML voucher sheet
+pub enum QrImage {
+    /// Base64-encoded PNG
+    Png(String),
+    /// Vector modules, drawn once as an SVG symbol and reused by every card
+    Svg(QrModules),
+}
 
 impl QrGenerator {
-    pub fn new() -> Self {
-        Self
+    pub fn with_options(options: QrOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &QrOptions {
+        &self.options
+    }
+
+    /// A generator using `options` when given, otherwise these options
+    pub fn with_override(&self, options: Option<&QrOptions>) -> Self {
+        options.map_or_else(
+            || self.clone(),
+            |options| Self::with_options(options.clone()),
+        )
     }
 
     /// Generate a QR code and return it as a base64-encoded PNG image
     pub fn generate_qr_base64(&self, data: &str) -> Result<String, QrGeneratorError> {
-        let qr_code = QrCode::with_error_correction_level(data, EcLevel::M)
-            .map_err(|e| QrGeneratorError::QrCodeGeneration(e.to_string()))?;
+        let qr_code = self.encode(data)?;
 
         // Create an image from the QR code
-        let image = self.qr_code_to_image(&qr_code)?;
+        let image = self.qr_code_to_image(&qr_code, self.options.module_size as usize)?;
 
         // Convert image to PNG bytes
-        let mut png_bytes = Vec::new();
-        {
-            let mut cursor = Cursor::new(&mut png_bytes);
-            image
-                .write_to(&mut cursor, image::ImageOutputFormat::Png)
-                .map_err(|e| QrGeneratorError::ImageEncoding(e.to_string()))?;
-        }
+        let png_bytes = self.encode_png(&image)?;
 
         // Encode as base64
         let base64_string = general_purpose::STANDARD.encode(&png_bytes);
         Ok(base64_string)
     }
 
-    fn qr_code_to_image(&self, qr_code: &QrCode) -> Result<RgbImage, QrGeneratorError> {
+    /// Generate a QR code as a module matrix, for drawing it as vector shapes
+    pub fn generate_modules(&self, data: &str) -> Result<QrModules, QrGeneratorError> {
+        let qr_code = self.encode(data)?;
+        Ok(QrModules {
+            width: qr_code.width(),
+            dark: qr_code
+                .to_colors()
+                .into_iter()
+                .map(|color| color == Color::Dark)
+                .collect(),
+            quiet_zone: self.options.quiet_zone as usize,
+            foreground: self.options.foreground_rgb(),
+            background: self.options.background_rgb(),
+        })
+    }
+
+    fn encode(&self, data: &str) -> Result<QrCode, QrGeneratorError> {
+        QrCode::with_error_correction_level(data, self.options.error_correction.ec_level())
+            .map_err(|e| QrGeneratorError::QrCodeGeneration(e.to_string()))
+    }
+
+    /// The QR code in the configured image format for HTML pages
+    pub fn generate_image(&self, data: &str) -> Result<QrImage, QrGeneratorError> {
+        match self.options.image_format {
+            QrImageFormat::Svg => self.generate_modules(data).map(QrImage::Svg),
+            QrImageFormat::Png => self.generate_qr_base64(data).map(QrImage::Png),
+        }
+    }
+
+    /// The QR code as an image with the largest whole number of pixels per module
+    /// that fits in `max_width` pixels, for printers that take bitmaps
+    pub fn generate_raster(
+        &self,
+        data: &str,
+        max_width: u32,
+    ) -> Result<RgbImage, QrGeneratorError> {
+        let qr_code = self.encode(data)?;
+        let modules = qr_code.width() as u32 + 2 * self.options.quiet_zone;
+        let scale = (max_width / modules).max(1);
+        self.qr_code_to_image(&qr_code, scale as usize)
+    }
+
+    /// Encode as PNG, recording the configured DPI so the image prints at a known size
+    fn encode_png(&self, image: &RgbImage) -> Result<Vec<u8>, QrGeneratorError> {
+        let error = |e: png::EncodingError| QrGeneratorError::ImageEncoding(e.to_string());
+        let mut png_bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_bytes, image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let pixels_per_metre = (self.options.dpi as f64 / 0.0254).round() as u32;
+        encoder.set_pixel_dims(Some(png::PixelDimensions {
+            xppu: pixels_per_metre,
+            yppu: pixels_per_metre,
+            unit: png::Unit::Meter,
+        }));
+        let mut writer = encoder.write_header().map_err(error)?;
+        writer.write_image_data(image.as_raw()).map_err(error)?;
+        writer.finish().map_err(error)?;
+        Ok(png_bytes)
+    }
+
+    fn qr_code_to_image(
+        &self,
+        qr_code: &QrCode,
+        scale: usize,
+    ) -> Result<RgbImage, QrGeneratorError> {
         let modules = qr_code.to_colors();
         let width = qr_code.width();
-        let scale = 4; // Scale factor to make QR code smaller for better page density
-        let border = 2 * scale; // Smaller border around QR code
+        let border = self.options.quiet_zone as usize * scale;
+        let (r, g, b) = self.options.foreground_rgb();
+        let dark = Rgb([r, g, b]);
+        let (r, g, b) = self.options.background_rgb();
+        let light = Rgb([r, g, b]);
 
         let img_width = (width * scale) + (border * 2);
         let img_height = img_width;
 
         let mut image: RgbImage = ImageBuffer::new(img_width as u32, img_height as u32);
 
-        // Fill with white background
+        // Fill with the background colour
         for pixel in image.pixels_mut() {
-            *pixel = Rgb([255, 255, 255]);
+            *pixel = light;
         }
 
         // Draw QR code modules
         for (y, row) in modules.chunks(width).enumerate() {
             for (x, &module) in row.iter().enumerate() {
                 let color = match module {
-                    Color::Dark => Rgb([0, 0, 0]),        // Black for dark modules
-                    Color::Light => Rgb([255, 255, 255]), // White for light modules
+                    Color::Dark => dark,
+                    Color::Light => light,
                 };
 
                 // Draw scaled module
@@ -82,6 +443,9 @@ pub enum QrGeneratorError {
 
     #[error("Image encoding failed: {0}")]
     ImageEncoding(String),
+
+    #[error("Invalid QR settings: {0}")]
+    InvalidOptions(String),
 }
 
 #[cfg(test)]
@@ -90,7 +454,7 @@ mod tests {
 
     #[test]
     fn test_generate_qr_base64() {
-        let generator = QrGenerator::new();
+        let generator = QrGenerator::default();
         let result = generator.generate_qr_base64("TEST");
         assert!(result.is_ok());
 
@@ -101,9 +465,139 @@ mod tests {
         assert!(general_purpose::STANDARD.decode(&base64).is_ok());
     }
 
+    #[test]
+    fn test_generate_raster() {
+        // 21 modules plus a quiet zone of 2 on each side fit 8 times into 200 pixels
+        let image = QrGenerator::default().generate_raster("TEST", 200).unwrap();
+        assert_eq!(image.width(), 25 * 8);
+        assert_eq!(image.height(), 25 * 8);
+        assert_eq!(image.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(image.get_pixel(16, 16), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_generate_modules() {
+        let modules = QrGenerator::default().generate_modules("TEST").unwrap();
+        assert_eq!(modules.width, 21);
+        // Top-left finder pattern: dark corner, light separator ring
+        assert!(modules.is_dark(0, 0));
+        assert!(!modules.is_dark(7, 0));
+    }
+
+    #[test]
+    fn test_options_change_the_image() {
+        let options = QrOptions {
+            error_correction: ErrorCorrection::H,
+            module_size: 10,
+            quiet_zone: 4,
+            foreground: "#1e3a8a".to_string(),
+            dpi: 600,
+            ..QrOptions::default()
+        };
+        assert!(options.validate().is_ok());
+        let generator = QrGenerator::with_options(options);
+        let png = general_purpose::STANDARD
+            .decode(generator.generate_qr_base64("TEST").unwrap())
+            .unwrap();
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        assert_eq!(dims.xppu, 23622); // 600 dpi
+                                      // Version 1 at level H is still 21 modules, plus 4 quiet modules per side
+        assert_eq!(reader.info().width, (21 + 8) * 10);
+
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(40, 40).0, [0x1e, 0x3a, 0x8a]);
+
+        let modules = generator.generate_modules("TEST").unwrap();
+        assert_eq!(modules.quiet_zone, 4);
+        assert_eq!(modules.foreground, (0x1e, 0x3a, 0x8a));
+    }
+
+    #[test]
+    fn test_svg_output() {
+        let generator = QrGenerator::default();
+        let modules = generator.generate_modules("TEST").unwrap();
+        assert_eq!(modules.size(), 25);
+        assert_eq!(modules.foreground_hex(), "#000000");
+        assert_eq!(modules.background_hex(), "#ffffff");
+        // The top-left finder pattern starts with a 7-module run after the quiet zone
+        assert!(modules.svg_path().starts_with("M2 2h7v1h-7z"));
+
+        let dark: usize = modules.dark_runs().map(|(_, start, end)| end - start).sum();
+        let expected = (0..21)
+            .flat_map(|y| (0..21).map(move |x| (x, y)))
+            .filter(|&(x, y)| modules.is_dark(x, y))
+            .count();
+        assert_eq!(dark, expected);
+
+        // Much smaller than the same code as a base64 PNG
+        let png = generator
+            .generate_qr_base64("WIFI:T:WPA;S:Lobby;P:secret;;")
+            .unwrap();
+        let path = generator
+            .generate_modules("WIFI:T:WPA;S:Lobby;P:secret;;")
+            .unwrap()
+            .svg_path();
+        assert!(path.len() < png.len());
+
+        assert!(matches!(
+            generator.generate_image("TEST").unwrap(),
+            QrImage::Svg(_)
+        ));
+        let png_generator = QrGenerator::with_options(QrOptions {
+            image_format: QrImageFormat::Png,
+            ..QrOptions::default()
+        });
+        assert!(matches!(
+            png_generator.generate_image("TEST").unwrap(),
+            QrImage::Png(_)
+        ));
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert!(QrOptions::default().validate().is_ok());
+        let invalid = [
+            QrOptions {
+                module_size: 0,
+                ..QrOptions::default()
+            },
+            QrOptions {
+                quiet_zone: 11,
+                ..QrOptions::default()
+            },
+            QrOptions {
+                dpi: 10,
+                ..QrOptions::default()
+            },
+            QrOptions {
+                foreground: "black".to_string(),
+                ..QrOptions::default()
+            },
+            // Inverted and low-contrast codes scan badly
+            QrOptions {
+                foreground: "#ffffff".to_string(),
+                background: "#000000".to_string(),
+                ..QrOptions::default()
+            },
+            QrOptions {
+                foreground: "#cccccc".to_string(),
+                ..QrOptions::default()
+            },
+        ];
+        for options in invalid {
+            assert!(options.validate().is_err(), "{:?}", options);
+        }
+        assert_eq!("q".parse::<ErrorCorrection>().unwrap(), ErrorCorrection::Q);
+        assert!("X".parse::<ErrorCorrection>().is_err());
+    }
+
     #[test]
     fn test_generate_wifi_qr() {
-        let generator = QrGenerator::new();
+        let generator = QrGenerator::default();
         let wifi_data = "WIFI:T:WPA;S:TestNetwork;P:password123;H:false;;";
         let result = generator.generate_qr_base64(wifi_data);
         assert!(result.is_ok());
@@ -0,0 +1,817 @@
+//! A small RADIUS server (RFC 2865 and 2866) for access points and controllers
+//! with external captive portal authentication. Guests log in with the voucher
+//! code as their PAP password, which redeems the voucher on the access point's
+//! network like `/api/redeem` does; accounting records their sessions.
+
+use crate::config::{RadiusClient, RadiusConfig};
+use crate::database::Database;
+use crate::redemption::{normalize_mac, Device, RedeemError, VoucherSession};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Recorded as who redeemed a voucher over RADIUS
+const RADIUS_USER: &str = "radius";
+
+const ACCESS_REQUEST: u8 = 1;
+const ACCESS_ACCEPT: u8 = 2;
+const ACCESS_REJECT: u8 = 3;
+const ACCOUNTING_REQUEST: u8 = 4;
+const ACCOUNTING_RESPONSE: u8 = 5;
+
+const USER_NAME: u8 = 1;
+const USER_PASSWORD: u8 = 2;
+const NAS_IP_ADDRESS: u8 = 4;
+const FRAMED_IP_ADDRESS: u8 = 8;
+const REPLY_MESSAGE: u8 = 18;
+const CLASS: u8 = 25;
+const SESSION_TIMEOUT: u8 = 27;
+const CALLING_STATION_ID: u8 = 31;
+const NAS_IDENTIFIER: u8 = 32;
+const ACCT_STATUS_TYPE: u8 = 40;
+const ACCT_INPUT_OCTETS: u8 = 42;
+const ACCT_OUTPUT_OCTETS: u8 = 43;
+const ACCT_SESSION_ID: u8 = 44;
+const ACCT_SESSION_TIME: u8 = 46;
+const ACCT_INPUT_GIGAWORDS: u8 = 52;
+const ACCT_OUTPUT_GIGAWORDS: u8 = 53;
+const MESSAGE_AUTHENTICATOR: u8 = 80;
+
+const ACCT_START: u32 = 1;
+const ACCT_STOP: u32 = 2;
+const ACCT_INTERIM_UPDATE: u32 = 3;
+
+const HEADER_LEN: usize = 20;
+const MAX_PACKET_LEN: usize = 4096;
+const MAX_ATTRIBUTE_LEN: usize = 253;
+
+/// How long answers are kept to repeat them for retransmitted requests
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// Bind the configured ports and serve requests in the background.
+/// Returns the bound addresses, none when the server is not configured.
+pub async fn start(
+    config: &RadiusConfig,
+    database: Arc<Database>,
+) -> anyhow::Result<Vec<SocketAddr>> {
+    let Some(secret) = &config.secret else {
+        return Ok(Vec::new());
+    };
+    for client in &config.clients {
+        anyhow::ensure!(
+            database.get_network(&client.network_id).await?.is_some(),
+            "radius.clients in config.toml names network {}, which does not exist",
+            client.network_id
+        );
+    }
+    let server = Arc::new(RadiusServer::new(database, secret, config));
+    let mut bound = Vec::new();
+    for address in [&config.auth_address, &config.accounting_address]
+        .into_iter()
+        .flatten()
+    {
+        let socket = UdpSocket::bind(address)
+            .await
+            .with_context(|| format!("Failed to listen for RADIUS requests on {}", address))?;
+        bound.push(socket.local_addr()?);
+        tokio::spawn(server.clone().serve(socket));
+    }
+    Ok(bound)
+}
+
+/// Recent answers by client address, identifier and request authenticator
+type Answers = HashMap<(SocketAddr, u8, [u8; 16]), (Instant, Vec<u8>)>;
+
+pub struct RadiusServer {
+    database: Arc<Database>,
+    secret: Vec<u8>,
+    clients: Vec<RadiusClient>,
+    require_message_authenticator: bool,
+}
+
+impl RadiusServer {
+    pub fn new(database: Arc<Database>, secret: &str, config: &RadiusConfig) -> Self {
+        Self {
+            database,
+            secret: secret.as_bytes().to_vec(),
+            clients: config.clients.clone(),
+            require_message_authenticator: config.require_message_authenticator,
+        }
+    }
+
+    /// Answer requests on `socket` until the process ends
+    pub async fn serve(self: Arc<Self>, socket: UdpSocket) {
+        let mut buffer = vec![0u8; MAX_PACKET_LEN];
+        let mut answered = Answers::new();
+
+        loop {
+            let (len, source) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(error) => {
+                    eprintln!("RADIUS: receive failed: {}", error);
+                    continue;
+                }
+            };
+            let Ok(request) = Packet::parse(&buffer[..len]) else {
+                continue;
+            };
+
+            answered.retain(|_, (at, _)| at.elapsed() < DUPLICATE_WINDOW);
+            let key = (source, request.identifier, request.authenticator);
+            let response = match answered.get(&key) {
+                // A retransmission gets the same answer, so a login that
+                // redeemed the voucher is not refused the second time
+                Some((_, response)) => Some(response.clone()),
+                None => self.handle(&request, source.ip()).await,
+            };
+            if let Some(response) = response {
+                if let Err(error) = socket.send_to(&response, source).await {
+                    eprintln!("RADIUS: reply to {} failed: {}", source, error);
+                }
+                answered.insert(key, (Instant::now(), response));
+            }
+        }
+    }
+
+    /// The answer to a request, or `None` for requests to drop silently
+    async fn handle(&self, request: &Packet, source: IpAddr) -> Option<Vec<u8>> {
+        match request.code {
+            ACCESS_REQUEST => self.authenticate(request, source).await,
+            ACCOUNTING_REQUEST => self.account(request, source).await,
+            _ => None,
+        }
+    }
+
+    /// The network of the access point that sent `request`; requests from
+    /// access points that aren't configured are dropped
+    fn network_for(&self, request: &Packet, source: IpAddr) -> Option<&str> {
+        let nas_identifier = request.text(NAS_IDENTIFIER);
+        let client = self
+            .clients
+            .iter()
+            .find(|client| client.matches(source, nas_identifier.as_deref()));
+        if client.is_none() {
+            eprintln!(
+                "RADIUS: dropped a request from unknown client {} (NAS-Identifier {})",
+                source,
+                nas_identifier.as_deref().unwrap_or("none")
+            );
+        }
+        client.map(|client| client.network_id.as_str())
+    }
+
+    async fn authenticate(&self, request: &Packet, source: IpAddr) -> Option<Vec<u8>> {
+        match request.attribute(MESSAGE_AUTHENTICATOR) {
+            Some(_) if !request.has_valid_message_authenticator(&self.secret) => return None,
+            None if self.require_message_authenticator => return None,
+            _ => {}
+        }
+        let network_id = self.network_for(request, source)?;
+        let Some(hidden) = request.attribute(USER_PASSWORD) else {
+            return Some(self.reject(request, "Only PAP logins are supported"));
+        };
+        let Some(code) = reveal_password(hidden, &self.secret, &request.authenticator)
+            .and_then(|password| String::from_utf8(password).ok())
+        else {
+            return Some(self.reject(request, "Invalid password"));
+        };
+        let device = request.device();
+
+        let redeemed = self
+            .database
+            .redeem_voucher(network_id, code.trim(), &device, RADIUS_USER)
+            .await;
+
+        match redeemed {
+            Ok(redemption) => {
+                // Class comes back in the session's accounting requests
+                let mut attributes = vec![(CLASS, redemption.voucher_id.into_bytes())];
+                if let Some(seconds) = redemption.remaining_seconds {
+                    let seconds = u32::try_from(seconds).unwrap_or(u32::MAX).max(1);
+                    attributes.push((SESSION_TIMEOUT, seconds.to_be_bytes().to_vec()));
+                }
+                Some(respond(request, ACCESS_ACCEPT, attributes, &self.secret))
+            }
+            Err(error) => match error.downcast_ref::<RedeemError>() {
+                Some(refusal) => Some(self.reject(request, &refusal.to_string())),
+                // Unanswered, the access point asks again
+                None => {
+                    eprintln!("RADIUS: login failed: {:#}", error);
+                    None
+                }
+            },
+        }
+    }
+
+    fn reject(&self, request: &Packet, message: &str) -> Vec<u8> {
+        let mut message = message.as_bytes().to_vec();
+        message.truncate(MAX_ATTRIBUTE_LEN);
+        respond(
+            request,
+            ACCESS_REJECT,
+            vec![(REPLY_MESSAGE, message)],
+            &self.secret,
+        )
+    }
+
+    async fn account(&self, request: &Packet, source: IpAddr) -> Option<Vec<u8>> {
+        if !request.has_valid_accounting_authenticator(&self.secret) {
+            return None;
+        }
+        let network_id = self.network_for(request, source)?;
+        let ack = respond(request, ACCOUNTING_RESPONSE, Vec::new(), &self.secret);
+        let status = request.integer(ACCT_STATUS_TYPE);
+        if !matches!(status, Some(ACCT_START | ACCT_STOP | ACCT_INTERIM_UPDATE)) {
+            // Accounting-On and -Off are about the access point itself
+            return Some(ack);
+        }
+        let Some(session_id) = request.text(ACCT_SESSION_ID) else {
+            return Some(ack);
+        };
+
+        let voucher = match request.text(CLASS) {
+            Some(voucher_id) => self.database.get_voucher(&voucher_id).await,
+            None => match request.text(USER_NAME) {
+                Some(code) => self.database.get_voucher_by_code(code.trim()).await,
+                None => Ok(None),
+            },
+        };
+        let voucher = match voucher {
+            Ok(Some(voucher)) if voucher.network_id.as_deref() == Some(network_id) => voucher,
+            // Sessions of other users of the access point are not ours to record
+            Ok(_) => return Some(ack),
+            Err(error) => {
+                eprintln!("RADIUS: accounting failed: {:#}", error);
+                return None;
+            }
+        };
+
+        let now = chrono::Utc::now();
+        let session_seconds = request.integer(ACCT_SESSION_TIME).unwrap_or(0);
+        let device = request.device();
+        let session = VoucherSession {
+            voucher_id: voucher.id,
+            session_id,
+            nas: request
+                .text(NAS_IDENTIFIER)
+                .or_else(|| request.ip(NAS_IP_ADDRESS).map(|ip| ip.to_string()))
+                .unwrap_or_else(|| source.to_string()),
+            client_mac: device.mac,
+            client_ip: device.ip.map(|ip| ip.to_string()),
+            started_at: now - chrono::Duration::seconds(session_seconds.into()),
+            stopped_at: (status == Some(ACCT_STOP)).then_some(now),
+            session_seconds,
+            input_bytes: request.octets(ACCT_INPUT_OCTETS, ACCT_INPUT_GIGAWORDS),
+            output_bytes: request.octets(ACCT_OUTPUT_OCTETS, ACCT_OUTPUT_GIGAWORDS),
+        };
+        match self.database.record_voucher_session(&session).await {
+            Ok(()) => Some(ack),
+            Err(error) => {
+                eprintln!("RADIUS: accounting failed: {:#}", error);
+                None
+            }
+        }
+    }
+}
+
+/// A RADIUS packet with its attributes in the order they were sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Packet {
+    code: u8,
+    identifier: u8,
+    authenticator: [u8; 16],
+    attributes: Vec<(u8, Vec<u8>)>,
+}
+
+impl Packet {
+    fn parse(data: &[u8]) -> Result<Self, RadiusError> {
+        if data.len() < HEADER_LEN {
+            return Err(RadiusError::Malformed);
+        }
+        // Octets past the length are padding
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if !(HEADER_LEN..=MAX_PACKET_LEN).contains(&length) || length > data.len() {
+            return Err(RadiusError::Malformed);
+        }
+
+        let mut attributes = Vec::new();
+        let mut rest = &data[HEADER_LEN..length];
+        while !rest.is_empty() {
+            let attribute_len = *rest.get(1).ok_or(RadiusError::Malformed)? as usize;
+            if attribute_len < 2 || attribute_len > rest.len() {
+                return Err(RadiusError::Malformed);
+            }
+            attributes.push((rest[0], rest[2..attribute_len].to_vec()));
+            rest = &rest[attribute_len..];
+        }
+
+        Ok(Self {
+            code: data[0],
+            identifier: data[1],
+            authenticator: data[4..HEADER_LEN].try_into().expect("16 octets"),
+            attributes,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.code, self.identifier, 0, 0];
+        bytes.extend_from_slice(&self.authenticator);
+        for (kind, value) in &self.attributes {
+            bytes.push(*kind);
+            bytes.push((value.len() + 2) as u8);
+            bytes.extend_from_slice(value);
+        }
+        let length = (bytes.len() as u16).to_be_bytes();
+        bytes[2..4].copy_from_slice(&length);
+        bytes
+    }
+
+    fn attribute(&self, kind: u8) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn text(&self, kind: u8) -> Option<String> {
+        self.attribute(kind)
+            .and_then(|value| String::from_utf8(value.to_vec()).ok())
+            .filter(|text| !text.is_empty())
+    }
+
+    fn integer(&self, kind: u8) -> Option<u32> {
+        self.attribute(kind)
+            .and_then(|value| value.try_into().ok())
+            .map(u32::from_be_bytes)
+    }
+
+    fn ip(&self, kind: u8) -> Option<IpAddr> {
+        self.attribute(kind)
+            .and_then(|value| <[u8; 4]>::try_from(value).ok())
+            .map(|octets| IpAddr::V4(Ipv4Addr::from(octets)))
+    }
+
+    /// A byte counter with its 4 GiB wraps counted in a gigawords attribute
+    fn octets(&self, kind: u8, gigawords: u8) -> u64 {
+        let wraps = u64::from(self.integer(gigawords).unwrap_or(0));
+        (wraps << 32) + u64::from(self.integer(kind).unwrap_or(0))
+    }
+
+    /// The guest's device; access points send its MAC address as Calling-Station-Id
+    fn device(&self) -> Device {
+        Device {
+            mac: self
+                .text(CALLING_STATION_ID)
+                .and_then(|id| normalize_mac(&id)),
+            ip: self.ip(FRAMED_IP_ADDRESS),
+        }
+    }
+
+    /// RFC 3579: an HMAC-MD5 of the packet with the attribute's value zeroed
+    fn has_valid_message_authenticator(&self, secret: &[u8]) -> bool {
+        let Some(received) = self.attribute(MESSAGE_AUTHENTICATOR) else {
+            return false;
+        };
+        let mut zeroed = self.clone();
+        for (kind, value) in &mut zeroed.attributes {
+            if *kind == MESSAGE_AUTHENTICATOR {
+                value.fill(0);
+            }
+        }
+        let mut mac = Hmac::<Md5>::new_from_slice(secret).expect("HMAC takes keys of any length");
+        mac.update(&zeroed.encode());
+        mac.verify_slice(received).is_ok()
+    }
+
+    /// RFC 2866: an MD5 of the packet with a zero authenticator and the secret
+    fn has_valid_accounting_authenticator(&self, secret: &[u8]) -> bool {
+        let zeroed = Self {
+            authenticator: [0; 16],
+            ..self.clone()
+        };
+        md5(&[&zeroed.encode(), secret]) == self.authenticator
+    }
+}
+
+/// A response to `request`, signed with the response authenticator. Access
+/// responses also carry a Message-Authenticator, as RFC 3579 recommends.
+fn respond(request: &Packet, code: u8, attributes: Vec<(u8, Vec<u8>)>, secret: &[u8]) -> Vec<u8> {
+    let mut response = Packet {
+        code,
+        identifier: request.identifier,
+        authenticator: request.authenticator,
+        attributes,
+    };
+    if code != ACCOUNTING_RESPONSE {
+        response
+            .attributes
+            .push((MESSAGE_AUTHENTICATOR, vec![0; 16]));
+        let mut mac = Hmac::<Md5>::new_from_slice(secret).expect("HMAC takes keys of any length");
+        mac.update(&response.encode());
+        if let Some((_, value)) = response.attributes.last_mut() {
+            *value = mac.finalize().into_bytes().to_vec();
+        }
+    }
+
+    let mut bytes = response.encode();
+    let authenticator = md5(&[&bytes, secret]);
+    bytes[4..HEADER_LEN].copy_from_slice(&authenticator);
+    bytes
+}
+
+fn md5(parts: &[&[u8]]) -> [u8; 16] {
+    let mut hasher = Md5::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Undo the User-Password hiding of RFC 2865 section 5.2
+fn reveal_password(hidden: &[u8], secret: &[u8], authenticator: &[u8; 16]) -> Option<Vec<u8>> {
+    if hidden.is_empty() || hidden.len() > 128 || !hidden.len().is_multiple_of(16) {
+        return None;
+    }
+    let mut password = Vec::with_capacity(hidden.len());
+    let mut previous: &[u8] = authenticator;
+    for chunk in hidden.chunks(16) {
+        let key = md5(&[secret, previous]);
+        password.extend(chunk.iter().zip(key).map(|(c, k)| c ^ k));
+        previous = chunk;
+    }
+    while password.last() == Some(&0) {
+        password.pop();
+    }
+    Some(password)
+}
+
+#[derive(Debug, thiserror::Error)]
+enum RadiusError {
+    #[error("malformed RADIUS packet")]
+    Malformed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use crate::voucher::{Voucher, VoucherState};
+    use crate::wifi_network::WiFiNetwork;
+
+    const SECRET: &str = "testing123";
+
+    fn hex(text: &str) -> Vec<u8> {
+        text.split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect()
+    }
+
+    /// Hide a password the way a client does
+    fn hide_password(password: &str, secret: &[u8], authenticator: &[u8; 16]) -> Vec<u8> {
+        let mut padded = password.as_bytes().to_vec();
+        padded.resize(padded.len().div_ceil(16).max(1) * 16, 0);
+        let mut hidden: Vec<u8> = Vec::with_capacity(padded.len());
+        for chunk in padded.chunks(16) {
+            let previous = match hidden.len() {
+                0 => authenticator.to_vec(),
+                len => hidden[len - 16..].to_vec(),
+            };
+            let key = md5(&[secret, &previous]);
+            hidden.extend(chunk.iter().zip(key).map(|(c, k)| c ^ k));
+        }
+        hidden
+    }
+
+    /// An Access-Request for `code`, signed with a Message-Authenticator when `signed`
+    fn login_request(identifier: u8, code: &str, mac: &str, nas: &str, signed: bool) -> Packet {
+        let authenticator: [u8; 16] = rand::random();
+        let mut request = Packet {
+            code: ACCESS_REQUEST,
+            identifier,
+            authenticator,
+            attributes: vec![
+                (USER_NAME, code.as_bytes().to_vec()),
+                (
+                    USER_PASSWORD,
+                    hide_password(code, SECRET.as_bytes(), &authenticator),
+                ),
+                (CALLING_STATION_ID, mac.as_bytes().to_vec()),
+                (NAS_IDENTIFIER, nas.as_bytes().to_vec()),
+            ],
+        };
+        if signed {
+            request
+                .attributes
+                .push((MESSAGE_AUTHENTICATOR, vec![0; 16]));
+            let mut hmac = Hmac::<Md5>::new_from_slice(SECRET.as_bytes()).unwrap();
+            hmac.update(&request.encode());
+            request.attributes.last_mut().unwrap().1 = hmac.finalize().into_bytes().to_vec();
+        }
+        request
+    }
+
+    /// A client that sends one request and waits for the answer
+    struct Client {
+        socket: UdpSocket,
+        server: SocketAddr,
+        identifier: u8,
+    }
+
+    impl Client {
+        async fn new(server: SocketAddr) -> Self {
+            Self {
+                socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+                server,
+                identifier: 0,
+            }
+        }
+
+        async fn login(&mut self, code: &str, mac: &str) -> Packet {
+            self.identifier += 1;
+            self.send(login_request(self.identifier, code, mac, "ap-lobby", true))
+                .await
+        }
+
+        async fn account(
+            &mut self,
+            status: u32,
+            class: &[u8],
+            attributes: Vec<(u8, Vec<u8>)>,
+        ) -> Packet {
+            self.identifier += 1;
+            let mut request = Packet {
+                code: ACCOUNTING_REQUEST,
+                identifier: self.identifier,
+                authenticator: [0; 16],
+                attributes: vec![
+                    (ACCT_STATUS_TYPE, status.to_be_bytes().to_vec()),
+                    (ACCT_SESSION_ID, b"session-1".to_vec()),
+                    (CLASS, class.to_vec()),
+                    (NAS_IDENTIFIER, b"ap-lobby".to_vec()),
+                ],
+            };
+            request.attributes.extend(attributes);
+            request.authenticator = md5(&[&request.encode(), SECRET.as_bytes()]);
+            self.send(request).await
+        }
+
+        async fn send(&self, request: Packet) -> Packet {
+            self.socket
+                .send_to(&request.encode(), self.server)
+                .await
+                .unwrap();
+            let mut buffer = [0u8; MAX_PACKET_LEN];
+            let (len, _) =
+                tokio::time::timeout(Duration::from_secs(5), self.socket.recv_from(&mut buffer))
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+            let response = Packet::parse(&buffer[..len]).unwrap();
+            assert_eq!(response.identifier, request.identifier);
+            let check = Packet {
+                authenticator: request.authenticator,
+                ..response.clone()
+            };
+            assert_eq!(
+                md5(&[&check.encode(), SECRET.as_bytes()]),
+                response.authenticator,
+                "response authenticator"
+            );
+            if response.code != ACCOUNTING_RESPONSE {
+                assert!(check.has_valid_message_authenticator(SECRET.as_bytes()));
+            }
+            response
+        }
+    }
+
+    #[test]
+    fn test_rfc_2865_example() {
+        // The Access-Request of RFC 2865 section 7.1, secret "xyzzy5461"
+        let request = Packet::parse(&hex("01 00 00 38 0f 40 3f 94 73 97 80 57 bd 83 d5 cb
+             98 f4 22 7a 01 06 6e 65 6d 6f 02 12 0d be 70 8d
+             93 d4 13 ce 31 96 e4 3f 78 2a 0a ee 04 06 c0 a8
+             01 10 05 06 00 00 00 03"))
+        .unwrap();
+        assert_eq!(request.text(USER_NAME).as_deref(), Some("nemo"));
+        assert_eq!(
+            request.ip(NAS_IP_ADDRESS),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 16)))
+        );
+        let password = reveal_password(
+            request.attribute(USER_PASSWORD).unwrap(),
+            b"xyzzy5461",
+            &request.authenticator,
+        )
+        .unwrap();
+        assert_eq!(password, b"arctangent");
+        assert_eq!(request.encode().len(), 0x38);
+
+        let long = "a-password-longer-than-one-block";
+        assert_eq!(
+            reveal_password(
+                &hide_password(long, b"secret", &request.authenticator),
+                b"secret",
+                &request.authenticator
+            )
+            .unwrap(),
+            long.as_bytes()
+        );
+        assert!(Packet::parse(&hex("01 00 00 16 00 00")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_and_accounting() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let database = Arc::new(database);
+        let other_network = WiFiNetwork::new(
+            "Pool".to_string(),
+            "Pool-Guest".to_string(),
+            "secret".to_string(),
+            None,
+        );
+        database.create_network(&other_network).await.unwrap();
+        let mut vouchers: Vec<Voucher> = ["DAY-PASS", "REVOKED"]
+            .iter()
+            .map(|code| {
+                let mut voucher = Voucher::new(code.to_string());
+                voucher.network_id = Some(network.id.clone());
+                voucher.state = VoucherState::Issued;
+                voucher.validity.duration_minutes = Some(1440);
+                voucher
+            })
+            .collect();
+        vouchers.push(Voucher {
+            network_id: Some(other_network.id.clone()),
+            state: VoucherState::Issued,
+            ..Voucher::new("POOL-PASS".to_string())
+        });
+        database.create_vouchers(&vouchers).await.unwrap();
+        database
+            .set_voucher_state(&vouchers[1].id, VoucherState::Revoked, "admin")
+            .await
+            .unwrap();
+
+        let config = RadiusConfig {
+            auth_address: Some("127.0.0.1:0".to_string()),
+            accounting_address: Some("127.0.0.1:0".to_string()),
+            secret: Some(SECRET.to_string()),
+            clients: vec![RadiusClient {
+                address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                nas_identifier: Some("ap-lobby".to_string()),
+                network_id: network.id.clone(),
+            }],
+            ..RadiusConfig::default()
+        };
+        let bound = start(&config, database.clone()).await.unwrap();
+        let mut auth = Client::new(bound[0]).await;
+        let mut accounting = Client::new(bound[1]).await;
+
+        let accept = auth.login("DAY-PASS", "AA-BB-CC-DD-EE-FF").await;
+        assert_eq!(accept.code, ACCESS_ACCEPT);
+        let timeout = accept.integer(SESSION_TIMEOUT).unwrap();
+        assert!((86390..=86400).contains(&timeout));
+        let class = accept.attribute(CLASS).unwrap().to_vec();
+        assert_eq!(class, vouchers[0].id.as_bytes());
+
+        // The same device logs in again, another one is refused
+        assert_eq!(
+            auth.login("DAY-PASS", "aa:bb:cc:dd:ee:ff").await.code,
+            ACCESS_ACCEPT
+        );
+        let reject = auth.login("DAY-PASS", "11-22-33-44-55-66").await;
+        assert_eq!(reject.code, ACCESS_REJECT);
+        assert_eq!(
+            reject.text(REPLY_MESSAGE).as_deref(),
+            Some("the voucher was already used on another device")
+        );
+        assert_eq!(auth.login("REVOKED", "").await.code, ACCESS_REJECT);
+        assert_eq!(auth.login("NO-SUCH-CODE", "").await.code, ACCESS_REJECT);
+        // Another network's voucher doesn't work on this access point, and stays unused
+        assert_eq!(auth.login("POOL-PASS", "").await.code, ACCESS_REJECT);
+        assert_eq!(
+            database
+                .get_voucher(&vouchers[2].id)
+                .await
+                .unwrap()
+                .unwrap()
+                .state,
+            VoucherState::Issued
+        );
+
+        let voucher = database
+            .get_voucher(&vouchers[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(voucher.state, VoucherState::Redeemed);
+        assert_eq!(voucher.client_mac.as_deref(), Some("aa:bb:cc:dd:ee:ff"));
+        let transitions = database.get_voucher_transitions(&voucher.id).await.unwrap();
+        assert_eq!(transitions[0].changed_by.as_deref(), Some(RADIUS_USER));
+
+        let start = accounting
+            .account(
+                ACCT_START,
+                &class,
+                vec![(FRAMED_IP_ADDRESS, vec![10, 0, 0, 7])],
+            )
+            .await;
+        assert_eq!(start.code, ACCOUNTING_RESPONSE);
+        let stop = accounting
+            .account(
+                ACCT_STOP,
+                &class,
+                vec![
+                    (ACCT_SESSION_TIME, 600u32.to_be_bytes().to_vec()),
+                    (ACCT_INPUT_OCTETS, 1000u32.to_be_bytes().to_vec()),
+                    (ACCT_OUTPUT_OCTETS, 5u32.to_be_bytes().to_vec()),
+                    (ACCT_OUTPUT_GIGAWORDS, 1u32.to_be_bytes().to_vec()),
+                ],
+            )
+            .await;
+        assert_eq!(stop.code, ACCOUNTING_RESPONSE);
+
+        let sessions = database.get_voucher_sessions(&voucher.id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.session_id, "session-1");
+        assert_eq!(session.nas, "ap-lobby");
+        assert_eq!(session.client_ip.as_deref(), Some("10.0.0.7"));
+        assert!(session.stopped_at.is_some());
+        assert_eq!(session.session_seconds, 600);
+        assert_eq!(session.input_bytes, 1000);
+        assert_eq!(session.output_bytes, (1 << 32) + 5);
+    }
+
+    #[tokio::test]
+    async fn test_drops_requests_with_wrong_secret() {
+        let database = Arc::new(test_util::database().await);
+        let server = RadiusServer::new(database, "other-secret", &RadiusConfig::default());
+
+        let mut request = Packet {
+            code: ACCOUNTING_REQUEST,
+            identifier: 1,
+            authenticator: [0; 16],
+            attributes: vec![(ACCT_STATUS_TYPE, ACCT_START.to_be_bytes().to_vec())],
+        };
+        request.authenticator = md5(&[&request.encode(), SECRET.as_bytes()]);
+        assert!(server
+            .handle(&request, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await
+            .is_none());
+
+        let request = Packet {
+            code: ACCESS_REQUEST,
+            identifier: 2,
+            authenticator: [7; 16],
+            attributes: vec![
+                (USER_PASSWORD, vec![1; 16]),
+                (MESSAGE_AUTHENTICATOR, vec![0; 16]),
+            ],
+        };
+        assert!(server
+            .handle(&request, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drops_unknown_clients_and_unsigned_logins() {
+        let (database, network) = test_util::database_with_lobby().await;
+        let database = Arc::new(database);
+        database
+            .create_vouchers(&[Voucher {
+                network_id: Some(network.id.clone()),
+                state: VoucherState::Issued,
+                ..Voucher::new("DAY-PASS".to_string())
+            }])
+            .await
+            .unwrap();
+        let mut config = RadiusConfig {
+            clients: vec![RadiusClient {
+                address: None,
+                nas_identifier: Some("ap-lobby".to_string()),
+                network_id: network.id.clone(),
+            }],
+            ..RadiusConfig::default()
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let server = RadiusServer::new(database.clone(), SECRET, &config);
+        let unsigned = login_request(1, "DAY-PASS", "", "ap-lobby", false);
+        assert!(server.handle(&unsigned, localhost).await.is_none());
+        let stranger = login_request(2, "DAY-PASS", "", "ap-elsewhere", true);
+        assert!(server.handle(&stranger, localhost).await.is_none());
+
+        // Access points that can't sign their requests work once allowed to
+        config.require_message_authenticator = false;
+        let server = RadiusServer::new(database, SECRET, &config);
+        let accept = Packet::parse(&server.handle(&unsigned, localhost).await.unwrap()).unwrap();
+        assert_eq!(accept.code, ACCESS_ACCEPT);
+    }
+}
@@ -0,0 +1,182 @@
+//! Redeeming voucher codes for captive portals, so a portal can check the codes
+//! guests enter against this app instead of keeping its own copy.
+//!
+//! A code is redeemed once, by the first device that logs in with it. That
+//! device may log in again, for example after roaming, until its time is up.
+
+use crate::voucher::{Validity, Voucher};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// The guest device logging in, as reported by the portal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Device {
+    /// Lowercase and colon separated, e.g. `aa:bb:cc:dd:ee:ff`
+    pub mac: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl Device {
+    /// Parse the MAC and IP address a portal sent; blank values count as missing
+    pub fn parse(mac: Option<&str>, ip: Option<&str>) -> Result<Self, RedeemError> {
+        let mac = mac
+            .map(str::trim)
+            .filter(|mac| !mac.is_empty())
+            .map(|mac| normalize_mac(mac).ok_or_else(|| RedeemError::InvalidMac(mac.to_string())))
+            .transpose()?;
+        let ip = ip
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| {
+                ip.parse()
+                    .map_err(|_| RedeemError::InvalidIp(ip.to_string()))
+            })
+            .transpose()?;
+        Ok(Self { mac, ip })
+    }
+
+    /// Whether this is the device that redeemed `voucher`. Only the MAC address
+    /// counts, since the IP address changes between networks and leases.
+    pub fn redeemed(&self, voucher: &Voucher) -> bool {
+        self.mac.is_some() && self.mac == voucher.client_mac
+    }
+}
+
+/// Accept `aa:bb:cc:dd:ee:ff`, `AA-BB-CC-DD-EE-FF`, `aabb.ccdd.eeff` and `aabbccddeeff`
+pub fn normalize_mac(mac: &str) -> Option<String> {
+    let digits: String = mac
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-' | '.'))
+        .collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = digits.to_ascii_lowercase();
+    let octets: Vec<&str> = (0..12).step_by(2).map(|i| &digits[i..i + 2]).collect();
+    Some(octets.join(":"))
+}
+
+/// A successful login with a voucher code, returned to the portal
+#[derive(Debug, Clone, Serialize)]
+pub struct Redemption {
+    pub voucher_id: String,
+    pub code: String,
+    pub redeemed_at: DateTime<Utc>,
+    /// When access ends; `None` when the voucher has no duration
+    pub session_ends_at: Option<DateTime<Utc>>,
+    /// Seconds of access left; `None` when the voucher has no duration
+    pub remaining_seconds: Option<i64>,
+    /// False when the device that redeemed the code logs in again
+    pub first_login: bool,
+    #[serde(flatten)]
+    pub validity: Validity,
+}
+
+impl Redemption {
+    /// The access left on a redeemed voucher at `now`
+    pub fn new(voucher: &Voucher, first_login: bool, now: DateTime<Utc>) -> Self {
+        let redeemed_at = voucher.redeemed_at.unwrap_or(now);
+        let session_ends_at = voucher.validity.session_ends_at(redeemed_at);
+        Self {
+            voucher_id: voucher.id.clone(),
+            code: voucher.code.clone(),
+            redeemed_at,
+            session_ends_at,
+            remaining_seconds: session_ends_at.map(|ends| (ends - now).num_seconds().max(0)),
+            first_login,
+            validity: voucher.validity.clone(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.remaining_seconds == Some(0)
+    }
+}
+
+/// A guest's connection with a voucher, as reported by RADIUS accounting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VoucherSession {
+    pub voucher_id: String,
+    /// Acct-Session-Id, unique for the access point that reported it
+    pub session_id: String,
+    /// NAS-Identifier or address of the access point
+    pub nas: String,
+    pub client_mac: Option<String>,
+    pub client_ip: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// `None` while the session is running
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub session_seconds: u32,
+    /// Bytes the guest uploaded
+    pub input_bytes: u64,
+    /// Bytes the guest downloaded
+    pub output_bytes: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RedeemError {
+    #[error("unknown voucher code")]
+    UnknownCode,
+    #[error("invalid MAC address '{0}'")]
+    InvalidMac(String),
+    #[error("invalid IP address '{0}'")]
+    InvalidIp(String),
+    #[error("the voucher has not been handed out yet")]
+    NotIssued,
+    #[error("the voucher was already used on another device")]
+    AlreadyRedeemed,
+    #[error("the voucher's time is used up")]
+    UsedUp,
+    #[error("the voucher has expired")]
+    Expired,
+    #[error("the voucher has been revoked")]
+    Revoked,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voucher::VoucherState;
+
+    #[test]
+    fn test_parse_device() {
+        let device = Device::parse(Some(" AA-BB-CC-0D-0E-0F "), Some("10.0.0.7")).unwrap();
+        assert_eq!(device.mac.as_deref(), Some("aa:bb:cc:0d:0e:0f"));
+        assert_eq!(device.ip, Some("10.0.0.7".parse().unwrap()));
+        assert_eq!(
+            normalize_mac("aabb.ccdd.eeff").as_deref(),
+            Some("aa:bb:cc:dd:ee:ff")
+        );
+        assert_eq!(Device::parse(Some(""), None).unwrap(), Device::default());
+        assert!(matches!(
+            Device::parse(Some("aa:bb:cc"), None),
+            Err(RedeemError::InvalidMac(_))
+        ));
+        assert!(matches!(
+            Device::parse(None, Some("10.0.0.300")),
+            Err(RedeemError::InvalidIp(_))
+        ));
+    }
+
+    #[test]
+    fn test_redemption() {
+        let now = Utc::now();
+        let mut voucher = Voucher::new("CODE".to_string());
+        voucher.state = VoucherState::Redeemed;
+        voucher.validity.duration_minutes = Some(60);
+        voucher.redeemed_at = Some(now - chrono::Duration::minutes(15));
+        voucher.client_mac = Some("aa:bb:cc:dd:ee:ff".to_string());
+
+        let redemption = Redemption::new(&voucher, false, now);
+        assert_eq!(redemption.remaining_seconds, Some(45 * 60));
+        assert!(!redemption.is_over());
+        let later = Redemption::new(&voucher, false, now + chrono::Duration::hours(1));
+        assert_eq!(later.remaining_seconds, Some(0));
+        assert!(later.is_over());
+
+        let device = Device::parse(Some("AABBCCDDEEFF"), None).unwrap();
+        assert!(device.redeemed(&voucher));
+        assert!(!Device::default().redeemed(&voucher));
+    }
+}
@@ -0,0 +1,177 @@
+//! Sending plain text email through the configured SMTP server with lettre.
+//! Blocking, like the HTTP client; run it with `tokio::task::spawn_blocking`.
+
+use crate::config::{SmtpConfig, SmtpSecurity};
+use crate::http_client::TIMEOUT;
+use lettre::message::{header::ContentType, Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{SmtpTransport, Transport};
+
+/// Name this client introduces itself with
+const CLIENT_NAME: &str = "voucher-creator";
+
+/// Send an email with a plain text `body` to each address in `to`
+pub fn send(
+    config: &SmtpConfig,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<(), SmtpError> {
+    let host = config.host.as_deref().ok_or(SmtpError::NotConfigured)?;
+    let from = config.from.as_deref().ok_or(SmtpError::NotConfigured)?;
+
+    let mut message = Message::builder()
+        .from(from.parse::<Mailbox>()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN);
+    for recipient in to {
+        message = message.to(recipient.parse::<Mailbox>()?);
+    }
+    let message = message.body(body.to_string())?;
+
+    let mut transport = match config.security {
+        SmtpSecurity::StartTls => SmtpTransport::starttls_relay(host)?,
+        SmtpSecurity::Tls => SmtpTransport::relay(host)?,
+        SmtpSecurity::None => SmtpTransport::builder_dangerous(host),
+    }
+    .port(config.port)
+    .hello_name(ClientId::Domain(CLIENT_NAME.to_string()))
+    .timeout(Some(TIMEOUT));
+    if let Some(username) = &config.username {
+        let password = config.password.clone().unwrap_or_default();
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+
+    transport.build().send(&message)?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SmtpError {
+    #[error("smtp.host and smtp.from are not set in config.toml")]
+    NotConfigured,
+    #[error("invalid email address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("could not build the email: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("sending the email failed: {0}")]
+    Transport(#[from] lettre::transport::smtp::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// A mail server that accepts one message and returns the conversation
+    fn fake_server(listener: TcpListener) -> std::thread::JoinHandle<Vec<String>> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = Vec::new();
+            writer.write_all(b"220 mail.test ESMTP\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches("\r\n").to_string();
+                received.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-mail.test\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 ok\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else if line.starts_with("RCPT TO:<nobody@example.com>") {
+                    b"550 no such user\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+            received
+        })
+    }
+
+    fn config(port: u16) -> SmtpConfig {
+        SmtpConfig {
+            host: Some("127.0.0.1".to_string()),
+            port,
+            security: SmtpSecurity::None,
+            username: Some("vouchers".to_string()),
+            password: Some("secret".to_string()),
+            from: Some("WiFi Vouchers <vouchers@example.com>".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = fake_server(listener);
+
+        send(
+            &config(port),
+            &["frontdesk@example.com".to_string()],
+            "Low on vouchers: Café",
+            "3 left\n.hidden line",
+        )
+        .unwrap();
+
+        let received = server.join().unwrap();
+        assert_eq!(received[0], "EHLO voucher-creator");
+        assert!(received[1].starts_with("AUTH PLAIN"));
+        assert!(received.contains(&"MAIL FROM:<vouchers@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<frontdesk@example.com>".to_string()));
+        // The non-ASCII subject is encoded, not sent raw
+        let subject = received
+            .iter()
+            .find(|line| line.starts_with("Subject: "))
+            .unwrap();
+        assert!(subject.contains("=?utf-8?"));
+        assert!(received.contains(&"3 left".to_string()));
+        assert!(received.contains(&"..hidden line".to_string()));
+        assert_eq!(received.last().unwrap(), "QUIT");
+    }
+
+    #[test]
+    fn test_send_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = fake_server(listener);
+
+        let error = send(
+            &config(port),
+            &["nobody@example.com".to_string()],
+            "Subject",
+            "Body",
+        )
+        .unwrap_err();
+        assert!(matches!(error, SmtpError::Transport(error) if error.is_permanent()));
+        drop(server);
+
+        assert!(matches!(
+            send(&SmtpConfig::default(), &[], "Subject", "Body"),
+            Err(SmtpError::NotConfigured)
+        ));
+        assert!(matches!(
+            send(&config(port), &["not an address".to_string()], "S", "B"),
+            Err(SmtpError::Address(_))
+        ));
+    }
+}
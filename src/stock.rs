@@ -0,0 +1,265 @@
+//! Low-stock alerts. Each network may set how few unused vouchers is too few;
+//! the stock is checked after vouchers are printed, imported or handed out.
+//!
+//! The first check that finds a network low raises an alert and sends a
+//! notification. Later checks stay quiet until the network is restocked above
+//! its threshold, which clears the alert.
+
+use crate::config::{AlertsConfig, SmtpConfig};
+use crate::webhook::WebhookEvent;
+use crate::wifi_network::WiFiNetwork;
+use crate::{http_client, smtp, AppState};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Where low-stock notifications go besides the server log
+#[derive(Debug, Clone, Default)]
+pub struct StockAlerts {
+    pub alerts: AlertsConfig,
+    pub smtp: SmtpConfig,
+}
+
+/// A network running low on unused vouchers, as sent to the webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct LowStockAlert {
+    pub event: &'static str,
+    pub network_id: String,
+    pub network_name: String,
+    pub available: usize,
+    pub threshold: u32,
+    pub raised_at: DateTime<Utc>,
+}
+
+impl LowStockAlert {
+    pub fn new(network: &WiFiNetwork, available: usize, threshold: u32) -> Self {
+        Self {
+            event: "low_stock",
+            network_id: network.id.clone(),
+            network_name: network.name.clone(),
+            available,
+            threshold,
+            raised_at: Utc::now(),
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        format!("Low on vouchers: {}", self.network_name)
+    }
+
+    pub fn message(&self) -> String {
+        format!(
+            "{} has {} unused voucher{} left (alert at {} or fewer).",
+            self.network_name,
+            self.available,
+            if self.available == 1 { "" } else { "s" },
+            self.threshold
+        )
+    }
+}
+
+/// Check a network's stock against its threshold, raising or clearing its
+/// alert. Failures are logged and never fail the request that changed the stock.
+pub async fn check(state: &AppState, network_id: &str) {
+    if let Err(error) = try_check(state, network_id).await {
+        eprintln!(
+            "Low-stock check for network {} failed: {:#}",
+            network_id, error
+        );
+    }
+}
+
+async fn try_check(state: &AppState, network_id: &str) -> anyhow::Result<()> {
+    let Some(network) = state.database.get_network(network_id).await? else {
+        return Ok(());
+    };
+    let counts = state.database.get_voucher_counts(network_id).await?;
+    match network.low_stock_threshold {
+        Some(threshold) if network.is_low_on_stock(counts.available) => {
+            if state.database.raise_stock_alert(network_id).await? {
+                let alert = LowStockAlert::new(&network, counts.available, threshold);
+                state.webhooks.emit(WebhookEvent::StockLow, &alert).await;
+                notify(state.stock_alerts.as_ref().clone(), alert);
+            }
+        }
+        _ => {
+            state.database.clear_stock_alert(network_id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Log the alert and send it on in the background
+fn notify(stock_alerts: StockAlerts, alert: LowStockAlert) {
+    println!("Low-stock alert: {}", alert.message());
+    if stock_alerts.alerts.webhook_url.is_some() || !stock_alerts.alerts.email_to.is_empty() {
+        tokio::task::spawn_blocking(move || stock_alerts.deliver(&alert));
+    }
+}
+
+impl StockAlerts {
+    /// Post the alert to the webhook and email it, logging failures
+    fn deliver(&self, alert: &LowStockAlert) {
+        if let Some(url) = &self.alerts.webhook_url {
+            let body = serde_json::to_vec(alert).unwrap_or_default();
+            if let Err(error) = http_client::post_json(url, &[], &body) {
+                eprintln!("Low-stock webhook to {} failed: {}", url, error);
+            }
+        }
+        if !self.alerts.email_to.is_empty() {
+            if let Err(error) = smtp::send(
+                &self.smtp,
+                &self.alerts.email_to,
+                &alert.subject(),
+                &alert.message(),
+            ) {
+                eprintln!("Low-stock email failed: {}", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AuthConfig, PrintingConfig, ReceiptPrinterConfig, RedemptionConfig};
+    use crate::qr_generator::QrGenerator;
+    use crate::test_util;
+    use crate::voucher::Voucher;
+    use crate::webhook::Webhooks;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    async fn state() -> AppState {
+        let database = Arc::new(test_util::database().await);
+        AppState {
+            database: database.clone(),
+            qr_generator: QrGenerator::default(),
+            auth: Arc::new(AuthConfig::default()),
+            printing: Arc::new(PrintingConfig::default()),
+            receipt_printer: Arc::new(ReceiptPrinterConfig::default()),
+            redemption: Arc::new(RedemptionConfig::default()),
+            stock_alerts: Arc::new(StockAlerts::default()),
+            webhooks: Webhooks::new(database),
+        }
+    }
+
+    async fn is_raised(state: &AppState, network_id: &str) -> bool {
+        state
+            .database
+            .get_stock_alert(network_id)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_check_raises_once_and_clears_on_restock() {
+        let state = state().await;
+        let mut network = test_util::lobby();
+        network.low_stock_threshold = Some(2);
+        state.database.create_network(&network).await.unwrap();
+
+        let voucher = |code: &str| Voucher {
+            network_id: Some(network.id.clone()),
+            ..Voucher::new(code.to_string())
+        };
+        state
+            .database
+            .create_vouchers(&[voucher("A"), voucher("B")])
+            .await
+            .unwrap();
+        check(&state, &network.id).await;
+        let raised_at = state.database.get_stock_alert(&network.id).await.unwrap();
+        assert!(raised_at.is_some());
+        // Still low: the alert stays as it was, without a second notification
+        check(&state, &network.id).await;
+        assert_eq!(
+            state.database.get_stock_alert(&network.id).await.unwrap(),
+            raised_at
+        );
+        assert!(!state.database.raise_stock_alert(&network.id).await.unwrap());
+
+        state
+            .database
+            .create_vouchers(&[voucher("C")])
+            .await
+            .unwrap();
+        check(&state, &network.id).await;
+        assert!(!is_raised(&state, &network.id).await);
+
+        // Vouchers past their expiry date are not stock, even before they are swept
+        state
+            .database
+            .allocate_vouchers(&network.id, 1, "api")
+            .await
+            .unwrap();
+        let lapsed = Voucher {
+            validity: crate::voucher::Validity {
+                expires_at: crate::voucher::parse_expiry("2020-01-01"),
+                ..Default::default()
+            },
+            ..voucher("D")
+        };
+        state.database.create_vouchers(&[lapsed]).await.unwrap();
+        check(&state, &network.id).await;
+        assert!(is_raised(&state, &network.id).await);
+        state.database.clear_stock_alert(&network.id).await.unwrap();
+
+        // Without a threshold there is never an alert
+        network.low_stock_threshold = None;
+        state.database.update_network(&network).await.unwrap();
+        state.database.raise_stock_alert(&network.id).await.unwrap();
+        check(&state, &network.id).await;
+        assert!(!is_raised(&state, &network.id).await);
+    }
+
+    #[test]
+    fn test_deliver_posts_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .into_inner()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        });
+
+        let network = test_util::lobby();
+        let alert = LowStockAlert::new(&network, 1, 5);
+        assert_eq!(
+            alert.message(),
+            "Lobby has 1 unused voucher left (alert at 5 or fewer)."
+        );
+        StockAlerts {
+            alerts: AlertsConfig {
+                webhook_url: Some(url),
+                email_to: Vec::new(),
+            },
+            smtp: SmtpConfig::default(),
+        }
+        .deliver(&alert);
+
+        let body = server.join().unwrap();
+        assert_eq!(body["event"], "low_stock");
+        assert_eq!(body["network_id"], network.id);
+        assert_eq!(body["available"], 1);
+        assert_eq!(body["threshold"], 5);
+    }
+}
@@ -1,588 +1,481 @@
+//! Page templates, compiled from the `templates/` directory by askama.
+//! Every `{{ ... }}` expression is HTML-escaped unless marked `|safe`.
+
 use crate::database::VoucherCounts;
-use crate::voucher::Voucher;
-use crate::wifi_network::WiFiNetwork;
-use std::fs;
-
-// Load template files at compile time or runtime
-fn load_template(name: &str) -> String {
-    let templates_dir = match crate::config::Config::load() {
-        Ok(config) => config.templates_dir,
-        Err(_) => "templates".to_string(), // Fallback to default if config can't be loaded
-    };
-
-    let template_path = format!("{}/{}.html", templates_dir, name);
-    fs::read_to_string(&template_path)
-        .unwrap_or_else(|_| panic!("Failed to load template: {}", template_path))
+use crate::layout::SheetLayout;
+use crate::pfsense::{PfSenseSettings, RollSettings, VoucherRoll};
+use crate::portal::PortalQrMode;
+use crate::print_job::PrintJob;
+use crate::qr_generator::{ErrorCorrection, QrImage, QrImageFormat, QrOptions};
+use crate::user::User;
+use crate::voucher::{Voucher, VoucherState};
+use crate::webhook::{DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent};
+use crate::wifi_network::{SecurityType, WiFiNetwork};
+use askama::Template;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+#[derive(Template)]
+#[template(path = "no-vouchers.html")]
+pub struct NoVouchersTemplate;
+
+#[derive(Template)]
+#[template(path = "no-unused-vouchers.html")]
+pub struct NoUnusedVouchersTemplate;
+
+/// Printable sheet of voucher cards, one card per voucher
+#[derive(Template)]
+#[template(path = "vouchers.html")]
+pub struct VouchersTemplate<'a> {
+    pub network: &'a WiFiNetwork,
+    pub qr: &'a QrImage,
+    /// Captive portal login code of each voucher, by voucher id
+    pub login_qrs: &'a HashMap<String, QrImage>,
+    pub vouchers: &'a [Voucher],
+    pub layout: &'a SheetLayout,
+    /// Id of the print job the sheet belongs to, which serves the logo
+    pub job_id: &'a str,
+    /// Id of the print job the sheet belongs to while its vouchers are only reserved
+    pub reserved_job: Option<&'a str>,
 }
 
-pub fn no_vouchers_template() -> String {
-    load_template("no-vouchers")
+/// Whether a response page reports success or failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseKind {
+    Success,
+    Error,
 }
 
-pub fn voucher_card_template() -> String {
-    load_template("voucher-card")
+impl ResponseKind {
+    pub fn subtitle(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "Operation completed successfully",
+            ResponseKind::Error => "An error occurred while processing your request",
+        }
+    }
+
+    pub fn header_gradient(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "bg-gradient-to-r from-emerald-500 to-teal-600",
+            ResponseKind::Error => "bg-gradient-to-r from-red-500 to-pink-600",
+        }
+    }
+
+    pub fn icon_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "fas fa-check-circle",
+            ResponseKind::Error => "fas fa-exclamation-triangle",
+        }
+    }
+
+    pub fn text_color_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "text-emerald-100",
+            ResponseKind::Error => "text-red-100",
+        }
+    }
+
+    pub fn message_bg_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "bg-gradient-to-r from-green-50 to-emerald-50",
+            ResponseKind::Error => "bg-gradient-to-r from-red-50 to-pink-50",
+        }
+    }
+
+    pub fn message_border_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "border-green-200",
+            ResponseKind::Error => "border-red-200",
+        }
+    }
+
+    pub fn message_icon_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "fas fa-check-circle text-green-600",
+            ResponseKind::Error => "fas fa-exclamation-circle text-red-600",
+        }
+    }
+
+    pub fn message_text_class(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "text-green-800",
+            ResponseKind::Error => "text-red-800",
+        }
+    }
+
+    pub fn message_title(&self) -> &'static str {
+        match self {
+            ResponseKind::Success => "Success!",
+            ResponseKind::Error => "Error!",
+        }
+    }
 }
 
-pub fn vouchers_template() -> String {
-    load_template("vouchers")
+/// A link rendered as a button on a response page
+pub struct LinkButton {
+    pub href: String,
+    pub icon: &'static str,
+    pub label: &'static str,
+    pub gradient: &'static str,
 }
 
-pub fn generate_voucher_card(
-    qr_code_base64: &str,
-    network_ssid: &str,
-    network_name: &str,
-    network_password: &str,
-    voucher_code: &str,
-) -> String {
-    voucher_card_template()
-        .replace("{{QR_CODE_BASE64}}", qr_code_base64)
-        .replace("{{NETWORK_SSID}}", network_ssid)
-        .replace("{{NETWORK_NAME}}", network_name)
-        .replace("{{NETWORK_PASSWORD}}", network_password)
-        .replace("{{VOUCHER_CODE}}", voucher_code)
+impl LinkButton {
+    pub fn new(
+        href: impl Into<String>,
+        icon: &'static str,
+        label: &'static str,
+        gradient: &'static str,
+    ) -> Self {
+        Self {
+            href: href.into(),
+            icon,
+            label,
+            gradient,
+        }
+    }
 }
 
-pub fn generate_vouchers_page(
-    voucher_count: usize,
-    network_name: &str,
-    network_ssid: &str,
-    voucher_cards: &str,
-) -> String {
-    vouchers_template()
-        .replace("{{VOUCHER_COUNT}}", &voucher_count.to_string())
-        .replace("{{NETWORK_NAME}}", network_name)
-        .replace("{{NETWORK_SSID}}", network_ssid)
-        .replace("{{VOUCHER_CARDS}}", voucher_cards)
+/// Generic success/error page shown after a form submission
+#[derive(Template)]
+#[template(path = "response.html")]
+pub struct ResponseTemplate {
+    pub title: String,
+    pub kind: ResponseKind,
+    pub message: String,
+    pub voucher_count: usize,
+    pub buttons: Vec<LinkButton>,
 }
 
-pub fn response_template() -> String {
-    load_template("response")
+impl ResponseTemplate {
+    pub fn success(
+        title: impl Into<String>,
+        message: impl Into<String>,
+        voucher_count: usize,
+        buttons: Vec<LinkButton>,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            kind: ResponseKind::Success,
+            message: message.into(),
+            voucher_count,
+            buttons,
+        }
+    }
+
+    pub fn error(
+        title: impl Into<String>,
+        message: impl Into<String>,
+        buttons: Vec<LinkButton>,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            kind: ResponseKind::Error,
+            message: message.into(),
+            voucher_count: 0,
+            buttons,
+        }
+    }
 }
 
-pub fn success_response(title: &str, message: &str, voucher_count: usize, buttons: &str) -> String {
-    let stats_section = if voucher_count > 0 {
-        format!(
-            r#"
-            <div class="mt-6 bg-gradient-to-r from-green-50 to-emerald-50 rounded-2xl p-6 border border-green-200">
-                <div class="text-center">
-                    <div class="flex items-center justify-center space-x-6">
-                        <div class="text-center">
-                            <div class="text-3xl font-bold text-green-800">{}</div>
-                            <div class="text-sm text-green-600 font-medium">Vouchers Loaded</div>
-                        </div>
-                        <div class="w-px h-12 bg-green-300"></div>
-                        <div class="text-center">
-                            <div class="text-3xl font-bold text-green-800">✓</div>
-                            <div class="text-sm text-green-600 font-medium">Ready to Print</div>
-                        </div>
-                    </div>
-                </div>
-            </div>
-            "#,
-            voucher_count
-        )
-    } else {
-        "".to_string()
-    };
-
-    response_template()
-        .replace("{{TITLE}}", title)
-        .replace("{{SUBTITLE}}", "Operation completed successfully")
-        .replace(
-            "{{HEADER_GRADIENT}}",
-            "bg-gradient-to-r from-emerald-500 to-teal-600",
-        )
-        .replace("{{ICON_CLASS}}", "fas fa-check-circle")
-        .replace("{{TEXT_COLOR_CLASS}}", "text-emerald-100")
-        .replace(
-            "{{MESSAGE_BG_CLASS}}",
-            "bg-gradient-to-r from-green-50 to-emerald-50",
-        )
-        .replace("{{MESSAGE_BORDER_CLASS}}", "border-green-200")
-        .replace(
-            "{{MESSAGE_ICON_CLASS}}",
-            "fas fa-check-circle text-green-600",
-        )
-        .replace("{{MESSAGE_TEXT_CLASS}}", "text-green-800")
-        .replace("{{MESSAGE_TITLE}}", "Success!")
-        .replace("{{MESSAGE_CONTENT}}", message)
-        .replace("{{PRIMARY_BUTTON}}", buttons)
-        .replace("{{SECONDARY_BUTTONS}}", "")
-        .replace("{{ADDITIONAL_INFO}}", "")
-        .replace("{{STATS_SECTION}}", &stats_section)
-        .replace("{{AUTO_REDIRECT}}", "false")
-        .replace("{{REDIRECT_URL}}", "")
-        .replace("{{REDIRECT_DELAY}}", "0")
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate<'a> {
+    pub error: Option<&'a str>,
+    pub next: &'a str,
 }
 
-pub fn error_response(title: &str, message: &str, buttons: &str) -> String {
-    response_template()
-        .replace("{{TITLE}}", title)
-        .replace(
-            "{{SUBTITLE}}",
-            "An error occurred while processing your request",
-        )
-        .replace(
-            "{{HEADER_GRADIENT}}",
-            "bg-gradient-to-r from-red-500 to-pink-600",
-        )
-        .replace("{{ICON_CLASS}}", "fas fa-exclamation-triangle")
-        .replace("{{TEXT_COLOR_CLASS}}", "text-red-100")
-        .replace(
-            "{{MESSAGE_BG_CLASS}}",
-            "bg-gradient-to-r from-red-50 to-pink-50",
-        )
-        .replace("{{MESSAGE_BORDER_CLASS}}", "border-red-200")
-        .replace(
-            "{{MESSAGE_ICON_CLASS}}",
-            "fas fa-exclamation-circle text-red-600",
-        )
-        .replace("{{MESSAGE_TEXT_CLASS}}", "text-red-800")
-        .replace("{{MESSAGE_TITLE}}", "Error!")
-        .replace("{{MESSAGE_CONTENT}}", message)
-        .replace("{{PRIMARY_BUTTON}}", buttons)
-        .replace("{{SECONDARY_BUTTONS}}", "")
-        .replace("{{ADDITIONAL_INFO}}", "")
-        .replace("{{STATS_SECTION}}", "")
-        .replace("{{AUTO_REDIRECT}}", "false")
-        .replace("{{REDIRECT_URL}}", "")
-        .replace("{{REDIRECT_DELAY}}", "0")
+/// A network together with its voucher counts, as listed on the admin page
+pub struct NetworkSummary {
+    pub network: WiFiNetwork,
+    pub counts: VoucherCounts,
+    /// When the network's current low-stock alert was raised
+    pub stock_alert: Option<DateTime<Utc>>,
+}
+
+impl NetworkSummary {
+    pub fn is_low_on_stock(&self) -> bool {
+        self.network.is_low_on_stock(self.counts.available)
+    }
 }
 
-pub fn admin_template(networks: &[WiFiNetwork], voucher_counts: &[VoucherCounts]) -> String {
-    let template = load_template("admin");
-
-    let network_rows = networks
-        .iter()
-        .zip(voucher_counts.iter())
-        .map(|(network, counts)| {
-            let voucher_count = counts.total;
-            let unused_count = counts.unused;
-            let status_badge = if network.is_active {
-                r#"<span class="inline-flex items-center px-3 py-1.5 rounded-full text-xs font-semibold bg-gradient-to-r from-green-100 to-emerald-100 text-green-800 border border-green-200">
-                    <i class="fas fa-check-circle mr-1"></i>Active
-                </span>"#
-            } else {
-                r#"<span class="inline-flex items-center px-3 py-1.5 rounded-full text-xs font-semibold bg-gradient-to-r from-red-100 to-pink-100 text-red-800 border border-red-200">
-                    <i class="fas fa-times-circle mr-1"></i>Inactive
-                </span>"#
-            };
-
-            format!(
-                r#"
-                <tr class="group hover:bg-gradient-to-r hover:from-blue-50 hover:to-indigo-50 transition-all duration-300 border-b border-gray-100">
-                    <td class="px-6 py-6">
-                        <div class="flex items-center">
-                            <div class="flex-shrink-0 w-10 h-10 bg-gradient-to-br from-blue-500 to-indigo-600 rounded-lg flex items-center justify-center mr-4">
-                                <i class="fas fa-wifi text-white text-sm"></i>
-                            </div>
-                            <div>
-                                <div class="text-sm font-bold text-gray-900">{}</div>
-                                <div class="text-xs text-gray-500 mt-1">Network</div>
-                            </div>
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">
-                        <div class="text-sm font-mono bg-gradient-to-r from-gray-100 to-gray-200 text-gray-800 px-3 py-2 rounded-lg border border-gray-300">
-                            <i class="fas fa-broadcast-tower mr-2 text-gray-600"></i>{}
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">
-                        {}
-                    </td>
-                    <td class="px-6 py-6">
-                        <div class="text-sm text-gray-700 max-w-xs">
-                            <i class="fas fa-info-circle mr-2 text-gray-400"></i>
-                            {}
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">
-                        <div class="flex items-center space-x-2">
-                            <div class="bg-gradient-to-r from-emerald-100 to-teal-100 text-emerald-800 px-3 py-1 rounded-lg text-sm font-semibold border border-emerald-200">
-                                <i class="fas fa-ticket-alt mr-1"></i>{} available
-                            </div>
-                            <div class="text-gray-500 text-sm">/ {} total</div>
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">
-                        <div class="flex items-center space-x-2">
-                            <a href="/admin/networks/{}/vouchers" class="bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700 text-white px-3 py-2 rounded-lg text-xs font-semibold transition-all duration-200 transform hover:scale-105 shadow-md hover:shadow-lg">
-                                <i class="fas fa-list mr-1"></i>View
-                            </a>
-                            <a href="/generate?network_id={}" class="bg-gradient-to-r from-emerald-500 to-teal-600 hover:from-emerald-600 hover:to-teal-700 text-white px-3 py-2 rounded-lg text-xs font-semibold transition-all duration-200 transform hover:scale-105 shadow-md hover:shadow-lg">
-                                <i class="fas fa-print mr-1"></i>Generate
-                            </a>
-                            <form method="post" action="/admin/networks/{}/delete" class="inline">
-                                <button type="submit" class="bg-gradient-to-r from-red-500 to-pink-600 hover:from-red-600 hover:to-pink-700 text-white px-3 py-2 rounded-lg text-xs font-semibold transition-all duration-200 transform hover:scale-105 shadow-md hover:shadow-lg" onclick="return confirm('⚠️ Are you sure you want to delete this network? This will also remove all associated vouchers.')">
-                                    <i class="fas fa-trash mr-1"></i>Delete
-                                </button>
-                            </form>
-                        </div>
-                    </td>
-                </tr>
-                "#,
-                network.name,
-                network.ssid,
-                status_badge,
-                network.description.as_ref().unwrap_or(&"No description provided".to_string()),
-                unused_count,
-                voucher_count,
-                network.id,
-                network.id,
-                network.id
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let network_options = networks
-        .iter()
-        .map(|network| {
-            format!(
-                r#"<option value="{}">{} ({})</option>"#,
-                network.id, network.name, network.ssid
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let empty_networks_message = if networks.is_empty() {
-        r#"<div class="text-center py-16">
-            <div class="bg-gradient-to-br from-blue-50 to-indigo-100 rounded-2xl p-12 border border-blue-200">
-                <i class="fas fa-network-wired text-6xl text-blue-400 mb-6"></i>
-                <h3 class="text-2xl font-bold text-gray-800 mb-4">No Networks Yet</h3>
-                <p class="text-gray-600 mb-6 max-w-md mx-auto">Get started by creating your first WiFi network using the form above. Once created, you can upload voucher codes and generate printable vouchers.</p>
-                <div class="flex items-center justify-center space-x-2 text-blue-600">
-                    <i class="fas fa-arrow-up animate-bounce"></i>
-                    <span class="font-medium">Use the "Create Network" form above</span>
-                </div>
-            </div>
-        </div>"#
-    } else {
-        ""
-    };
-
-    template
-        .replace("{{NETWORK_ROWS}}", &network_rows)
-        .replace("{{NETWORK_OPTIONS}}", &network_options)
-        .replace("{{EMPTY_NETWORKS_MESSAGE}}", empty_networks_message)
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub struct AdminTemplate<'a> {
+    pub current_user: &'a User,
+    pub networks: Vec<NetworkSummary>,
+    pub security_types: &'a [SecurityType],
 }
 
-pub fn network_vouchers_template(
-    network: Option<&WiFiNetwork>,
-    vouchers: &[Voucher],
-    network_id: &str,
-    voucher_counts: &VoucherCounts,
-) -> String {
-    let template = load_template("network-vouchers");
-
-    let network_info = match network {
-        Some(net) => format!(
-            r#"
-            <div class="mb-8 animate-fade-in">
-                <div class="bg-white rounded-2xl shadow-lg overflow-hidden border border-gray-200">
-                    <div class="bg-gradient-to-r from-blue-500 to-indigo-600 p-8 relative overflow-hidden">
-                        <div class="absolute top-0 right-0 w-32 h-32 bg-white opacity-10 rounded-full -mr-16 -mt-16"></div>
-                        <div class="absolute bottom-0 left-0 w-24 h-24 bg-white opacity-10 rounded-full -ml-12 -mb-12"></div>
-                        <div class="relative z-10">
-                            <div class="flex items-center justify-between">
-                                <div>
-                                    <h2 class="text-3xl font-bold text-white mb-3">
-                                        <i class="fas fa-wifi mr-3"></i>{}
-                                    </h2>
-                                    <p class="text-blue-100 text-lg">Network Configuration Details</p>
-                                </div>
-                                <div class="hidden lg:block">
-                                    <i class="fas fa-network-wired text-6xl text-white opacity-30"></i>
-                                </div>
-                            </div>
-                        </div>
-                    </div>
-                    <div class="p-8">
-                        <div class="grid grid-cols-1 md:grid-cols-2 gap-8">
-                            <div class="bg-gradient-to-br from-blue-50 to-indigo-50 rounded-xl p-6 border border-blue-200">
-                                <div class="flex items-center mb-3">
-                                    <div class="w-10 h-10 bg-gradient-to-br from-blue-500 to-blue-600 rounded-lg flex items-center justify-center mr-3">
-                                        <i class="fas fa-broadcast-tower text-white"></i>
-                                    </div>
-                                    <h3 class="font-bold text-blue-900">Network SSID</h3>
-                                </div>
-                                <div class="font-mono bg-white text-blue-800 px-4 py-3 rounded-lg border border-blue-300 text-lg font-semibold">
-                                    {}</div>
-                            </div>
-                            <div class="bg-gradient-to-br from-purple-50 to-indigo-50 rounded-xl p-6 border border-purple-200">
-                                <div class="flex items-center mb-3">
-                                    <div class="w-10 h-10 bg-gradient-to-br from-purple-500 to-purple-600 rounded-lg flex items-center justify-center mr-3">
-                                        <i class="fas fa-info-circle text-white"></i>
-                                    </div>
-                                    <h3 class="font-bold text-purple-900">Description</h3>
-                                </div>
-                                <p class="text-purple-800 text-lg">{}</p>
-                            </div>
-                        </div>
-                    </div>
-                </div>
-            </div>
-            "#,
-            net.name,
-            net.ssid,
-            net.description.as_ref().unwrap_or(&"No description provided".to_string())
-        ),
-        None => r#"
-            <div class="mb-8 animate-fade-in">
-                <div class="bg-white rounded-2xl shadow-lg overflow-hidden border border-red-200">
-                    <div class="bg-gradient-to-r from-red-500 to-pink-600 p-8">
-                        <h2 class="text-3xl font-bold text-white mb-2">
-                            <i class="fas fa-exclamation-triangle mr-3"></i>Network Not Found
-                        </h2>
-                        <p class="text-red-100 text-lg">The requested network could not be found in the system.</p>
-                    </div>
-                </div>
-            </div>
-        "#.to_string(),
-    };
-
-    let voucher_rows = vouchers
-        .iter()
-        .enumerate()
-        .map(|(i, voucher)| {
-            let status_badge = if voucher.is_used {
-                r#"<span class="inline-flex items-center px-3 py-1.5 rounded-full text-xs font-semibold bg-gradient-to-r from-red-100 to-pink-100 text-red-800 border border-red-200">
-                    <i class="fas fa-times-circle mr-1"></i>Used
-                </span>"#
-            } else {
-                r#"<span class="inline-flex items-center px-3 py-1.5 rounded-full text-xs font-semibold bg-gradient-to-r from-green-100 to-emerald-100 text-green-800 border border-green-200">
-                    <i class="fas fa-check-circle mr-1"></i>Available
-                </span>"#
-            };
-
-            format!(
-                r#"
-                <tr class="group hover:bg-gradient-to-r hover:from-gray-50 hover:to-blue-50 transition-all duration-300 border-b border-gray-100">
-                    <td class="px-6 py-6">
-                        <div class="flex items-center">
-                            <div class="w-8 h-8 bg-gradient-to-br from-gray-600 to-gray-700 rounded-lg flex items-center justify-center mr-3">
-                                <span class="text-white text-sm font-bold">{}</span>
-                            </div>
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">
-                        <div class="flex items-center">
-                            <div class="w-10 h-10 bg-gradient-to-br from-indigo-500 to-purple-600 rounded-lg flex items-center justify-center mr-4">
-                                <i class="fas fa-barcode text-white text-sm"></i>
-                            </div>
-                            <div>
-                                <div class="text-sm font-mono bg-gradient-to-r from-gray-100 to-gray-200 text-gray-800 px-3 py-2 rounded-lg border border-gray-300 font-semibold">
-                                    {}
-                                </div>
-                                <div class="text-xs text-gray-500 mt-1">Voucher Code</div>
-                            </div>
-                        </div>
-                    </td>
-                    <td class="px-6 py-6">{}</td>
-                    <td class="px-6 py-6">
-                        <div class="flex items-center text-sm text-gray-600">
-                            <i class="fas fa-calendar-alt mr-2 text-gray-400"></i>
-                            <div>
-                                <div class="font-medium">{}</div>
-                                <div class="text-xs text-gray-400">Created</div>
-                            </div>
-                        </div>
-                    </td>
-                </tr>
-                "#,
-                i + 1,
-                voucher.code,
-                status_badge,
-                voucher.created_at.format("%Y-%m-%d %H:%M")
-            )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-
-    let empty_vouchers_message = if vouchers.is_empty() {
-        r#"<div class="text-center py-16 animate-fade-in">
-            <div class="bg-gradient-to-br from-amber-50 to-orange-100 rounded-2xl p-12 border border-amber-200 max-w-md mx-auto">
-                <i class="fas fa-ticket-alt text-6xl text-amber-400 mb-6"></i>
-                <h3 class="text-2xl font-bold text-gray-800 mb-4">No Vouchers Yet</h3>
-                <p class="text-gray-600 mb-6">This network doesn't have any voucher codes uploaded yet.</p>
-                <div class="space-y-3">
-                    <div class="flex items-center justify-center space-x-2 text-amber-600">
-                        <i class="fas fa-arrow-up animate-bounce"></i>
-                        <span class="font-medium">Go to Admin Panel to upload vouchers</span>
-                    </div>
-                    <a href="/admin" class="inline-block bg-gradient-to-r from-amber-500 to-orange-600 hover:from-amber-600 hover:to-orange-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                        <i class="fas fa-upload mr-2"></i>Upload Vouchers
-                    </a>
-                </div>
-            </div>
-        </div>"#
-    } else {
-        ""
-    };
-
-    template
-        .replace("{{NETWORK_ID}}", network_id)
-        .replace("{{NETWORK_INFO}}", &network_info)
-        .replace("{{VOUCHER_COUNT}}", &vouchers.len().to_string())
-        .replace("{{VOUCHER_ROWS}}", &voucher_rows)
-        .replace("{{EMPTY_VOUCHERS_MESSAGE}}", empty_vouchers_message)
-        .replace("{{TOTAL_COUNT}}", &voucher_counts.total.to_string())
-        .replace("{{USED_COUNT}}", &voucher_counts.used.to_string())
-        .replace("{{UNUSED_COUNT}}", &voucher_counts.unused.to_string())
-        .replace("{{PRINTED_COUNT}}", &voucher_counts.used.to_string())
-        .replace("{{UNPRINTED_COUNT}}", &voucher_counts.unused.to_string())
+impl AdminTemplate<'_> {
+    /// Networks at or below their low-stock threshold, for the alert banner
+    pub fn low_stock(&self) -> Vec<&NetworkSummary> {
+        self.networks
+            .iter()
+            .filter(|summary| summary.is_low_on_stock())
+            .collect()
+    }
 }
 
-pub fn print_selection_page(network: &WiFiNetwork, voucher_counts: &VoucherCounts) -> String {
-    format!(
-        r#"
-        <!DOCTYPE html>
-        <html lang="en">
-        <head>
-            <meta charset="UTF-8">
-            <meta name="viewport" content="width=device-width, initial-scale=1.0">
-            <title>Print Vouchers - {}</title>
-            <script src="https://cdn.tailwindcss.com"></script>
-            <link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/font-awesome/6.0.0/css/all.min.css">
-        </head>
-        <body class="bg-gradient-to-br from-blue-50 to-indigo-100 min-h-screen">
-            <div class="container mx-auto px-4 py-8">
-                <div class="max-w-2xl mx-auto">
-                    <div class="bg-white rounded-2xl shadow-xl overflow-hidden border border-gray-200">
-                        <div class="bg-gradient-to-r from-blue-500 to-indigo-600 p-8">
-                            <h1 class="text-3xl font-bold text-white mb-2">
-                                <i class="fas fa-print mr-3"></i>Print Vouchers
-                            </h1>
-                            <p class="text-blue-100">Select how many voucher codes to print for {}</p>
-                        </div>
-
-                        <div class="p-8">
-                            <div class="grid grid-cols-2 gap-4 mb-8">
-                                <div class="bg-gradient-to-br from-green-50 to-emerald-50 rounded-xl p-6 border border-green-200">
-                                    <div class="flex items-center justify-between">
-                                        <div>
-                                            <div class="text-2xl font-bold text-green-800">{}</div>
-                                            <div class="text-sm text-green-600">Available to Use</div>
-                                        </div>
-                                        <i class="fas fa-print text-2xl text-green-400"></i>
-                                    </div>
-                                </div>
-                                <div class="bg-gradient-to-br from-blue-50 to-indigo-50 rounded-xl p-6 border border-blue-200">
-                                    <div class="flex items-center justify-between">
-                                        <div>
-                                            <div class="text-2xl font-bold text-blue-800">{}</div>
-                                            <div class="text-sm text-blue-600">Already Used</div>
-                                        </div>
-                                        <i class="fas fa-check text-2xl text-blue-400"></i>
-                                    </div>
-                                </div>
-                            </div>
-
-                            <form action="/print" method="post" enctype="multipart/form-data" class="space-y-6">
-                                <input type="hidden" name="network_id" value="{}">
-
-                                <div>
-                                    <label for="count" class="block text-sm font-bold text-gray-700 mb-3">
-                                        <i class="fas fa-hashtag mr-2"></i>Number of vouchers to use
-                                    </label>
-                                    <input type="number"
-                                           id="count"
-                                           name="count"
-                                           min="1"
-                                           max="{}"
-                                           value="1"
-                                           class="w-full px-4 py-3 border border-gray-300 rounded-xl focus:ring-2 focus:ring-blue-500 focus:border-blue-500 text-lg font-semibold"
-                                           required>
-                                    <p class="mt-2 text-sm text-gray-600">
-                                        <i class="fas fa-info-circle mr-1"></i>
-                                        Enter a number between 1 and {} (unused vouchers)
-                                    </p>
-                                </div>
-
-                                <div class="bg-gradient-to-r from-amber-50 to-orange-50 rounded-xl p-6 border border-amber-200">
-                                    <div class="flex items-start space-x-3">
-                                        <i class="fas fa-exclamation-triangle text-amber-600 mt-1"></i>
-                                        <div>
-                                            <h4 class="font-bold text-amber-800 mb-2">Important Note</h4>
-                                            <p class="text-amber-700 text-sm">
-                                                Once vouchers are used, they will be marked as "used" and won't appear in future requests.
-                                                This ensures each voucher code is only used once.
-                                            </p>
-                                        </div>
-                                    </div>
-                                </div>
-
-                                <div class="flex space-x-4">
-                                    <button type="submit"
-                                            class="flex-1 bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700 text-white px-6 py-4 rounded-xl font-bold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                                        <i class="fas fa-print mr-2"></i>Use Vouchers
-                                    </button>
-                                    <a href="/admin"
-                                       class="flex-1 bg-gradient-to-r from-gray-500 to-gray-600 hover:from-gray-600 hover:to-gray-700 text-white px-6 py-4 rounded-xl font-bold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl text-center">
-                                        <i class="fas fa-arrow-left mr-2"></i>Cancel
-                                    </a>
-                                </div>
-                            </form>
-                        </div>
-                    </div>
-                </div>
-            </div>
-        </body>
-        </html>
-        "#,
-        network.name,
-        network.name,
-        voucher_counts.unused,
-        voucher_counts.used,
-        network.id,
-        voucher_counts.unused,
-        voucher_counts.unused
-    )
+/// A user together with the ids of the networks they are assigned to
+pub struct UserSummary {
+    pub user: User,
+    pub network_ids: Vec<String>,
 }
 
-pub fn no_unused_vouchers_template() -> String {
-    r#"
-        <!DOCTYPE html>
-        <html lang="en">
-        <head>
-            <meta charset="UTF-8">
-            <meta name="viewport" content="width=device-width, initial-scale=1.0">
-            <title>No Vouchers Available</title>
-            <script src="https://cdn.tailwindcss.com"></script>
-            <link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/font-awesome/6.0.0/css/all.min.css">
-        </head>
-        <body class="bg-gradient-to-br from-blue-50 to-indigo-100 min-h-screen">
-            <div class="container mx-auto px-4 py-8">
-                <div class="max-w-2xl mx-auto">
-                    <div class="bg-white rounded-2xl shadow-xl overflow-hidden border border-gray-200">
-                        <div class="bg-gradient-to-r from-amber-500 to-orange-600 p-8">
-                            <h1 class="text-3xl font-bold text-white mb-2">
-                                <i class="fas fa-exclamation-triangle mr-3"></i>No Vouchers Available
-                            </h1>
-                            <p class="text-amber-100">All vouchers for this network have already been printed</p>
-                        </div>
-
-                        <div class="p-8 text-center">
-                            <div class="bg-gradient-to-br from-amber-50 to-orange-100 rounded-2xl p-12 border border-amber-200">
-                                <i class="fas fa-print text-6xl text-amber-400 mb-6"></i>
-                                <h3 class="text-2xl font-bold text-gray-800 mb-4">All Vouchers Used</h3>
-                                <p class="text-gray-600 mb-6">
-                                    There are no unused voucher codes remaining for this network.
-                                    All available vouchers have already been used.
-                                </p>
-                                <div class="space-y-3">
-                                    <a href="/admin"
-                                       class="inline-block bg-gradient-to-r from-blue-500 to-blue-600 hover:from-blue-600 hover:to-blue-700 text-white px-6 py-3 rounded-xl font-semibold transition-all duration-200 transform hover:scale-105 shadow-lg hover:shadow-xl">
-                                        <i class="fas fa-arrow-left mr-2"></i>Back to Admin
-                                    </a>
-                                </div>
-                            </div>
-                        </div>
-                    </div>
-                </div>
-            </div>
-        </body>
-        </html>
-        "#.to_string()
+impl UserSummary {
+    pub fn has_network(&self, network_id: &str) -> bool {
+        self.network_ids.iter().any(|id| id == network_id)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+pub struct UsersTemplate<'a> {
+    pub current_user: &'a User,
+    pub users: Vec<UserSummary>,
+    pub networks: Vec<WiFiNetwork>,
+}
+
+#[derive(Template)]
+#[template(path = "webhooks.html")]
+pub struct WebhooksTemplate<'a> {
+    pub current_user: &'a User,
+    pub webhooks: Vec<Webhook>,
+    pub events: &'a [WebhookEvent],
+}
+
+#[derive(Template)]
+#[template(path = "webhook.html")]
+pub struct WebhookTemplate<'a> {
+    pub current_user: &'a User,
+    pub webhook: Webhook,
+    /// The most recent deliveries, newest first
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Template)]
+#[template(path = "network-vouchers.html")]
+pub struct NetworkVouchersTemplate<'a> {
+    pub network: Option<WiFiNetwork>,
+    pub network_id: &'a str,
+    /// The network's vouchers, only those in `state_filter` when one is chosen
+    pub vouchers: Vec<Voucher>,
+    pub voucher_counts: VoucherCounts,
+    pub voucher_states: &'a [VoucherState],
+    pub state_filter: Option<VoucherState>,
+    pub pfsense: Option<PfSenseSettings>,
+    /// Saved roll settings, or pfSense's defaults when none are saved yet
+    pub roll_settings: RollSettings,
+    pub rolls: Vec<VoucherRoll>,
+    /// The network's own QR settings, or the global ones when it has none
+    pub qr_options: QrOptions,
+    pub qr_custom: bool,
+    pub error_correction_levels: &'a [ErrorCorrection],
+    pub image_formats: &'a [QrImageFormat],
+    pub security_types: &'a [SecurityType],
+    pub portal_modes: &'a [PortalQrMode],
+}
+
+impl NetworkVouchersTemplate<'_> {
+    pub fn is_low_on_stock(&self) -> bool {
+        self.network
+            .as_ref()
+            .is_some_and(|network| network.is_low_on_stock(self.voucher_counts.available))
+    }
+}
+
+/// A network's past print jobs, each linking to its job page
+#[derive(Template)]
+#[template(path = "print-jobs.html")]
+pub struct PrintJobsTemplate<'a> {
+    pub network: &'a WiFiNetwork,
+    pub jobs: Vec<PrintJob>,
+}
+
+/// One print job, where a reserved job is opened, then confirmed or released
+#[derive(Template)]
+#[template(path = "print-job.html")]
+pub struct PrintJobTemplate<'a> {
+    pub network: &'a WiFiNetwork,
+    pub job: &'a PrintJob,
+    /// Address of the receipt printer that receipt jobs can be sent to
+    pub receipt_printer: Option<&'a str>,
+}
+
+/// Form asking how many vouchers to print for a network
+#[derive(Template)]
+#[template(path = "print-selection.html")]
+pub struct PrintSelectionTemplate<'a> {
+    pub network: &'a WiFiNetwork,
+    pub voucher_counts: &'a VoucherCounts,
+    pub layouts: &'a [SheetLayout],
+    /// Admins may save the chosen layout as the network's default
+    pub can_set_default: bool,
+    /// How long printed vouchers stay reserved before they must be confirmed
+    pub reservation_minutes: i64,
+}
+
+#[derive(Template)]
+#[template(path = "voucher-list.html")]
+pub struct VoucherListTemplate {
+    pub vouchers: Vec<Voucher>,
+}
+
+mod filters {
+    /// Format a length in millimetres as a CSS value
+    pub fn mm<T: std::fmt::Display>(value: T) -> askama::Result<String> {
+        Ok(format!("{:.2}mm", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::Role;
+
+    fn network(name: &str) -> WiFiNetwork {
+        WiFiNetwork::new(
+            name.to_string(),
+            "Guest<WiFi>".to_string(),
+            "pa\"ss".to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_voucher_sheet_escapes_network_and_codes() {
+        let network = network("<script>alert(1)</script>");
+        let vouchers = vec![Voucher::new("<b>CODE</b>".to_string())];
+        let html = VouchersTemplate {
+            network: &network,
+            qr: &QrImage::Png("abc".to_string()),
+            login_qrs: &HashMap::new(),
+            vouchers: &vouchers,
+            layout: crate::layout::preset("a4").unwrap(),
+            job_id: "job-1",
+            reserved_job: None,
+        }
+        .render()
+        .unwrap();
+
+        assert!(!html.contains("<script>alert(1)</script>"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(html.contains("&lt;b&gt;CODE&lt;/b&gt;"));
+        assert!(html.contains("Guest&lt;WiFi&gt;"));
+    }
+
+    #[test]
+    fn test_admin_page_hides_admin_sections_from_operators() {
+        let operator = User::new("desk".to_string(), String::new(), Role::Operator);
+        let summary = NetworkSummary {
+            network: network("<i>Lobby</i>"),
+            counts: VoucherCounts {
+                total: 2,
+                available: 1,
+                printed: 1,
+                ..Default::default()
+            },
+            stock_alert: None,
+        };
+        let html = AdminTemplate {
+            current_user: &operator,
+            networks: vec![summary],
+            security_types: &SecurityType::ALL,
+        }
+        .render()
+        .unwrap();
+
+        assert!(html.contains("&lt;i&gt;Lobby&lt;/i&gt;"));
+        assert!(!html.contains("/delete"));
+        assert!(!html.contains("/admin/users"));
+        assert!(!html.contains("Running low on"));
+    }
+
+    #[test]
+    fn test_admin_page_warns_about_low_stock() {
+        let admin = User::new("admin".to_string(), String::new(), Role::Admin);
+        let mut low = network("Lobby");
+        low.low_stock_threshold = Some(5);
+        let mut stocked = network("Pool");
+        stocked.low_stock_threshold = Some(1);
+        let counts = || VoucherCounts {
+            total: 3,
+            available: 3,
+            ..Default::default()
+        };
+        let html = AdminTemplate {
+            current_user: &admin,
+            networks: vec![
+                NetworkSummary {
+                    network: low,
+                    counts: counts(),
+                    stock_alert: Some(Utc::now()),
+                },
+                NetworkSummary {
+                    network: stocked,
+                    counts: counts(),
+                    stock_alert: None,
+                },
+            ],
+            security_types: &SecurityType::ALL,
+        }
+        .render()
+        .unwrap();
+
+        assert!(html.contains("Running low on"));
+        assert!(html.contains("has 3 unused vouchers"));
+        assert!(html.contains("(alert at 5 or fewer)"));
+        assert!(!html.contains("alert at 1 or fewer"));
+    }
+
+    #[test]
+    fn test_webhook_page_shows_delivery_log() {
+        let admin = User::new("admin".to_string(), String::new(), Role::Admin);
+        let webhook = Webhook::new(
+            "https://hooks.example.com/vouchers".to_string(),
+            "whsec-test".to_string(),
+            vec![WebhookEvent::VoucherUsed],
+        )
+        .unwrap();
+        let now = Utc::now();
+        let mut delivered = WebhookDelivery::new(
+            webhook.id.clone(),
+            WebhookEvent::VoucherUsed,
+            r#"{"event":"voucher.used"}"#.to_string(),
+            now,
+        );
+        delivered.record(&Ok(204), now);
+        let mut failing = delivered.clone();
+        failing.status = DeliveryStatus::Pending;
+        failing.record(&Err(crate::http_client::HttpError::Status(503)), now);
+
+        let html = WebhookTemplate {
+            current_user: &admin,
+            webhook,
+            deliveries: vec![failing, delivered],
+        }
+        .render()
+        .unwrap();
+
+        assert!(html.contains("whsec-test"));
+        assert!(html.contains("voucher.used"));
+        assert!(html.contains("HTTP 204"));
+        assert!(html.contains("HTTP 503"));
+        assert!(html.contains("next try"));
+        // The payload is shown escaped
+        assert!(html.contains("&quot;event&quot;"));
+    }
+
+    #[test]
+    fn test_login_page_escapes_next() {
+        let html = LoginTemplate {
+            error: Some("<bad>"),
+            next: "/admin\"><script>",
+        }
+        .render()
+        .unwrap();
+
+        assert!(html.contains("&lt;bad&gt;"));
+        assert!(!html.contains("\"><script>"));
+    }
 }
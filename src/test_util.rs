@@ -0,0 +1,33 @@
+//! Fixtures shared by the tests of several modules
+
+use crate::crypto::PasswordCipher;
+use crate::database::Database;
+use crate::wifi_network::WiFiNetwork;
+
+/// A cipher with a fresh random key
+pub fn cipher() -> PasswordCipher {
+    PasswordCipher::from_base64(&PasswordCipher::generate_key()).unwrap()
+}
+
+/// An empty in-memory database
+pub async fn database() -> Database {
+    Database::new("sqlite::memory:", cipher()).await.unwrap()
+}
+
+/// The network most tests use, not yet stored
+pub fn lobby() -> WiFiNetwork {
+    WiFiNetwork::new(
+        "Lobby".to_string(),
+        "Lobby-Guest".to_string(),
+        "secret".to_string(),
+        None,
+    )
+}
+
+/// An in-memory database holding the [`lobby`] network
+pub async fn database_with_lobby() -> (Database, WiFiNetwork) {
+    let database = database().await;
+    let network = lobby();
+    database.create_network(&network).await.unwrap();
+    (database, network)
+}
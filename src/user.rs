@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// What a user is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Full access: manage networks, users, uploads and voucher state
+    Admin,
+    /// Front desk: may only print vouchers for assigned networks
+    Operator,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Admin => "Admin",
+            Role::Operator => "Front Desk",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "operator" => Ok(Role::Operator),
+            other => anyhow::bail!("Unknown role: {}", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl User {
+    pub fn new(username: String, password_hash: String, role: Role) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            username,
+            password_hash,
+            role,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_user() {
+        let user = User::new("frontdesk".to_string(), "hash".to_string(), Role::Operator);
+        assert_eq!(user.username, "frontdesk");
+        assert_eq!(user.password_hash, "hash");
+        assert_eq!(user.role, Role::Operator);
+        assert!(!user.is_admin());
+        assert!(!user.id.is_empty());
+    }
+
+    #[test]
+    fn test_role_round_trip() {
+        for role in [Role::Admin, Role::Operator] {
+            assert_eq!(role.as_str().parse::<Role>().unwrap(), role);
+        }
+        assert!("superuser".parse::<Role>().is_err());
+    }
+}
@@ -1,4 +1,7 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,23 +10,345 @@ pub struct Voucher {
     pub code: String,
     pub network_id: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Whether the voucher has left the pool, kept next to `state` for API clients
     pub is_used: bool,
     pub used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub state: VoucherState,
+    pub state_changed_at: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub validity: Validity,
+    /// When a guest first logged in with the code, which starts its duration
+    pub redeemed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// MAC address of the device that redeemed the code, if the portal sent it
+    pub client_mac: Option<String>,
+    /// IP address of the device that redeemed the code, if the portal sent it
+    pub client_ip: Option<String>,
 }
 
 impl Voucher {
     pub fn new(code: String) -> Self {
+        let created_at = chrono::Utc::now();
         Self {
             id: Uuid::new_v4().to_string(),
             code,
             network_id: None,
-            created_at: chrono::Utc::now(),
+            created_at,
             is_used: false,
             used_at: None,
+            state: VoucherState::Available,
+            state_changed_at: created_at,
+            validity: Validity::default(),
+            redeemed_at: None,
+            client_mac: None,
+            client_ip: None,
         }
     }
 }
 
+/// Where a voucher is in its life, from the unused pool to a guest's device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoucherState {
+    /// In the unused pool
+    #[default]
+    Available,
+    /// Held by a print job waiting to be confirmed
+    Reserved,
+    /// On a confirmed sheet or receipt, not yet known to be handed out
+    Printed,
+    /// Handed to a guest by the front desk or through the API
+    Issued,
+    /// Used by a guest to log in
+    Redeemed,
+    /// Withdrawn by an admin and no longer accepted
+    Revoked,
+    /// Passed its expiry date before a guest redeemed it
+    Expired,
+}
+
+impl VoucherState {
+    pub const ALL: [VoucherState; 7] = [
+        Self::Available,
+        Self::Reserved,
+        Self::Printed,
+        Self::Issued,
+        Self::Redeemed,
+        Self::Revoked,
+        Self::Expired,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Available => "available",
+            Self::Reserved => "reserved",
+            Self::Printed => "printed",
+            Self::Issued => "issued",
+            Self::Redeemed => "redeemed",
+            Self::Revoked => "revoked",
+            Self::Expired => "expired",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Available => "Available",
+            Self::Reserved => "Reserved",
+            Self::Printed => "Printed",
+            Self::Issued => "Issued to guest",
+            Self::Redeemed => "Redeemed",
+            Self::Revoked => "Revoked",
+            Self::Expired => "Expired",
+        }
+    }
+
+    /// Whether the voucher has left the pool, whatever happened to it since
+    pub fn is_used(&self) -> bool {
+        matches!(
+            self,
+            Self::Printed | Self::Issued | Self::Redeemed | Self::Revoked
+        )
+    }
+
+    /// States entered only by print jobs and expiry, never set by hand
+    pub fn is_automatic(&self) -> bool {
+        matches!(self, Self::Reserved | Self::Expired)
+    }
+
+    /// Whether a voucher in this state may move to `next`
+    pub fn can_become(&self, next: VoucherState) -> bool {
+        use VoucherState::*;
+        matches!(
+            (self, next),
+            (Available, Reserved | Issued | Redeemed | Revoked | Expired)
+                | (Reserved, Available | Printed)
+                // Back to the pool corrects a sheet or code that never reached a guest
+                | (Printed, Available | Issued | Redeemed | Revoked | Expired)
+                | (Issued, Available | Redeemed | Revoked | Expired)
+                | (Redeemed, Revoked)
+        )
+    }
+
+    /// The states an admin can move a voucher in this state to
+    pub fn next_states(&self) -> Vec<VoucherState> {
+        if self.is_automatic() {
+            return Vec::new();
+        }
+        Self::ALL
+            .into_iter()
+            .filter(|next| !next.is_automatic() && self.can_become(*next))
+            .collect()
+    }
+}
+
+impl fmt::Display for VoucherState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for VoucherState {
+    type Err = VoucherStateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| VoucherStateError::Unknown(s.to_string()))
+    }
+}
+
+/// One recorded change of a voucher's state
+#[derive(Debug, Clone, Serialize)]
+pub struct VoucherTransition {
+    pub from: VoucherState,
+    pub to: VoucherState,
+    pub changed_at: DateTime<Utc>,
+    /// Username of whoever made the change; `None` for expiry
+    pub changed_by: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VoucherStateError {
+    #[error("unknown voucher state '{0}'")]
+    Unknown(String),
+    #[error("a {0} voucher cannot become {1}")]
+    Transition(VoucherState, VoucherState),
+    #[error("{0} vouchers change state with their print job or expiry date")]
+    Automatic(VoucherState),
+    #[error("the voucher changed state at the same time; reload and try again")]
+    Conflict,
+}
+
+/// What a voucher grants and until when it can be used. Every limit is optional;
+/// the captive portal enforces them, this app stores, prints and reports them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Validity {
+    /// How long access lasts once the code is first used, in minutes
+    pub duration_minutes: Option<u32>,
+    /// The code cannot be used after this time
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Data allowance in megabytes
+    pub data_limit_mb: Option<u64>,
+    /// Download speed cap in kbit/s
+    pub download_kbps: Option<u32>,
+    /// Upload speed cap in kbit/s
+    pub upload_kbps: Option<u32>,
+}
+
+/// CSV header names of the validity columns
+pub const VALIDITY_COLUMNS: [&str; 5] = [
+    "duration_minutes",
+    "expires_at",
+    "data_limit_mb",
+    "download_kbps",
+    "upload_kbps",
+];
+
+impl Validity {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check the limits before storing them
+    pub fn validate(&self) -> Result<(), ValidityError> {
+        if self.duration_minutes == Some(0) {
+            return Err(ValidityError::Zero("duration_minutes"));
+        }
+        if self.data_limit_mb == Some(0) {
+            return Err(ValidityError::Zero("data_limit_mb"));
+        }
+        if self.download_kbps == Some(0) {
+            return Err(ValidityError::Zero("download_kbps"));
+        }
+        if self.upload_kbps == Some(0) {
+            return Err(ValidityError::Zero("upload_kbps"));
+        }
+        Ok(())
+    }
+
+    /// Set a limit from its CSV column or form field; blank values leave it unset
+    pub fn set(&mut self, column: &str, value: &str) -> Result<(), ValidityError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(());
+        }
+        let invalid = || ValidityError::Invalid(column.to_string(), value.to_string());
+        match column {
+            "duration_minutes" => {
+                self.duration_minutes = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "expires_at" => self.expires_at = Some(parse_expiry(value).ok_or_else(invalid)?),
+            "data_limit_mb" => self.data_limit_mb = Some(value.parse().map_err(|_| invalid())?),
+            "download_kbps" => self.download_kbps = Some(value.parse().map_err(|_| invalid())?),
+            "upload_kbps" => self.upload_kbps = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(ValidityError::UnknownColumn(column.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Values in the order of [`VALIDITY_COLUMNS`], blank when unset
+    pub fn columns(&self) -> [String; 5] {
+        let value = |value: Option<String>| value.unwrap_or_default();
+        [
+            value(self.duration_minutes.map(|v| v.to_string())),
+            value(self.expires_at.map(|v| v.to_rfc3339())),
+            value(self.data_limit_mb.map(|v| v.to_string())),
+            value(self.download_kbps.map(|v| v.to_string())),
+            value(self.upload_kbps.map(|v| v.to_string())),
+        ]
+    }
+
+    /// "Valid for 24 hours", or `None` without a duration
+    pub fn duration_label(&self) -> Option<String> {
+        self.duration_minutes
+            .map(|minutes| format!("Valid for {}", format_minutes(minutes)))
+    }
+
+    /// The limits as short lines for voucher cards and receipts
+    pub fn card_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.duration_label().into_iter().collect();
+        if let Some(expires_at) = self.expires_at {
+            lines.push(format!("Use by {}", expires_at.format("%d %b %Y")));
+        }
+        if let Some(data_limit_mb) = self.data_limit_mb {
+            lines.push(format!("Data: {}", format_megabytes(data_limit_mb)));
+        }
+        match (self.download_kbps, self.upload_kbps) {
+            (Some(down), Some(up)) => lines.push(format!(
+                "Speed: {} down / {} up",
+                format_kbps(down),
+                format_kbps(up)
+            )),
+            (Some(down), None) => lines.push(format!("Speed: {} down", format_kbps(down))),
+            (None, Some(up)) => lines.push(format!("Speed: {} up", format_kbps(up))),
+            (None, None) => {}
+        }
+        lines
+    }
+
+    /// All limits on one line, for tables
+    pub fn summary(&self) -> String {
+        self.card_lines().join(" · ")
+    }
+
+    /// When access that started at `redeemed_at` ends, or `None` without a duration.
+    /// The expiry date only limits when the code can first be used.
+    pub fn session_ends_at(&self, redeemed_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.duration_minutes
+            .map(|minutes| redeemed_at + chrono::Duration::minutes(minutes.into()))
+    }
+}
+
+/// An expiry given as RFC 3339, or as a date meaning the end of that day in UTC
+pub fn parse_expiry(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .map(|date_time| date_time.and_utc())
+}
+
+fn format_minutes(minutes: u32) -> String {
+    let plural =
+        |count: u32, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
+    if minutes > 1440 && minutes.is_multiple_of(1440) {
+        plural(minutes / 1440, "day")
+    } else if minutes.is_multiple_of(60) {
+        plural(minutes / 60, "hour")
+    } else {
+        plural(minutes, "minute")
+    }
+}
+
+fn format_megabytes(megabytes: u64) -> String {
+    if megabytes >= 1000 && megabytes.is_multiple_of(1000) {
+        format!("{} GB", megabytes / 1000)
+    } else {
+        format!("{} MB", megabytes)
+    }
+}
+
+fn format_kbps(kbps: u32) -> String {
+    if kbps >= 1000 && kbps.is_multiple_of(1000) {
+        format!("{} Mbit/s", kbps / 1000)
+    } else {
+        format!("{} kbit/s", kbps)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ValidityError {
+    #[error("invalid {0} '{1}'")]
+    Invalid(String, String),
+    #[error("{0} must be greater than 0")]
+    Zero(&'static str),
+    #[error("unknown validity column '{0}'")]
+    UnknownColumn(String),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,5 +360,80 @@ mod tests {
         assert!(!voucher.is_used);
         assert!(voucher.used_at.is_none());
         assert!(voucher.network_id.is_none());
+        assert!(voucher.validity.is_empty());
+        assert_eq!(voucher.state, VoucherState::Available);
+    }
+
+    #[test]
+    fn test_voucher_state_transitions() {
+        use VoucherState::*;
+        assert!(Available.can_become(Reserved));
+        assert!(Reserved.can_become(Printed));
+        assert!(Printed.can_become(Redeemed));
+        assert!(!Redeemed.can_become(Available));
+        assert!(!Revoked.can_become(Available));
+        assert!(!Expired.can_become(Issued));
+        assert!(Issued.can_become(Expired) && !Redeemed.can_become(Expired));
+        assert!(!Reserved.can_become(Issued));
+
+        assert_eq!(Available.next_states(), vec![Issued, Redeemed, Revoked]);
+        assert_eq!(Redeemed.next_states(), vec![Revoked]);
+        assert_eq!(Issued.next_states(), vec![Available, Redeemed, Revoked]);
+        assert!(Reserved.next_states().is_empty());
+        assert!(Printed.is_used() && !Expired.is_used());
+        assert_eq!("issued".parse::<VoucherState>().unwrap(), Issued);
+        assert!("used".parse::<VoucherState>().is_err());
+    }
+
+    #[test]
+    fn test_validity_lines() {
+        let mut validity = Validity::default();
+        validity.set("duration_minutes", "1440").unwrap();
+        validity.set("expires_at", "2026-12-31").unwrap();
+        validity.set("data_limit_mb", "2000").unwrap();
+        validity.set("download_kbps", "10000").unwrap();
+        validity.set("upload_kbps", "512").unwrap();
+        validity.set("upload_kbps", " ").unwrap();
+
+        assert_eq!(
+            validity.card_lines(),
+            vec![
+                "Valid for 24 hours",
+                "Use by 31 Dec 2026",
+                "Data: 2 GB",
+                "Speed: 10 Mbit/s down / 512 kbit/s up",
+            ]
+        );
+        assert_eq!(
+            validity.expires_at.unwrap().to_rfc3339(),
+            "2026-12-31T23:59:59+00:00"
+        );
+        let mut parsed = Validity::default();
+        for (column, value) in VALIDITY_COLUMNS.into_iter().zip(validity.columns()) {
+            parsed.set(column, &value).unwrap();
+        }
+        assert_eq!(parsed, validity);
+        let redeemed_at = parse_expiry("2027-01-05T08:00:00Z").unwrap();
+        assert_eq!(
+            validity.session_ends_at(redeemed_at).unwrap().to_rfc3339(),
+            "2027-01-06T08:00:00+00:00"
+        );
+        assert!(Validity::default().session_ends_at(redeemed_at).is_none());
+        assert_eq!(format_minutes(10080), "7 days");
+        assert_eq!(format_minutes(90), "90 minutes");
+        assert_eq!(format_minutes(60), "1 hour");
+    }
+
+    #[test]
+    fn test_validity_errors() {
+        let mut validity = Validity::default();
+        assert!(validity.set("duration_minutes", "soon").is_err());
+        assert!(validity.set("expires_at", "31/12/2026").is_err());
+        assert!(validity.set("colour", "red").is_err());
+        validity.set("data_limit_mb", "0").unwrap();
+        assert!(matches!(
+            validity.validate(),
+            Err(ValidityError::Zero("data_limit_mb"))
+        ));
     }
 }
@@ -0,0 +1,696 @@
+//! Outgoing webhooks, so external systems can follow voucher and network events.
+//!
+//! Each event is queued in the database as one delivery per subscribed webhook,
+//! then posted as signed JSON by a background worker. Failed deliveries are
+//! retried with growing delays before they are given up.
+
+use crate::database::Database;
+use crate::http_client::{self, HttpError};
+use crate::voucher::Voucher;
+use crate::wifi_network::WiFiNetwork;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Attempts before a delivery is given up
+pub const MAX_ATTEMPTS: u32 = 8;
+
+/// Delay before the first retry; it doubles after each failed attempt
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+
+/// How often the worker looks for retries that are due
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Deliveries sent per round of the worker
+const BATCH_SIZE: usize = 20;
+
+/// Something that happened which webhooks can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "network.created")]
+    NetworkCreated,
+    #[serde(rename = "network.deleted")]
+    NetworkDeleted,
+    #[serde(rename = "vouchers.imported")]
+    VouchersImported,
+    #[serde(rename = "print_job.created")]
+    PrintJobCreated,
+    #[serde(rename = "voucher.used")]
+    VoucherUsed,
+    #[serde(rename = "voucher.unused")]
+    VoucherUnused,
+    #[serde(rename = "stock.low")]
+    StockLow,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 7] = [
+        Self::NetworkCreated,
+        Self::NetworkDeleted,
+        Self::VouchersImported,
+        Self::PrintJobCreated,
+        Self::VoucherUsed,
+        Self::VoucherUnused,
+        Self::StockLow,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NetworkCreated => "network.created",
+            Self::NetworkDeleted => "network.deleted",
+            Self::VouchersImported => "vouchers.imported",
+            Self::PrintJobCreated => "print_job.created",
+            Self::VoucherUsed => "voucher.used",
+            Self::VoucherUnused => "voucher.unused",
+            Self::StockLow => "stock.low",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::NetworkCreated => "Network created",
+            Self::NetworkDeleted => "Network deleted",
+            Self::VouchersImported => "Vouchers imported",
+            Self::PrintJobCreated => "Print job created",
+            Self::VoucherUsed => "Voucher marked used",
+            Self::VoucherUnused => "Voucher marked unused",
+            Self::StockLow => "Stock low",
+        }
+    }
+
+    /// The event for a voucher that moved into or out of the unused pool, if it did
+    pub fn for_voucher_change(was_used: bool, voucher: &Voucher) -> Option<Self> {
+        match (was_used, voucher.state.is_used()) {
+            (false, true) => Some(Self::VoucherUsed),
+            (true, false) => Some(Self::VoucherUnused),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = WebhookError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| WebhookError::UnknownEvent(s.to_string()))
+    }
+}
+
+/// An external URL subscribed to events
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// Key for the payload signature, shared with the receiver
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Events to send; empty means all of them
+    pub events: Vec<WebhookEvent>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    /// A new active webhook; a blank `secret` is replaced with a random one
+    pub fn new(
+        url: String,
+        secret: String,
+        events: Vec<WebhookEvent>,
+    ) -> Result<Self, WebhookError> {
+        http_client::check_url(&url).map_err(|_| WebhookError::InvalidUrl(url.clone()))?;
+        let secret = if secret.trim().is_empty() {
+            generate_secret()
+        } else {
+            secret.trim().to_string()
+        };
+        Ok(Self {
+            id: Uuid::new_v4().to_string(),
+            url,
+            secret,
+            events,
+            is_active: true,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// 32 random bytes, hex encoded
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The `X-Webhook-Signature` value: HMAC-SHA256 of `{timestamp}.{body}` keyed
+/// with the webhook's secret
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
+
+/// Where a delivery stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Waiting for its first attempt or a retry
+    Pending,
+    Delivered,
+    /// Given up after the last attempt
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub const ALL: [DeliveryStatus; 3] = [Self::Pending, Self::Delivered, Self::Failed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Pending => "Pending",
+            Self::Delivered => "Delivered",
+            Self::Failed => "Failed",
+        }
+    }
+}
+
+impl fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = WebhookError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| WebhookError::UnknownStatus(s.to_string()))
+    }
+}
+
+/// One event queued for one webhook, with the outcome of its attempts
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub event: WebhookEvent,
+    /// The JSON body, kept so retries send exactly the same payload
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// When the next attempt is due while pending
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// HTTP status of the last response, if there was one
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+impl WebhookDelivery {
+    pub fn new(
+        webhook_id: String,
+        event: WebhookEvent,
+        payload: String,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            webhook_id,
+            event,
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: Some(now),
+            response_status: None,
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        }
+    }
+
+    /// Record an attempt, scheduling a retry after a failure until the attempts run out
+    pub fn record(&mut self, result: &Result<u16, HttpError>, now: DateTime<Utc>) {
+        self.attempts += 1;
+        match result {
+            Ok(status) => {
+                self.status = DeliveryStatus::Delivered;
+                self.response_status = Some(*status);
+                self.last_error = None;
+                self.next_attempt_at = None;
+                self.delivered_at = Some(now);
+            }
+            Err(error) => {
+                self.response_status = match error {
+                    HttpError::Status(status) => Some(*status),
+                    _ => None,
+                };
+                self.last_error = Some(error.to_string());
+                match retry_delay(self.attempts) {
+                    Some(delay) => {
+                        self.next_attempt_at =
+                            Some(now + chrono::Duration::from_std(delay).unwrap_or_default());
+                    }
+                    None => {
+                        self.status = DeliveryStatus::Failed;
+                        self.next_attempt_at = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue a failed delivery again, with a fresh set of attempts
+    pub fn retry(&mut self, now: DateTime<Utc>) {
+        self.status = DeliveryStatus::Pending;
+        self.attempts = 0;
+        self.next_attempt_at = Some(now);
+    }
+}
+
+/// How long to wait after `attempts` failed attempts; `None` once they are used up
+pub fn retry_delay(attempts: u32) -> Option<Duration> {
+    (attempts < MAX_ATTEMPTS).then(|| FIRST_RETRY * 2u32.pow(attempts.saturating_sub(1)))
+}
+
+/// The JSON body of every delivery
+#[derive(Serialize)]
+struct Payload<'a, T: Serialize> {
+    /// Shared by the deliveries of the same event to different webhooks
+    id: &'a str,
+    event: WebhookEvent,
+    created_at: DateTime<Utc>,
+    data: T,
+}
+
+/// Queues events and runs the worker that delivers them
+#[derive(Clone)]
+pub struct Webhooks {
+    database: Arc<Database>,
+    wake: Arc<Notify>,
+}
+
+impl Webhooks {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self {
+            database,
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Start the background worker that sends queued deliveries
+    pub fn start(&self) {
+        let webhooks = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(error) = webhooks.deliver_due().await {
+                    eprintln!("Webhook delivery failed: {:#}", error);
+                }
+                tokio::select! {
+                    _ = webhooks.wake.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+    }
+
+    /// Queue `event` for each active webhook subscribed to it. Failures are
+    /// logged and never fail the request the event came from.
+    pub async fn emit(&self, event: WebhookEvent, data: impl Serialize) {
+        if let Err(error) = self.queue(event, data).await {
+            eprintln!("Could not queue webhook event {}: {:#}", event, error);
+        }
+    }
+
+    async fn queue(&self, event: WebhookEvent, data: impl Serialize) -> anyhow::Result<()> {
+        let webhooks = self.database.get_webhooks().await?;
+        let now = Utc::now();
+        let id = Uuid::new_v4().to_string();
+        let payload = serde_json::to_string(&Payload {
+            id: &id,
+            event,
+            created_at: now,
+            data,
+        })?;
+        let deliveries: Vec<WebhookDelivery> = webhooks
+            .iter()
+            .filter(|webhook| webhook.is_active && webhook.wants(event))
+            .map(|webhook| WebhookDelivery::new(webhook.id.clone(), event, payload.clone(), now))
+            .collect();
+        if !deliveries.is_empty() {
+            self.database.create_webhook_deliveries(&deliveries).await?;
+            self.wake.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Send `voucher.used` or `voucher.unused` if a state change moved the
+    /// voucher into or out of the unused pool
+    pub async fn voucher_changed(&self, was_used: bool, voucher: &Voucher, changed_by: &str) {
+        if let Some(event) = WebhookEvent::for_voucher_change(was_used, voucher) {
+            self.emit(
+                event,
+                serde_json::json!({
+                    "voucher_id": voucher.id,
+                    "code": voucher.code,
+                    "network_id": voucher.network_id,
+                    "state": voucher.state,
+                    "changed_by": changed_by,
+                }),
+            )
+            .await;
+        }
+    }
+
+    /// Send `vouchers.imported` for codes added to a network (or to none) from `source`
+    pub async fn vouchers_imported(&self, network_id: Option<&str>, count: usize, source: &str) {
+        self.emit(
+            WebhookEvent::VouchersImported,
+            serde_json::json!({
+                "network_id": network_id,
+                "count": count,
+                "source": source,
+            }),
+        )
+        .await;
+    }
+
+    /// Wake the worker, e.g. after a delivery was queued again by hand
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Send the deliveries that are due, returning how many were attempted
+    pub async fn deliver_due(&self) -> anyhow::Result<usize> {
+        let deliveries = self
+            .database
+            .get_due_webhook_deliveries(Utc::now(), BATCH_SIZE)
+            .await?;
+        let mut webhooks: HashMap<String, Option<Webhook>> = HashMap::new();
+        for mut delivery in deliveries.iter().cloned() {
+            if !webhooks.contains_key(&delivery.webhook_id) {
+                let webhook = self.database.get_webhook(&delivery.webhook_id).await?;
+                webhooks.insert(delivery.webhook_id.clone(), webhook);
+            }
+            let Some(Some(webhook)) = webhooks.get(&delivery.webhook_id) else {
+                continue;
+            };
+            let (url, secret, request) = (
+                webhook.url.clone(),
+                webhook.secret.clone(),
+                delivery.clone(),
+            );
+            let result = tokio::task::spawn_blocking(move || send(&url, &secret, &request))
+                .await
+                .unwrap_or_else(|error| Err(HttpError::Transport(error.to_string())));
+            if let Err(error) = &result {
+                eprintln!(
+                    "Webhook {} to {} failed (attempt {}): {}",
+                    delivery.event,
+                    webhook.url,
+                    delivery.attempts + 1,
+                    error
+                );
+            }
+            delivery.record(&result, Utc::now());
+            self.database.update_webhook_delivery(&delivery).await?;
+        }
+        Ok(deliveries.len())
+    }
+}
+
+/// The data of `network.created` and `network.deleted`
+pub fn network_data(network: &WiFiNetwork, changed_by: &str) -> serde_json::Value {
+    serde_json::json!({
+        "network_id": network.id,
+        "name": network.name,
+        "ssid": network.ssid,
+        "changed_by": changed_by,
+    })
+}
+
+/// Post a delivery's payload with its event, id and signature headers
+fn send(url: &str, secret: &str, delivery: &WebhookDelivery) -> Result<u16, HttpError> {
+    let timestamp = Utc::now().timestamp();
+    let signature = signature(secret, timestamp, &delivery.payload);
+    let timestamp = timestamp.to_string();
+    http_client::post_json(
+        url,
+        &[
+            ("X-Webhook-Event", delivery.event.as_str()),
+            ("X-Webhook-Delivery", &delivery.id),
+            ("X-Webhook-Timestamp", &timestamp),
+            ("X-Webhook-Signature", &signature),
+        ],
+        delivery.payload.as_bytes(),
+    )
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("unknown webhook event '{0}'")]
+    UnknownEvent(String),
+    #[error("unknown delivery status '{0}'")]
+    UnknownStatus(String),
+    #[error("'{0}' is not an http or https URL")]
+    InvalidUrl(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use crate::voucher::VoucherState;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_events() {
+        for event in WebhookEvent::ALL {
+            assert_eq!(event.as_str().parse::<WebhookEvent>().unwrap(), event);
+            assert_eq!(
+                serde_json::to_value(event).unwrap(),
+                serde_json::json!(event.as_str())
+            );
+        }
+        assert!("voucher.lost".parse::<WebhookEvent>().is_err());
+
+        let mut voucher = Voucher::new("CODE".to_string());
+        voucher.state = VoucherState::Issued;
+        assert_eq!(
+            WebhookEvent::for_voucher_change(false, &voucher),
+            Some(WebhookEvent::VoucherUsed)
+        );
+        assert_eq!(WebhookEvent::for_voucher_change(true, &voucher), None);
+        voucher.state = VoucherState::Available;
+        assert_eq!(
+            WebhookEvent::for_voucher_change(true, &voucher),
+            Some(WebhookEvent::VoucherUnused)
+        );
+    }
+
+    #[test]
+    fn test_new_webhook() {
+        let webhook = Webhook::new(
+            "https://hooks.example.com/vouchers".to_string(),
+            " ".to_string(),
+            vec![WebhookEvent::StockLow],
+        )
+        .unwrap();
+        assert_eq!(webhook.secret.len(), 64);
+        assert!(webhook.wants(WebhookEvent::StockLow));
+        assert!(!webhook.wants(WebhookEvent::NetworkCreated));
+
+        let all = Webhook::new(
+            "http://127.0.0.1:9000/".to_string(),
+            "secret".to_string(),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(all.secret, "secret");
+        assert!(all.wants(WebhookEvent::NetworkCreated));
+
+        assert!(matches!(
+            Webhook::new("ftp://example.com".to_string(), String::new(), Vec::new()),
+            Err(WebhookError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn test_signature() {
+        // Checked against Python's hmac module
+        assert_eq!(
+            signature("whsec-test", 1700000000, r#"{"event":"network.created"}"#),
+            "sha256=967df76c60c239bf9ccb968fd0ab39cabb33c2a47341598b591fc841e0fe2658"
+        );
+    }
+
+    #[test]
+    fn test_retries_back_off_then_give_up() {
+        assert_eq!(retry_delay(1), Some(Duration::from_secs(30)));
+        assert_eq!(retry_delay(2), Some(Duration::from_secs(60)));
+        assert_eq!(retry_delay(7), Some(Duration::from_secs(30 * 64)));
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+
+        let now = Utc::now();
+        let mut delivery = WebhookDelivery::new(
+            "hook".to_string(),
+            WebhookEvent::StockLow,
+            "{}".to_string(),
+            now,
+        );
+        delivery.record(&Err(HttpError::Status(503)), now);
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.response_status, Some(503));
+        assert_eq!(
+            delivery.next_attempt_at,
+            Some(now + chrono::Duration::seconds(30))
+        );
+        for _ in 1..MAX_ATTEMPTS {
+            delivery.record(&Err(HttpError::Status(503)), now);
+        }
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert!(delivery.next_attempt_at.is_none());
+
+        delivery.retry(now);
+        delivery.record(&Ok(204), now);
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 1);
+        assert!(delivery.last_error.is_none());
+    }
+
+    /// Answer one request with `status_line`, returning its headers and body
+    fn serve_once(
+        listener: TcpListener,
+        status_line: &'static str,
+    ) -> std::thread::JoinHandle<(HashMap<String, String>, String)> {
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    headers.insert(name.to_string(), value.trim().to_string());
+                }
+            }
+            let mut body = vec![0; headers["Content-Length"].parse().unwrap()];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .into_inner()
+                .write_all(format!("{}\r\nContent-Length: 0\r\n\r\n", status_line).as_bytes())
+                .unwrap();
+            (headers, String::from_utf8(body).unwrap())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_emit_and_deliver() {
+        let database = Arc::new(test_util::database().await);
+        let webhooks = Webhooks::new(database.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let subscribed = Webhook::new(
+            url.clone(),
+            "secret".to_string(),
+            vec![WebhookEvent::NetworkCreated],
+        )
+        .unwrap();
+        let other = Webhook::new(url, "other".to_string(), vec![WebhookEvent::StockLow]).unwrap();
+        database.create_webhook(&subscribed).await.unwrap();
+        database.create_webhook(&other).await.unwrap();
+
+        webhooks
+            .emit(
+                WebhookEvent::NetworkCreated,
+                serde_json::json!({"network_id": "net-1"}),
+            )
+            .await;
+
+        // The first attempt fails and is scheduled for a retry
+        let server = serve_once(listener.try_clone().unwrap(), "HTTP/1.1 500 Oops");
+        assert_eq!(webhooks.deliver_due().await.unwrap(), 1);
+        server.join().unwrap();
+        let deliveries = database
+            .get_webhook_deliveries(&subscribed.id, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, DeliveryStatus::Pending);
+        assert_eq!(deliveries[0].response_status, Some(500));
+        assert!(database
+            .get_webhook_deliveries(&other.id, 10)
+            .await
+            .unwrap()
+            .is_empty());
+        // Not due again until the backoff has passed
+        assert_eq!(webhooks.deliver_due().await.unwrap(), 0);
+
+        let mut delivery = deliveries[0].clone();
+        delivery.retry(Utc::now());
+        database.update_webhook_delivery(&delivery).await.unwrap();
+        let server = serve_once(listener, "HTTP/1.1 200 OK");
+        assert_eq!(webhooks.deliver_due().await.unwrap(), 1);
+        let (headers, body) = server.join().unwrap();
+
+        assert_eq!(headers["X-Webhook-Event"], "network.created");
+        assert_eq!(headers["X-Webhook-Delivery"], delivery.id);
+        let timestamp: i64 = headers["X-Webhook-Timestamp"].parse().unwrap();
+        assert_eq!(
+            headers["X-Webhook-Signature"],
+            signature("secret", timestamp, &body)
+        );
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["event"], "network.created");
+        assert_eq!(payload["data"]["network_id"], "net-1");
+
+        let delivery = database
+            .get_webhook_delivery(&delivery.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 1);
+    }
+}
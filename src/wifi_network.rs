@@ -1,6 +1,93 @@
+use crate::branding::Branding;
+use crate::portal::PortalLogin;
+use crate::qr_generator::QrOptions;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
+/// How clients authenticate to the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SecurityType {
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "wep")]
+    Wep,
+    #[serde(rename = "wpa")]
+    Wpa,
+    #[default]
+    #[serde(rename = "wpa2")]
+    Wpa2,
+    #[serde(rename = "wpa3-sae")]
+    Wpa3Sae,
+}
+
+impl SecurityType {
+    pub const ALL: [SecurityType; 5] =
+        [Self::Open, Self::Wep, Self::Wpa, Self::Wpa2, Self::Wpa3Sae];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Wep => "wep",
+            Self::Wpa => "wpa",
+            Self::Wpa2 => "wpa2",
+            Self::Wpa3Sae => "wpa3-sae",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Open => "Open (no password)",
+            Self::Wep => "WEP",
+            Self::Wpa => "WPA Personal",
+            Self::Wpa2 => "WPA2 Personal",
+            Self::Wpa3Sae => "WPA3 Personal (SAE)",
+        }
+    }
+
+    /// Value of the `T:` field in a WiFi QR code
+    fn qr_type(&self) -> &'static str {
+        match self {
+            Self::Open => "nopass",
+            Self::Wep => "WEP",
+            Self::Wpa | Self::Wpa2 => "WPA",
+            Self::Wpa3Sae => "SAE",
+        }
+    }
+
+    pub fn has_password(&self) -> bool {
+        *self != Self::Open
+    }
+}
+
+impl fmt::Display for SecurityType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SecurityType {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|security| security.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| NetworkError::UnknownSecurity(s.to_string()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkError {
+    #[error("unknown security type '{0}', expected open, wep, wpa, wpa2 or wpa3-sae")]
+    UnknownSecurity(String),
+    #[error("{0} networks need a password")]
+    MissingPassword(&'static str),
+    #[error("transition disable only applies to WPA3 networks")]
+    TransitionDisable,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WiFiNetwork {
     pub id: String,
@@ -10,6 +97,32 @@ pub struct WiFiNetwork {
     pub description: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub is_active: bool,
+    /// Id of the page layout preset used when printing
+    #[serde(default = "default_layout")]
+    pub layout: String,
+    #[serde(default)]
+    pub branding: Branding,
+    /// QR settings used instead of the global `[qr]` config
+    #[serde(default)]
+    pub qr_options: Option<QrOptions>,
+    #[serde(default)]
+    pub security: SecurityType,
+    /// The SSID is not broadcast
+    #[serde(default)]
+    pub hidden: bool,
+    /// Tell WPA3 clients not to fall back to WPA2 on this network
+    #[serde(default)]
+    pub transition_disable: bool,
+    /// Captive portal login codes printed on the cards
+    #[serde(default)]
+    pub portal: PortalLogin,
+    /// Alert when this many unused vouchers or fewer are left
+    #[serde(default)]
+    pub low_stock_threshold: Option<u32>,
+}
+
+fn default_layout() -> String {
+    crate::layout::DEFAULT_LAYOUT.to_string()
 }
 
 impl WiFiNetwork {
@@ -22,8 +135,75 @@ impl WiFiNetwork {
             description,
             created_at: chrono::Utc::now(),
             is_active: true,
+            layout: default_layout(),
+            branding: Branding::default(),
+            qr_options: None,
+            security: SecurityType::default(),
+            hidden: false,
+            transition_disable: false,
+            portal: PortalLogin::default(),
+            low_stock_threshold: None,
         }
     }
+
+    /// Whether `available` unused vouchers is at or below the alert threshold
+    pub fn is_low_on_stock(&self, available: usize) -> bool {
+        self.low_stock_threshold
+            .is_some_and(|threshold| available <= threshold as usize)
+    }
+
+    /// Check that the password and transition disable flag fit the security type
+    pub fn validate_security(&self) -> Result<(), NetworkError> {
+        if self.security.has_password() && self.password.is_empty() {
+            return Err(NetworkError::MissingPassword(self.security.label()));
+        }
+        if self.transition_disable && self.security != SecurityType::Wpa3Sae {
+            return Err(NetworkError::TransitionDisable);
+        }
+        Ok(())
+    }
+
+    /// The password as printed on voucher cards
+    pub fn password_line(&self) -> String {
+        if self.security.has_password() {
+            format!("Password: {}", self.password)
+        } else {
+            "No password needed".to_string()
+        }
+    }
+
+    /// Payload of the WiFi QR code that joins this network, in the
+    /// `WIFI:T:…;S:…;P:…;;` format understood by phone cameras
+    pub fn qr_payload(&self) -> String {
+        let mut payload = format!(
+            "WIFI:T:{};S:{};",
+            self.security.qr_type(),
+            escape_qr_value(&self.ssid)
+        );
+        if self.security.has_password() {
+            payload.push_str(&format!("P:{};", escape_qr_value(&self.password)));
+        }
+        if self.hidden {
+            payload.push_str("H:true;");
+        }
+        if self.transition_disable && self.security == SecurityType::Wpa3Sae {
+            payload.push_str("R:1;");
+        }
+        payload.push(';');
+        payload
+    }
+}
+
+/// Backslash-escape the characters that delimit fields in a WiFi QR code
+fn escape_qr_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 #[cfg(test)]
@@ -45,5 +225,65 @@ mod tests {
         assert_eq!(network.description, Some("Test description".to_string()));
         assert!(network.is_active);
         assert!(!network.id.is_empty());
+        assert_eq!(network.security, SecurityType::Wpa2);
+    }
+
+    fn network(ssid: &str, password: &str) -> WiFiNetwork {
+        WiFiNetwork::new(
+            "Test".to_string(),
+            ssid.to_string(),
+            password.to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_qr_payload() {
+        assert_eq!(
+            network("Lobby", "secret").qr_payload(),
+            "WIFI:T:WPA;S:Lobby;P:secret;;"
+        );
+
+        let escaped = network(r#"Cafe;Bar, "Main""#, r"a:b\c").qr_payload();
+        assert_eq!(escaped, r#"WIFI:T:WPA;S:Cafe\;Bar\, \"Main\";P:a\:b\\c;;"#);
+
+        let mut open = network("Guest", "ignored");
+        open.security = SecurityType::Open;
+        open.hidden = true;
+        assert_eq!(open.qr_payload(), "WIFI:T:nopass;S:Guest;H:true;;");
+        assert_eq!(open.password_line(), "No password needed");
+
+        let mut wpa3 = network("Office", "pw");
+        wpa3.security = SecurityType::Wpa3Sae;
+        wpa3.transition_disable = true;
+        assert_eq!(wpa3.qr_payload(), "WIFI:T:SAE;S:Office;P:pw;R:1;;");
+
+        let mut wep = network("Old", "12345");
+        wep.security = SecurityType::Wep;
+        assert_eq!(wep.qr_payload(), "WIFI:T:WEP;S:Old;P:12345;;");
+    }
+
+    #[test]
+    fn test_validate_security() {
+        assert!(network("Lobby", "secret").validate_security().is_ok());
+        assert!(matches!(
+            network("Lobby", "").validate_security(),
+            Err(NetworkError::MissingPassword(_))
+        ));
+
+        let mut open = network("Guest", "");
+        open.security = SecurityType::Open;
+        assert!(open.validate_security().is_ok());
+        open.transition_disable = true;
+        assert!(matches!(
+            open.validate_security(),
+            Err(NetworkError::TransitionDisable)
+        ));
+
+        assert_eq!(
+            "WPA3-SAE".parse::<SecurityType>().unwrap(),
+            SecurityType::Wpa3Sae
+        );
+        assert!("wpa4".parse::<SecurityType>().is_err());
     }
 }